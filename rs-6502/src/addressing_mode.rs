@@ -1,6 +1,5 @@
-use core::panic;
-
 /// Enumerates all possible MOS 6502 memory addressing modes
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum AddrMode {
     /// Accumulator (implied)
     Accumulator,
@@ -28,144 +27,115 @@ pub enum AddrMode {
     ZeroPageX,
     /// Zero page addressing mode with Y register offset
     ZeroPageY,
+    /// Zero page indirect addressing mode, 65C02 only
+    ZeroPageInd,
+    /// Absolute indirect addressing mode with X register offset, used by the 65C02 JMP
+    AbsIndX,
+    /// Zero page operand followed by a relative branch offset, used by BBR and BBS
+    ZeroPageRel,
 }
 
-pub fn get_addr_mode(opcode: u8) -> AddrMode {
-    let lo_nibble = opcode & 0x0F;
-    let hi_nibble = (opcode & 0xF0) >> 4;
+impl AddrMode {
+    /// Returns the length in bytes of an instruction using the addressing mode, opcode
+    /// included
+    pub const fn instruction_bytes(self) -> u8 {
+        match self {
+            AddrMode::Impl | AddrMode::Accumulator => 1,
+            AddrMode::Immediate
+            | AddrMode::ZeroPage
+            | AddrMode::ZeroPageX
+            | AddrMode::ZeroPageY
+            | AddrMode::ZeroPageInd
+            | AddrMode::IndX
+            | AddrMode::IndY
+            | AddrMode::Rel => 2,
+            AddrMode::Abs
+            | AddrMode::AbsX
+            | AddrMode::AbsY
+            | AddrMode::Ind
+            | AddrMode::AbsIndX
+            | AddrMode::ZeroPageRel => 3,
+        }
+    }
+}
 
-    match lo_nibble {
-        0x00 => {
-            if hi_nibble == 0x80 {
-                panic!(
-                    "Illegal opcode, no addressing mode available: {:#04X}",
-                    opcode
-                )
-            }
+/// Adds an index register to a base address, as the absolute indexed and indirect
+/// indexed modes do
+///
+/// ### Parameters:
+/// * `base` - The address before indexing
+/// * `index` - The value of the X or Y register
+///
+/// ### Returns:
+/// * A tuple containing the effective address and whether adding the index crossed
+///   into another page, which costs the CPU one more cycle to fix the high byte
+pub fn get_indexed_addr(base: u16, index: u8) -> (u16, bool) {
+    let addr = base.wrapping_add(index as u16);
 
-            if hi_nibble == 0x00 || hi_nibble == 0x04 || hi_nibble == 0x06 {
-                AddrMode::Impl
-            } else if hi_nibble % 2 == 1 {
-                AddrMode::Rel
-            } else if hi_nibble == 0x0A || hi_nibble == 0x0C || hi_nibble == 0x0E {
-                AddrMode::Immediate
-            } else if hi_nibble == 0x02 {
-                AddrMode::Abs
-            } else {
-                panic!(
-                    "Illegal opcode, no addressing mode available: {:#04X}",
-                    opcode
-                )
-            }
-        }
-        0x01 => {
-            if hi_nibble % 2 == 0 {
-                AddrMode::IndX
-            } else {
-                AddrMode::IndY
-            }
-        }
-        0x02 => {
-            if hi_nibble == 0x0A {
-                AddrMode::Immediate
-            } else {
-                panic!(
-                    "Illegal opcode, no addressing mode available: {:#04X}",
-                    opcode
-                )
-            }
-        }
-        0x04 => {
-            if hi_nibble == 0x02 || (hi_nibble >= 8 && hi_nibble % 2 == 0) {
-                AddrMode::ZeroPage
-            } else if hi_nibble == 0x09 || hi_nibble == 0x0B {
-                AddrMode::ZeroPageX
-            } else {
-                panic!(
-                    "Illegal opcode, no addressing mode available: {:#04X}",
-                    opcode
-                )
-            }
-        }
-        0x05 => {
-            if hi_nibble % 2 == 0 {
-                AddrMode::ZeroPage
-            } else {
-                AddrMode::ZeroPageX
-            }
-        }
-        0x06 => {
-            if hi_nibble % 2 == 0 {
-                AddrMode::ZeroPage
-            } else if hi_nibble == 0x09 || hi_nibble == 0x0B {
-                AddrMode::ZeroPageY
-            } else {
-                AddrMode::ZeroPageX
-            }
-        }
-        0x08 => AddrMode::Impl,
-        0x09 => {
-            if hi_nibble == 0x08 {
-                panic!(
-                    "Illegal opcode, no addressing mode available: {:#04X}",
-                    opcode
-                )
-            } else if hi_nibble % 2 == 0 {
-                AddrMode::Immediate
-            } else {
-                AddrMode::AbsY
-            }
-        }
-        0x0A => {
-            if hi_nibble <= 0x06 && hi_nibble % 2 == 0 {
-                AddrMode::Accumulator
-            } else if (hi_nibble >= 0x08 && hi_nibble <= 0x0C) || hi_nibble == 0x0E {
-                AddrMode::Impl
-            } else {
-                panic!(
-                    "Illegal opcode, no addressing mode available: {:#04X}",
-                    opcode
-                )
-            }
-        }
-        0x0C => {
-            if hi_nibble != 0x00 && hi_nibble != 0x06 && hi_nibble % 2 == 0 {
-                AddrMode::Abs
-            } else if hi_nibble == 0x06 {
-                AddrMode::Ind
-            } else if hi_nibble == 0x0B {
-                AddrMode::AbsX
-            } else {
-                panic!(
-                    "Illegal opcode, no addressing mode available: {:#04X}",
-                    opcode
-                )
-            }
-        }
-        0x0D => {
-            if hi_nibble % 2 == 0 {
-                AddrMode::Abs
-            } else {
-                AddrMode::AbsX
-            }
-        }
-        0x0E => {
-            if hi_nibble % 2 == 0 {
-                AddrMode::Abs
-            } else if hi_nibble == 0x0B {
-                AddrMode::AbsY
-            } else if hi_nibble == 0x09 {
-                panic!(
-                    "Illegal opcode, no addressing mode available: {:#04X}",
-                    opcode
-                )
-            } else {
-                AddrMode::AbsX
-            }
-        }
-        _ => panic!(
-            "Illegal opcode, no addressing mode available: {:#04X}",
-            opcode
-        ),
+    (addr, (base ^ addr) & 0xFF00 != 0)
+}
+
+/// Returns where a taken branch lands
+///
+/// ### Parameters:
+/// * `pc` - The address of the instruction following the branch
+/// * `offset` - The signed branch offset
+///
+/// ### Returns:
+/// * A tuple containing the branch target and whether it lies on another page than
+///   `pc`, which costs the CPU one more cycle to fix the high byte
+pub fn get_branch_target(pc: u16, offset: u8) -> (u16, bool) {
+    let target = pc.wrapping_add(offset as i8 as u16);
+
+    (target, (pc ^ target) & 0xFF00 != 0)
+}
+
+/// Returns the address holding the high byte of a pointer whose low byte is at `addr`
+///
+/// ### Parameters:
+/// * `addr` - The address of the pointer's low byte
+/// * `page_wrap` - Whether the increment stays within the page. Zero page pointers
+///   always wrap from $FF to $00, as does the NMOS `JMP ($xxFF)` which reads its high
+///   byte from $xx00.
+///
+/// ### Returns:
+/// * The address of the pointer's high byte
+pub fn get_pointer_high_addr(addr: u16, page_wrap: bool) -> u16 {
+    if page_wrap {
+        (addr & 0xFF00) | (addr.wrapping_add(1) & 0x00FF)
+    } else {
+        addr.wrapping_add(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indexed_addr() {
+        assert_eq!(get_indexed_addr(0x1200, 0x34), (0x1234, false));
+        assert_eq!(get_indexed_addr(0x12FF, 0x00), (0x12FF, false));
+        assert_eq!(get_indexed_addr(0x12FF, 0x01), (0x1300, true));
+        assert_eq!(get_indexed_addr(0x1280, 0xFF), (0x137F, true));
+    }
+
+    #[test]
+    fn branch_target() {
+        assert_eq!(get_branch_target(0x0202, 0x02), (0x0204, false));
+        assert_eq!(get_branch_target(0x0202, 0xFE), (0x0200, false));
+        assert_eq!(get_branch_target(0x02FE, 0x02), (0x0300, true));
+        assert_eq!(get_branch_target(0x0202, 0x80), (0x0182, true));
+        assert_eq!(get_branch_target(0x0202, 0x7F), (0x0281, false));
+    }
+
+    #[test]
+    fn pointer_high_addr() {
+        assert_eq!(get_pointer_high_addr(0x1234, false), 0x1235);
+        assert_eq!(get_pointer_high_addr(0x10FF, false), 0x1100);
+        assert_eq!(get_pointer_high_addr(0x10FF, true), 0x1000);
+        assert_eq!(get_pointer_high_addr(0x00FF, true), 0x0000);
+        assert_eq!(get_pointer_high_addr(0xFFFF, false), 0x0000);
+        assert_eq!(get_pointer_high_addr(0xFFFF, true), 0xFF00);
     }
 }
@@ -0,0 +1,1905 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::{error::Error, fmt, fs, mem};
+
+use crate::addressing_mode::AddrMode;
+use crate::loader::{Image, Segment};
+use crate::opcode::opcode_info;
+use crate::variant::CpuVariant;
+
+/// Name given to sources assembled from memory rather than from a file
+const SOURCE_NAME: &str = "<source>";
+
+/// Macro expansions and includes deeper than this are assumed to recurse forever
+const MAX_DEPTH: usize = 64;
+
+/// Scope of the local labels defined before the first global label. Global labels
+/// can't start with '.', so no scope clashes with one of them.
+const TOP_SCOPE: &str = ".";
+
+/// A macro invocation or an include the faulty line was reached through
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Origin {
+    /// The line belongs to a macro invoked at `file:line`
+    Macro {
+        name: String,
+        file: PathBuf,
+        line: usize,
+    },
+    /// The line belongs to a file included at `file:line`
+    Include { file: PathBuf, line: usize },
+}
+
+/// An error in the source, with where it was found
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AsmError {
+    /// File holding the faulty line
+    pub file: PathBuf,
+    /// Line of the file, counted from 1, 0 if the file itself could not be read
+    pub line: usize,
+    /// What is wrong with the line
+    pub message: String,
+    /// Macro invocations and includes the line was reached through, innermost first
+    pub chain: Vec<Origin>,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            0 => write!(f, "{}: {}", self.file.display(), self.message)?,
+            line => write!(f, "{}:{}: {}", self.file.display(), line, self.message)?,
+        }
+
+        for origin in &self.chain {
+            match origin {
+                Origin::Macro { name, file, line } => write!(
+                    f,
+                    "\n    in macro {} invoked at {}:{}",
+                    name,
+                    file.display(),
+                    line
+                )?,
+                Origin::Include { file, line } => {
+                    write!(f, "\n    in file included at {}:{}", file.display(), line)?
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for AsmError {}
+
+/// A line of source, with the file it was read from
+#[derive(Clone)]
+struct SourceLine {
+    file: Rc<Path>,
+    line: usize,
+    text: String,
+}
+
+/// Splits a source file into lines
+fn source_lines(file: Rc<Path>, source: &str) -> Vec<SourceLine> {
+    source
+        .lines()
+        .enumerate()
+        .map(|(index, text)| SourceLine {
+            file: file.clone(),
+            line: index + 1,
+            text: text.to_string(),
+        })
+        .collect()
+}
+
+/// Where a statement comes from, to point errors at it
+#[derive(Clone)]
+struct Location {
+    file: Rc<Path>,
+    line: usize,
+    chain: Rc<[Origin]>,
+}
+
+impl Location {
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError {
+            file: self.file.to_path_buf(),
+            line: self.line,
+            message: message.into(),
+            chain: self.chain.to_vec(),
+        }
+    }
+
+    /// Resolves a path relative to the directory of the file
+    fn resolve(&self, path: &str) -> PathBuf {
+        match self.file.parent() {
+            Some(dir) if &*self.file != Path::new(SOURCE_NAME) => dir.join(path),
+            _ => PathBuf::from(path),
+        }
+    }
+}
+
+/// A lexical element of a source line
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    /// Symbol, mnemonic, directive (`.org`), local label (`@loop`) or local label of
+    /// another scope (`start@loop`)
+    Ident(String),
+    Number(i64),
+    Str(String),
+    Punct(&'static str),
+}
+
+/// Punctuation and operators, the two character ones first so they win
+const PUNCTUATION: [&str; 25] = [
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "#", ",", "(", ")", "+", "-", "*", "/", "&",
+    "|", "^", "~", "!", ":", "=", "<", ">",
+];
+
+/// Splits a line into tokens, up to the comment
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        if c == ';' {
+            break;
+        }
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+
+        let (token, len) = match c {
+            '$' => number(&rest[1..], 16).map(|(value, len)| (Token::Number(value), len + 1))?,
+            '%' => number(&rest[1..], 2).map(|(value, len)| (Token::Number(value), len + 1))?,
+            '0' if rest[1..].starts_with(['x', 'X']) => {
+                number(&rest[2..], 16).map(|(value, len)| (Token::Number(value), len + 2))?
+            }
+            '0'..='9' => number(rest, 10).map(|(value, len)| (Token::Number(value), len))?,
+            '\'' => {
+                let mut chars = rest[1..].chars();
+                match (chars.next(), chars.next()) {
+                    (Some(char), Some('\'')) => (Token::Number(char as i64), char.len_utf8() + 2),
+                    _ => return Err(String::from("Unterminated character literal")),
+                }
+            }
+            '"' => match rest[1..].find('"') {
+                Some(end) => (Token::Str(rest[1..end + 1].to_string()), end + 2),
+                None => return Err(String::from("Unterminated string")),
+            },
+            c if c.is_ascii_alphabetic() || matches!(c, '_' | '.' | '@') => {
+                let len = rest[1..]
+                    .find(|c: char| !is_ident_char(c))
+                    .map_or(rest.len(), |len| len + 1);
+                (Token::Ident(rest[..len].to_string()), len)
+            }
+            _ => match PUNCTUATION.iter().find(|punct| rest.starts_with(**punct)) {
+                Some(punct) => (Token::Punct(punct), punct.len()),
+                None => return Err(format!("Unexpected character '{}'", c)),
+            },
+        };
+
+        tokens.push(token);
+        rest = &rest[len..];
+    }
+
+    Ok(tokens)
+}
+
+/// Returns whether a character can follow the first one of an identifier
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '@')
+}
+
+/// Reads the digits of a number in the given radix
+///
+/// ### Returns:
+/// * The value and the number of characters it was written with
+fn number(text: &str, radix: u32) -> Result<(i64, usize), String> {
+    let len = text
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(text.len());
+
+    match i64::from_str_radix(&text[..len], radix) {
+        Ok(value) if len > 0 => Ok((value, len)),
+        _ => Err(format!("Invalid number: {}", &text[..len])),
+    }
+}
+
+/// An operand expression, evaluated once the symbols it uses are known
+#[derive(Clone, PartialEq, Debug)]
+enum Expr {
+    Number(i64),
+    Symbol(String),
+    /// `*`, the address of the statement
+    Pc,
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Returns the names of the symbols used, as written
+    fn symbols(&self) -> Vec<&str> {
+        match self {
+            Expr::Number(_) | Expr::Pc => Vec::new(),
+            Expr::Symbol(name) => vec![name],
+            Expr::Unary(_, operand) => operand.symbols(),
+            Expr::Binary(_, left, right) => [left.symbols(), right.symbols()].concat(),
+        }
+    }
+}
+
+/// Binary operators from the lowest to the highest precedence
+const PRECEDENCE: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", ">", "<=", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/"],
+];
+
+/// Parses an expression out of a run of tokens, all of them must be used
+fn parse_expr(tokens: &[Token]) -> Result<Expr, String> {
+    if tokens.is_empty() {
+        return Err(String::from("Missing expression"));
+    }
+
+    let mut pos = 0;
+    let expr = parse_binary(tokens, &mut pos, 0)?;
+
+    match tokens.get(pos) {
+        None => Ok(expr),
+        Some(_) => Err(String::from("Unexpected tokens after the expression")),
+    }
+}
+
+fn parse_binary(tokens: &[Token], pos: &mut usize, level: usize) -> Result<Expr, String> {
+    if level == PRECEDENCE.len() {
+        return parse_unary(tokens, pos);
+    }
+
+    let mut left = parse_binary(tokens, pos, level + 1)?;
+    while let Some(Token::Punct(op)) = tokens.get(*pos) {
+        if !PRECEDENCE[level].contains(op) {
+            break;
+        }
+        *pos += 1;
+        let right = parse_binary(tokens, pos, level + 1)?;
+        left = Expr::Binary(op, Box::new(left), Box::new(right));
+    }
+
+    Ok(left)
+}
+
+fn parse_unary(tokens: &[Token], pos: &mut usize) -> Result<Expr, String> {
+    let token = tokens
+        .get(*pos)
+        .ok_or_else(|| String::from("Missing operand"))?;
+    *pos += 1;
+
+    match token {
+        Token::Number(value) => Ok(Expr::Number(*value)),
+        // Directives start with '.', so do the qualified names of local labels defined
+        // outside of any global label
+        Token::Ident(name) if !name.starts_with('.') || name.contains('@') => {
+            Ok(Expr::Symbol(name.clone()))
+        }
+        Token::Punct("*") => Ok(Expr::Pc),
+        Token::Punct(op @ ("-" | "~" | "!" | "<" | ">")) => {
+            Ok(Expr::Unary(op, Box::new(parse_unary(tokens, pos)?)))
+        }
+        Token::Punct("(") => {
+            let expr = parse_binary(tokens, pos, 0)?;
+            match tokens.get(*pos) {
+                Some(Token::Punct(")")) => {
+                    *pos += 1;
+                    Ok(expr)
+                }
+                _ => Err(String::from("Missing ')'")),
+            }
+        }
+        _ => Err(format!("Unexpected {} in expression", describe(token))),
+    }
+}
+
+/// Names a token in error messages
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Ident(name) => format!("'{}'", name),
+        Token::Number(value) => format!("'{}'", value),
+        Token::Str(text) => format!("\"{}\"", text),
+        Token::Punct(punct) => format!("'{}'", punct),
+    }
+}
+
+/// Splits tokens on the commas outside of parentheses
+fn split_commas(tokens: &[Token]) -> Vec<&[Token]> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (index, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Punct("(") => depth += 1,
+            Token::Punct(")") => depth -= 1,
+            Token::Punct(",") if depth == 0 => {
+                parts.push(&tokens[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&tokens[start..]);
+
+    parts
+}
+
+/// Returns the index of the parenthesis closing the one at the start of the tokens
+fn closing_paren(tokens: &[Token]) -> Option<usize> {
+    let mut depth = 0;
+
+    for (index, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Punct("(") => depth += 1,
+            Token::Punct(")") => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Returns whether the tokens are the name of a register, e.g. the `X` of `$10,X`
+fn is_register(tokens: &[Token], register: &str) -> bool {
+    matches!(tokens, [Token::Ident(name)] if name.eq_ignore_ascii_case(register))
+}
+
+/// Returns the directive starting a line, lowercased, e.g. `.if`
+fn directive(tokens: &[Token]) -> Option<String> {
+    match tokens.first() {
+        Some(Token::Ident(name)) if name.starts_with('.') => Some(name.to_ascii_lowercase()),
+        _ => None,
+    }
+}
+
+/// Returns the index of the line closing a block, skipping the blocks nested in it
+///
+/// ### Parameters:
+/// * `lines` - The lines following the line opening the block
+/// * `open` - The directive opening a block, e.g. `.repeat`
+/// * `close` - The directive closing it, e.g. `.endrepeat`
+fn block_end(lines: &[SourceLine], open: &str, close: &str) -> Option<usize> {
+    let mut depth = 0;
+
+    for (index, line) in lines.iter().enumerate() {
+        let Ok(tokens) = tokenize(&line.text) else {
+            continue;
+        };
+
+        match directive(&tokens) {
+            Some(name) if name == open => depth += 1,
+            Some(name) if name == close && depth == 0 => return Some(index),
+            Some(name) if name == close => depth -= 1,
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Writes tokens back as source text, numbers in decimal
+fn untokenize(tokens: &[Token]) -> String {
+    tokens
+        .iter()
+        .map(|token| match token {
+            Token::Ident(name) => name.clone(),
+            Token::Number(value) => value.to_string(),
+            Token::Str(text) => format!("\"{}\"", text),
+            Token::Punct(punct) => punct.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Replaces the identifiers naming macro parameters with the text of the arguments,
+/// strings, comments and the digits of numbers are left alone
+fn substitute(text: &str, names: &[String], values: &[String]) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        let len = match c {
+            ';' => rest.len(),
+            '"' | '\'' => rest[1..].find(c).map_or(rest.len(), |end| end + 2),
+            c if c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '@' | '$' | '%') => {
+                let len = rest[1..]
+                    .find(|c: char| !is_ident_char(c))
+                    .map_or(rest.len(), |len| len + 1);
+
+                if let Some(index) = names.iter().position(|name| *name == rest[..len]) {
+                    result += &values[index];
+                    rest = &rest[len..];
+                    continue;
+                }
+                len
+            }
+            c => c.len_utf8(),
+        };
+
+        result += &rest[..len];
+        rest = &rest[len..];
+    }
+
+    result
+}
+
+/// An item of a `.byte` list
+enum ByteItem {
+    Expr(Expr),
+    Str(String),
+}
+
+/// What a statement emits
+enum Emit {
+    Instruction {
+        opcode: u8,
+        mode: AddrMode,
+        operands: Vec<Expr>,
+    },
+    Bytes(Vec<ByteItem>),
+    Words(Vec<Expr>),
+    /// Contents of a file included with `.incbin`
+    Data(Vec<u8>),
+}
+
+/// A statement placed by the first pass, encoded by the second
+struct Statement {
+    location: Location,
+    addr: u16,
+    /// Global label local labels are looked up under
+    scope: String,
+    emit: Emit,
+}
+
+/// A macro defined with `.macro`
+struct Macro {
+    params: Vec<String>,
+    body: Vec<SourceLine>,
+}
+
+/// An `.if` block being assembled or skipped
+struct Condition {
+    /// Whether the lines of the current branch are assembled
+    active: bool,
+    /// Whether a branch of the block was assembled, or the whole block is skipped
+    taken: bool,
+    in_else: bool,
+    location: Location,
+}
+
+/// A constant whose value uses symbols defined after it, evaluated in the last pass
+struct Deferred {
+    name: String,
+    expr: Expr,
+    pc: u32,
+    scope: String,
+    location: Location,
+}
+
+/// Everything the first pass builds up
+struct FirstPass {
+    context: Context,
+    statements: Vec<Statement>,
+    macros: HashMap<String, Rc<Macro>>,
+    deferred: Vec<Deferred>,
+    /// Number of macro expansions and repetitions so far, names the scope of their
+    /// local labels
+    expansions: usize,
+    /// Number of macro expansions and includes the current line is nested in
+    depth: usize,
+}
+
+/// Two-pass assembler for the usual 6502 syntax
+///
+/// The first pass expands macros, repetitions and includes, sizes every statement and
+/// gives labels their addresses, the second evaluates the operands and encodes them.
+/// Opcodes are looked up in the same table the CPU executes from, so only the
+/// instructions of the chosen variant assemble. Undocumented opcodes are used for the
+/// mnemonics and modes no documented opcode has.
+///
+/// ```
+/// use rs_6502::asm::Assembler;
+/// use rs_6502::CpuVariant;
+///
+/// let source = "
+///         .org $0200
+/// start:  ldx #<message
+/// @loop:  dex
+///         bne @loop
+///         jmp start
+/// message .byte \"Hi\", 0
+/// ";
+///
+/// let image = Assembler::new(CpuVariant::Nmos6502).assemble(source).unwrap();
+///
+/// assert_eq!(image.segments[0].addr, 0x0200);
+/// assert_eq!(
+///     image.segments[0].data,
+///     [0xA2, 0x08, 0xCA, 0xD0, 0xFD, 0x4C, 0x00, 0x02, b'H', b'i', 0x00]
+/// );
+/// ```
+///
+/// Macros, conditional assembly and repetitions:
+///
+/// ```
+/// use rs_6502::asm::Assembler;
+/// use rs_6502::CpuVariant;
+///
+/// let source = "
+/// DEBUG = 0
+///         .macro store value, addr
+///         lda #value
+///         sta addr
+///         .endmacro
+///
+///         .org $0300
+///         store 1, $10
+///         .if DEBUG
+///         brk
+///         .else
+///         .repeat 2, i
+///         .byte i * 3
+///         .endrepeat
+///         .endif
+/// ";
+///
+/// let image = Assembler::new(CpuVariant::Nmos6502).assemble(source).unwrap();
+///
+/// assert_eq!(image.segments[0].data, [0xA9, 0x01, 0x85, 0x10, 0x00, 0x03]);
+/// ```
+pub struct Assembler {
+    variant: CpuVariant,
+    /// Opcodes of every mnemonic, the documented ones first
+    opcodes: HashMap<&'static str, Vec<(AddrMode, u8)>>,
+}
+
+/// Symbols and position of a pass
+struct Context {
+    /// Value of every symbol, `None` for constants waiting for the last pass
+    symbols: HashMap<String, Option<i64>>,
+    /// Address of the next statement, past `$FFFF` once the output overflows
+    pc: u32,
+    scope: String,
+    /// Undefined symbols are errors in the last pass, unknown values before it
+    final_pass: bool,
+}
+
+impl Context {
+    /// Returns the full name of a symbol, local labels are prefixed with their scope
+    fn qualify(&self, name: &str) -> String {
+        if name.starts_with('@') {
+            format!("{}{}", self.scope, name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// Evaluates an expression
+    ///
+    /// ### Returns:
+    /// * The value, `None` if a symbol isn't defined yet in the first pass
+    fn eval(&self, expr: &Expr) -> Result<Option<i64>, String> {
+        let value = match expr {
+            Expr::Number(value) => *value,
+            Expr::Pc => self.pc as i64,
+            Expr::Symbol(name) => match self.symbols.get(&self.qualify(name)) {
+                Some(Some(value)) => *value,
+                _ if self.final_pass => return Err(format!("Undefined symbol {}", name)),
+                _ => return Ok(None),
+            },
+            Expr::Unary(op, operand) => {
+                let Some(operand) = self.eval(operand)? else {
+                    return Ok(None);
+                };
+                match *op {
+                    "-" => operand.wrapping_neg(),
+                    "~" => !operand,
+                    "!" => (operand == 0) as i64,
+                    "<" => operand & 0xFF,
+                    _ => (operand >> 8) & 0xFF,
+                }
+            }
+            Expr::Binary(op, left, right) => {
+                let (Some(left), Some(right)) = (self.eval(left)?, self.eval(right)?) else {
+                    return Ok(None);
+                };
+                match *op {
+                    "||" => (left != 0 || right != 0) as i64,
+                    "&&" => (left != 0 && right != 0) as i64,
+                    "|" => left | right,
+                    "^" => left ^ right,
+                    "&" => left & right,
+                    "==" => (left == right) as i64,
+                    "!=" => (left != right) as i64,
+                    "<" => (left < right) as i64,
+                    ">" => (left > right) as i64,
+                    "<=" => (left <= right) as i64,
+                    ">=" => (left >= right) as i64,
+                    "<<" => left.checked_shl(right as u32).unwrap_or(0),
+                    ">>" => left.checked_shr(right as u32).unwrap_or(0),
+                    "+" => left.wrapping_add(right),
+                    "-" => left.wrapping_sub(right),
+                    "*" => left.wrapping_mul(right),
+                    _ if right == 0 => return Err(String::from("Division by zero")),
+                    _ => left.wrapping_div(right),
+                }
+            }
+        };
+
+        Ok(Some(value))
+    }
+
+    /// Evaluates an expression whose value must be known in the first pass
+    fn eval_now(&self, expr: &Expr) -> Result<i64, String> {
+        self.eval(expr)?
+            .ok_or_else(|| String::from("Expression uses a symbol defined after it"))
+    }
+
+    /// Evaluates an expression in the last pass, when every symbol is known
+    fn eval_final(&self, expr: &Expr) -> Result<i64, String> {
+        Ok(self.eval(expr)?.unwrap_or_default())
+    }
+
+    /// Gives a symbol a value, labels take the current address
+    ///
+    /// ### Parameters:
+    /// * `name` - The name of the symbol, local names are qualified with the scope
+    /// * `value` - The value, `None` for the current address
+    ///
+    /// ### Returns:
+    /// * The qualified name, or `Err(String)` if the symbol is already defined
+    fn define(&mut self, name: &str, value: Option<Option<i64>>) -> Result<String, String> {
+        // Only global labels open a new scope for local labels
+        if value.is_none() && !name.contains('@') {
+            self.scope = name.to_string();
+        }
+
+        let qualified = self.qualify(name);
+        if self.symbols.contains_key(&qualified) {
+            return Err(format!("Symbol {} already defined", name));
+        }
+        self.symbols
+            .insert(qualified.clone(), value.unwrap_or(Some(self.pc as i64)));
+
+        Ok(qualified)
+    }
+
+    /// Computes the constants left for the last pass. They may use each other in any
+    /// order, so they are evaluated again and again until all of them are known.
+    ///
+    /// ### Returns:
+    /// * `Err(AsmError)` if a constant uses an undefined symbol or depends on itself
+    fn resolve(&mut self, deferred: &[Deferred]) -> Result<(), AsmError> {
+        let mut pending: Vec<&Deferred> = deferred.iter().collect();
+
+        while !pending.is_empty() {
+            let mut waiting = Vec::new();
+
+            for constant in pending.iter().copied() {
+                self.pc = constant.pc;
+                self.scope.clone_from(&constant.scope);
+                let value = self
+                    .eval(&constant.expr)
+                    .map_err(|message| constant.location.error(message))?;
+
+                match value {
+                    Some(value) => {
+                        self.symbols.insert(constant.name.clone(), Some(value));
+                    }
+                    None => waiting.push(constant),
+                }
+            }
+
+            if waiting.len() == pending.len() {
+                return Err(self.unresolved(&waiting));
+            }
+            pending = waiting;
+        }
+
+        Ok(())
+    }
+
+    /// Explains why none of the constants left could be computed: one of them uses an
+    /// undefined symbol, or they depend on each other
+    fn unresolved(&mut self, pending: &[&Deferred]) -> AsmError {
+        for constant in pending {
+            self.scope.clone_from(&constant.scope);
+            let undefined = constant
+                .expr
+                .symbols()
+                .into_iter()
+                .find(|name| !self.symbols.contains_key(&self.qualify(name)));
+
+            if let Some(name) = undefined {
+                return constant
+                    .location
+                    .error(format!("Undefined symbol {}", name));
+            }
+        }
+
+        // Every constant left waits for another one, following them leads to a cycle
+        let mut path = vec![pending[0]];
+        loop {
+            let constant = path[path.len() - 1];
+            self.scope.clone_from(&constant.scope);
+            let next = constant
+                .expr
+                .symbols()
+                .into_iter()
+                .find_map(|name| {
+                    let name = self.qualify(name);
+                    pending.iter().find(|other| other.name == name)
+                })
+                .expect("A constant left waits for another one");
+
+            if let Some(start) = path.iter().position(|other| other.name == next.name) {
+                let names: Vec<&str> = path[start..]
+                    .iter()
+                    .chain([next])
+                    .map(|constant| constant.name.as_str())
+                    .collect();
+                return path[start]
+                    .location
+                    .error(format!("Circular definition {}", names.join(" -> ")));
+            }
+            path.push(next);
+        }
+    }
+}
+
+impl Assembler {
+    /// Creates an assembler for the instruction set of a CPU variant
+    pub fn new(variant: CpuVariant) -> Assembler {
+        let mut opcodes: HashMap<&'static str, Vec<(AddrMode, u8)>> = HashMap::new();
+
+        for documented in [true, false] {
+            for opcode in 0..=255 {
+                let info = opcode_info(opcode, variant);
+                if info.documented == documented {
+                    opcodes
+                        .entry(info.mnemonic)
+                        .or_default()
+                        .push((info.addr_mode, opcode));
+                }
+            }
+        }
+
+        Assembler { variant, opcodes }
+    }
+
+    /// Returns the CPU variant whose instruction set is assembled
+    pub fn variant(&self) -> CpuVariant {
+        self.variant
+    }
+
+    /// Assembles a source held in memory. Errors name it `<source>` and files it
+    /// includes are looked up from the current directory.
+    ///
+    /// ### Parameters:
+    /// * `source` - The assembly source
+    ///
+    /// ### Returns:
+    /// * The assembled image, one segment per run of contiguous output, or
+    ///   `Err(AsmError)` pointing at the first faulty line
+    pub fn assemble(&self, source: &str) -> Result<Image, AsmError> {
+        self.run(source_lines(Rc::from(Path::new(SOURCE_NAME)), source))
+    }
+
+    /// Assembles a source file, the files it includes are looked up from its directory
+    ///
+    /// ### Parameters:
+    /// * `path` - The path of the source file
+    ///
+    /// ### Returns:
+    /// * The assembled image, or `Err(AsmError)` pointing at the first faulty line
+    pub fn assemble_file(&self, path: &Path) -> Result<Image, AsmError> {
+        let source = fs::read_to_string(path).map_err(|error| AsmError {
+            file: path.to_path_buf(),
+            line: 0,
+            message: error.to_string(),
+            chain: Vec::new(),
+        })?;
+
+        self.run(source_lines(Rc::from(path), &source))
+    }
+
+    /// Runs both passes over the lines of the top-level file
+    fn run(&self, lines: Vec<SourceLine>) -> Result<Image, AsmError> {
+        let mut pass = FirstPass {
+            context: Context {
+                symbols: HashMap::new(),
+                pc: 0,
+                scope: TOP_SCOPE.to_string(),
+                final_pass: false,
+            },
+            statements: Vec::new(),
+            macros: HashMap::new(),
+            deferred: Vec::new(),
+            expansions: 0,
+            depth: 0,
+        };
+        self.process(&lines, &Rc::from([]), &mut pass)?;
+
+        let mut context = pass.context;
+        context.resolve(&pass.deferred)?;
+        context.final_pass = true;
+
+        let mut image = Image::default();
+        let mut written = vec![false; 0x10000];
+
+        for statement in &pass.statements {
+            let error = |message| statement.location.error(message);
+
+            context.pc = statement.addr as u32;
+            context.scope.clone_from(&statement.scope);
+            let data = self.encode(statement, &context).map_err(error)?;
+
+            let range = statement.addr as usize..statement.addr as usize + data.len();
+            if let Some(offset) = written[range.clone()].iter().position(|&used| used) {
+                return Err(error(format!(
+                    "Overwrites earlier output at ${:04X}",
+                    range.start + offset
+                )));
+            }
+            written[range.clone()].fill(true);
+
+            match image.segments.last_mut() {
+                Some(segment) if segment.addr as usize + segment.data.len() == range.start => {
+                    segment.data.extend(data)
+                }
+                _ if data.is_empty() => {}
+                _ => image.segments.push(Segment {
+                    addr: statement.addr,
+                    data,
+                }),
+            }
+        }
+
+        Ok(image)
+    }
+
+    /// Runs the first pass over lines, following conditional blocks and expanding
+    /// macros, repetitions and includes
+    ///
+    /// ### Parameters:
+    /// * `lines` - The lines of a file, macro body or repeated block
+    /// * `chain` - The macro invocations and includes the lines were reached through
+    /// * `pass` - The state of the first pass
+    fn process(
+        &self,
+        lines: &[SourceLine],
+        chain: &Rc<[Origin]>,
+        pass: &mut FirstPass,
+    ) -> Result<(), AsmError> {
+        let mut conditions: Vec<Condition> = Vec::new();
+        let mut index = 0;
+
+        while let Some(source) = lines.get(index) {
+            index += 1;
+            let location = Location {
+                file: source.file.clone(),
+                line: source.line,
+                chain: chain.clone(),
+            };
+            let error = |message| location.error(message);
+
+            let assembling = conditions.iter().all(|condition| condition.active);
+            let tokens = match tokenize(&source.text) {
+                Ok(tokens) => tokens,
+                // Skipped lines only need to be told apart from the block directives
+                Err(_) if !assembling => continue,
+                Err(message) => return Err(error(message)),
+            };
+            let operand = tokens.get(1..).unwrap_or_default();
+
+            match directive(&tokens).as_deref() {
+                Some(".if") => {
+                    // Conditions of skipped blocks aren't evaluated, their symbols may
+                    // not exist
+                    let active = assembling && {
+                        let value =
+                            parse_expr(operand).and_then(|expr| pass.context.eval_now(&expr));
+                        value.map_err(error)? != 0
+                    };
+                    conditions.push(Condition {
+                        active,
+                        taken: active || !assembling,
+                        in_else: false,
+                        location,
+                    });
+                }
+                Some(".else") => {
+                    let condition = conditions
+                        .last_mut()
+                        .ok_or_else(|| location.error(".else without .if"))?;
+                    if condition.in_else {
+                        return Err(location.error("Second .else for the same .if"));
+                    }
+                    condition.in_else = true;
+                    condition.active = !condition.taken;
+                }
+                Some(".endif") => {
+                    conditions
+                        .pop()
+                        .ok_or_else(|| location.error(".endif without .if"))?;
+                }
+                _ if !assembling => {}
+                Some(".macro") => {
+                    let end = block_end(&lines[index..], ".macro", ".endmacro")
+                        .ok_or_else(|| location.error("Missing .endmacro"))?;
+                    self.define_macro(operand, &lines[index..index + end], pass)
+                        .map_err(error)?;
+                    index += end + 1;
+                }
+                Some(".repeat") => {
+                    let end = block_end(&lines[index..], ".repeat", ".endrepeat")
+                        .ok_or_else(|| location.error("Missing .endrepeat"))?;
+                    self.repeat(operand, &lines[index..index + end], &location, pass)?;
+                    index += end + 1;
+                }
+                Some(".include") => self.include(operand, &location, pass)?,
+                Some(name @ (".endmacro" | ".endrepeat")) => {
+                    return Err(location.error(format!("{} without its opening block", name)))
+                }
+                _ => self.statement(source, &tokens, &location, pass)?,
+            }
+        }
+
+        match conditions.last() {
+            Some(condition) => Err(condition.location.error("Missing .endif")),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs the first pass over lines expanded from a macro, a repetition or an include
+    ///
+    /// ### Parameters:
+    /// * `lines` - The expanded lines
+    /// * `chain` - The macro invocations and includes the lines were reached through
+    /// * `location` - The line expanding them
+    /// * `local` - Whether the lines get their own scope for local labels
+    fn nested(
+        &self,
+        lines: &[SourceLine],
+        chain: Rc<[Origin]>,
+        location: &Location,
+        local: bool,
+        pass: &mut FirstPass,
+    ) -> Result<(), AsmError> {
+        if pass.depth == MAX_DEPTH {
+            return Err(location.error(format!(
+                "Nested more than {} levels deep, is a macro or file including itself?",
+                MAX_DEPTH
+            )));
+        }
+
+        // Like the top-level scope, it can't clash with a global label
+        let outer = local.then(|| {
+            pass.expansions += 1;
+            mem::replace(&mut pass.context.scope, format!(".{}", pass.expansions))
+        });
+        pass.depth += 1;
+
+        let result = self.process(lines, &chain, pass);
+
+        pass.depth -= 1;
+        if let Some(outer) = outer {
+            pass.context.scope = outer;
+        }
+
+        result
+    }
+
+    /// Defines a macro from `.macro name [param, ...]` and the lines of its body
+    fn define_macro(
+        &self,
+        operand: &[Token],
+        body: &[SourceLine],
+        pass: &mut FirstPass,
+    ) -> Result<(), String> {
+        let (name, params) = match operand {
+            [Token::Ident(name), params @ ..] if !name.starts_with(['.', '@']) => (name, params),
+            _ => return Err(String::from("Missing macro name")),
+        };
+        if pass.macros.contains_key(name) {
+            return Err(format!("Macro {} already defined", name));
+        }
+
+        let params = match params {
+            [] => Vec::new(),
+            _ => split_commas(params)
+                .into_iter()
+                .map(|param| match param {
+                    // Substituting it would also replace the register in `lda $10,x`
+                    _ if ["A", "X", "Y"].iter().any(|reg| is_register(param, reg)) => {
+                        Err(format!("Parameter {} names a register", untokenize(param)))
+                    }
+                    [Token::Ident(param)] if !param.starts_with(['.', '@']) => Ok(param.clone()),
+                    _ => Err(format!("Invalid parameter {}", untokenize(param))),
+                })
+                .collect::<Result<_, _>>()?,
+        };
+
+        pass.macros.insert(
+            name.clone(),
+            Rc::new(Macro {
+                params,
+                body: body.to_vec(),
+            }),
+        );
+
+        Ok(())
+    }
+
+    /// Expands a macro invocation
+    fn expand(
+        &self,
+        name: &str,
+        args: &[Token],
+        location: &Location,
+        pass: &mut FirstPass,
+    ) -> Result<(), AsmError> {
+        let definition = pass.macros[name].clone();
+        // Local labels passed as arguments belong to the scope of the invocation, not
+        // to the one of the expansion
+        let args: Vec<Token> = args
+            .iter()
+            .map(|token| match token {
+                Token::Ident(name) if name.starts_with('@') => {
+                    Token::Ident(pass.context.qualify(name))
+                }
+                token => token.clone(),
+            })
+            .collect();
+        let args: Vec<String> = match args.as_slice() {
+            [] => Vec::new(),
+            args => split_commas(args).into_iter().map(untokenize).collect(),
+        };
+        if args.len() != definition.params.len() {
+            return Err(location.error(format!(
+                "Macro {} takes {} arguments, got {}",
+                name,
+                definition.params.len(),
+                args.len()
+            )));
+        }
+
+        let body: Vec<SourceLine> = definition
+            .body
+            .iter()
+            .map(|line| SourceLine {
+                text: substitute(&line.text, &definition.params, &args),
+                ..line.clone()
+            })
+            .collect();
+
+        let origin = Origin::Macro {
+            name: name.to_string(),
+            file: location.file.to_path_buf(),
+            line: location.line,
+        };
+        let chain = [origin].into_iter().chain(location.chain.iter().cloned());
+
+        self.nested(&body, chain.collect(), location, true, pass)
+    }
+
+    /// Assembles the body of `.repeat count[, counter]` count times, the counter is
+    /// replaced with the number of the repetition, counted from 0
+    fn repeat(
+        &self,
+        operand: &[Token],
+        body: &[SourceLine],
+        location: &Location,
+        pass: &mut FirstPass,
+    ) -> Result<(), AsmError> {
+        let error = |message| location.error(message);
+
+        let (count, counter) = match split_commas(operand).as_slice() {
+            [count] => (*count, Vec::new()),
+            [count, [Token::Ident(counter)]] => (*count, vec![counter.clone()]),
+            _ => return Err(error(String::from("Expected .repeat count[, counter]"))),
+        };
+        let count = pass
+            .context
+            .eval_now(&parse_expr(count).map_err(error)?)
+            .map_err(error)?;
+        if count < 0 {
+            return Err(error(format!("Negative repeat count {}", count)));
+        }
+
+        for repetition in 0..count {
+            let value = [repetition.to_string()];
+            let lines: Vec<SourceLine> = body
+                .iter()
+                .map(|line| SourceLine {
+                    text: substitute(&line.text, &counter, &value),
+                    ..line.clone()
+                })
+                .collect();
+
+            self.nested(&lines, location.chain.clone(), location, true, pass)?;
+        }
+
+        Ok(())
+    }
+
+    /// Assembles the file named by `.include "file"` in place
+    fn include(
+        &self,
+        operand: &[Token],
+        location: &Location,
+        pass: &mut FirstPass,
+    ) -> Result<(), AsmError> {
+        let [Token::Str(path)] = operand else {
+            return Err(location.error("Expected .include \"file\""));
+        };
+
+        let path = location.resolve(path);
+        let source = fs::read_to_string(&path).map_err(|error| {
+            location.error(format!("Could not read {}: {}", path.display(), error))
+        })?;
+
+        let origin = Origin::Include {
+            file: location.file.to_path_buf(),
+            line: location.line,
+        };
+        let chain = [origin].into_iter().chain(location.chain.iter().cloned());
+
+        let lines = source_lines(Rc::from(path), &source);
+        self.nested(&lines, chain.collect(), location, false, pass)
+    }
+
+    /// Defines the label or constant of a line, expands a macro invocation or sizes
+    /// the statement
+    fn statement(
+        &self,
+        source: &SourceLine,
+        tokens: &[Token],
+        location: &Location,
+        pass: &mut FirstPass,
+    ) -> Result<(), AsmError> {
+        let error = |message| location.error(message);
+        let context = &mut pass.context;
+        let mut rest = tokens;
+
+        match rest {
+            // `name = value` and `name .equ value` define constants
+            [Token::Ident(name), Token::Punct("="), value @ ..] => {
+                return self.constant(name, value, location, pass)
+            }
+            [Token::Ident(name), Token::Ident(equ), value @ ..]
+                if equ.eq_ignore_ascii_case(".equ") =>
+            {
+                return self.constant(name, value, location, pass)
+            }
+            // A label ends with a colon, or starts the line without being an instruction
+            [Token::Ident(name), Token::Punct(":"), ..] if !name.starts_with('.') => {
+                context.define(name, None).map_err(error)?;
+                rest = &rest[2..];
+            }
+            [Token::Ident(name), ..]
+                if !source.text.starts_with(char::is_whitespace)
+                    && !name.starts_with('.')
+                    && !self.is_mnemonic(name)
+                    && !pass.macros.contains_key(name) =>
+            {
+                context.define(name, None).map_err(error)?;
+                rest = &rest[1..];
+            }
+            _ => {}
+        }
+
+        let (name, operand) = match rest {
+            [] => return Ok(()),
+            [Token::Ident(name), args @ ..] if pass.macros.contains_key(name) => {
+                return self.expand(name, args, location, pass)
+            }
+            [Token::Ident(name), operand @ ..] => (name.to_ascii_lowercase(), operand),
+            [token, ..] => return Err(error(format!("Unexpected {}", describe(token)))),
+        };
+
+        let context = &mut pass.context;
+        let emit = match name.as_str() {
+            ".org" => {
+                let addr = parse_expr(operand)
+                    .and_then(|expr| context.eval_now(&expr))
+                    .map_err(error)?;
+                context.pc = u16::try_from(addr).map_err(|_| {
+                    error(format!("Origin ${:X} is outside the address space", addr))
+                })? as u32;
+                return Ok(());
+            }
+            ".byte" => Emit::Bytes(
+                split_commas(operand)
+                    .into_iter()
+                    .map(|item| match item {
+                        [Token::Str(text)] => Ok(ByteItem::Str(text.clone())),
+                        _ => parse_expr(item).map(ByteItem::Expr),
+                    })
+                    .collect::<Result<_, _>>()
+                    .map_err(error)?,
+            ),
+            ".word" => Emit::Words(
+                split_commas(operand)
+                    .into_iter()
+                    .map(parse_expr)
+                    .collect::<Result<_, _>>()
+                    .map_err(error)?,
+            ),
+            ".incbin" => {
+                let [Token::Str(path)] = operand else {
+                    return Err(error(String::from("Expected .incbin \"file\"")));
+                };
+                let path = location.resolve(path);
+                let data = fs::read(&path).map_err(|reason| {
+                    error(format!("Could not read {}: {}", path.display(), reason))
+                })?;
+                Emit::Data(data)
+            }
+            ".if" | ".else" | ".endif" | ".macro" | ".endmacro" | ".repeat" | ".endrepeat"
+            | ".include" => return Err(error(format!("{} must start the line", name))),
+            _ if name.starts_with('.') => return Err(error(format!("Unknown directive {}", name))),
+            _ => self.instruction(&name, operand, context).map_err(error)?,
+        };
+
+        let size = match &emit {
+            Emit::Instruction { mode, .. } => mode.instruction_bytes() as usize,
+            Emit::Bytes(items) => items
+                .iter()
+                .map(|item| match item {
+                    ByteItem::Expr(_) => 1,
+                    ByteItem::Str(text) => text.len(),
+                })
+                .sum(),
+            Emit::Words(words) => 2 * words.len(),
+            Emit::Data(data) => data.len(),
+        };
+        if context.pc as usize + size > 0x10000 {
+            return Err(error(String::from("Output runs past $FFFF")));
+        }
+
+        pass.statements.push(Statement {
+            location: location.clone(),
+            addr: context.pc as u16,
+            scope: context.scope.clone(),
+            emit,
+        });
+        context.pc += size as u32;
+
+        Ok(())
+    }
+
+    /// Defines a constant, its value is computed in the last pass if it uses symbols
+    /// defined after it
+    fn constant(
+        &self,
+        name: &str,
+        value: &[Token],
+        location: &Location,
+        pass: &mut FirstPass,
+    ) -> Result<(), AsmError> {
+        let error = |message| location.error(message);
+        let context = &mut pass.context;
+
+        if name.starts_with('.') {
+            return Err(error(format!("Invalid constant name {}", name)));
+        }
+        let expr = parse_expr(value).map_err(error)?;
+        let value = context.eval(&expr).map_err(error)?;
+        let name = context.define(name, Some(value)).map_err(error)?;
+
+        if value.is_none() {
+            pass.deferred.push(Deferred {
+                name,
+                expr,
+                pc: context.pc,
+                scope: context.scope.clone(),
+                location: location.clone(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether a word is a mnemonic of the variant
+    fn is_mnemonic(&self, name: &str) -> bool {
+        self.opcodes
+            .contains_key(name.to_ascii_uppercase().as_str())
+    }
+
+    /// Returns the opcode of a mnemonic in an addressing mode, if the variant has one
+    fn opcode(&self, mnemonic: &str, mode: AddrMode) -> Option<u8> {
+        self.opcodes
+            .get(mnemonic)?
+            .iter()
+            .find(|(candidate, _)| *candidate == mode)
+            .map(|(_, opcode)| *opcode)
+    }
+
+    /// Picks the addressing mode of an instruction from the syntax of its operand
+    fn instruction(
+        &self,
+        name: &str,
+        operand: &[Token],
+        context: &Context,
+    ) -> Result<Emit, String> {
+        let mnemonic = name.to_ascii_uppercase();
+        if !self.opcodes.contains_key(mnemonic.as_str()) {
+            return Err(format!("Unknown instruction {}", name));
+        }
+        let has = |mode| self.opcode(&mnemonic, mode).is_some();
+
+        // Zero page when the value is known to fit, absolute otherwise
+        let sized = |zero_page, absolute, expr: &Expr| -> Result<AddrMode, String> {
+            let fits = matches!(context.eval(expr)?, Some(0..=0xFF));
+            Ok(match (has(zero_page), has(absolute)) {
+                (true, _) if fits => zero_page,
+                (_, true) => absolute,
+                _ => zero_page,
+            })
+        };
+
+        // Plain addresses are branch targets for the branches
+        let direct = |addr: Expr| -> Result<(AddrMode, Vec<Expr>), String> {
+            if has(AddrMode::Rel) {
+                Ok((AddrMode::Rel, vec![addr]))
+            } else {
+                Ok((sized(AddrMode::ZeroPage, AddrMode::Abs, &addr)?, vec![addr]))
+            }
+        };
+
+        let parts = split_commas(operand);
+        let (mode, operands) = match parts.as_slice() {
+            [[]] if has(AddrMode::Impl) => (AddrMode::Impl, vec![]),
+            [[]] if has(AddrMode::Accumulator) => (AddrMode::Accumulator, vec![]),
+            [[]] => return Err(format!("{} needs an operand", mnemonic)),
+            [register] if is_register(register, "A") && has(AddrMode::Accumulator) => {
+                (AddrMode::Accumulator, vec![])
+            }
+            [[Token::Punct("#"), value @ ..]] => (AddrMode::Immediate, vec![parse_expr(value)?]),
+            [addr, target] if has(AddrMode::ZeroPageRel) => (
+                AddrMode::ZeroPageRel,
+                vec![parse_expr(addr)?, parse_expr(target)?],
+            ),
+            [[Token::Punct("("), inner @ .., Token::Punct(")")]]
+                if closing_paren(operand) == Some(operand.len() - 1) =>
+            {
+                match split_commas(inner).as_slice() {
+                    [addr, register] if is_register(register, "X") => {
+                        let addr = parse_expr(addr)?;
+                        (sized(AddrMode::IndX, AddrMode::AbsIndX, &addr)?, vec![addr])
+                    }
+                    [addr] if has(AddrMode::Ind) || has(AddrMode::ZeroPageInd) => {
+                        let addr = parse_expr(addr)?;
+                        (
+                            sized(AddrMode::ZeroPageInd, AddrMode::Ind, &addr)?,
+                            vec![addr],
+                        )
+                    }
+                    // Parentheses around the whole operand always mean indirection,
+                    // they only group an expression that goes on after them
+                    [_] => {
+                        return Err(format!(
+                            "{} has no indirect addressing mode on the {}",
+                            mnemonic, self.variant
+                        ))
+                    }
+                    _ => return Err(String::from("Invalid operand")),
+                }
+            }
+            [[Token::Punct("("), inner @ .., Token::Punct(")")], register]
+                if is_register(register, "Y")
+                    && closing_paren(parts[0]) == Some(parts[0].len() - 1) =>
+            {
+                (AddrMode::IndY, vec![parse_expr(inner)?])
+            }
+            [addr, register] if is_register(register, "X") => {
+                let addr = parse_expr(addr)?;
+                (
+                    sized(AddrMode::ZeroPageX, AddrMode::AbsX, &addr)?,
+                    vec![addr],
+                )
+            }
+            [addr, register] if is_register(register, "Y") => {
+                let addr = parse_expr(addr)?;
+                (
+                    sized(AddrMode::ZeroPageY, AddrMode::AbsY, &addr)?,
+                    vec![addr],
+                )
+            }
+            [addr] => direct(parse_expr(addr)?)?,
+            _ => return Err(String::from("Invalid operand")),
+        };
+
+        match self.opcode(&mnemonic, mode) {
+            Some(opcode) => Ok(Emit::Instruction {
+                opcode,
+                mode,
+                operands,
+            }),
+            None => Err(format!(
+                "{} has no {} addressing mode",
+                mnemonic,
+                mode_name(mode)
+            )),
+        }
+    }
+
+    /// Encodes a statement once every symbol is known
+    fn encode(&self, statement: &Statement, context: &Context) -> Result<Vec<u8>, String> {
+        let mut data = Vec::new();
+
+        match &statement.emit {
+            Emit::Instruction {
+                opcode,
+                mode,
+                operands,
+            } => {
+                data.push(*opcode);
+                let values = operands
+                    .iter()
+                    .map(|expr| context.eval_final(expr))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let next = statement.addr as i64 + mode.instruction_bytes() as i64;
+
+                match (mode, values.as_slice()) {
+                    (AddrMode::Immediate, [value]) => data.push(byte(*value)?),
+                    (AddrMode::Rel, [target]) => data.push(branch_offset(*target, next)?),
+                    (AddrMode::ZeroPageRel, [addr, target]) => {
+                        data.push(zero_page(*addr)?);
+                        data.push(branch_offset(*target, next)?);
+                    }
+                    (_, [addr]) if mode.instruction_bytes() == 2 => data.push(zero_page(*addr)?),
+                    (_, [addr]) => data.extend(word(*addr)?.to_le_bytes()),
+                    _ => {}
+                }
+            }
+            Emit::Bytes(items) => {
+                for item in items {
+                    match item {
+                        ByteItem::Expr(expr) => data.push(byte(context.eval_final(expr)?)?),
+                        ByteItem::Str(text) => data.extend(text.bytes()),
+                    }
+                }
+            }
+            Emit::Words(words) => {
+                for expr in words {
+                    data.extend(word(context.eval_final(expr)?)?.to_le_bytes());
+                }
+            }
+            Emit::Data(bytes) => data.extend_from_slice(bytes),
+        }
+
+        Ok(data)
+    }
+}
+
+/// Checks a value fits in a byte, negative values are stored in two's complement
+fn byte(value: i64) -> Result<u8, String> {
+    match value {
+        -0x80..=0xFF => Ok(value as u8),
+        _ => Err(format!("Value {} doesn't fit in a byte", value)),
+    }
+}
+
+/// Checks a value fits in a word, negative values are stored in two's complement
+fn word(value: i64) -> Result<u16, String> {
+    match value {
+        -0x8000..=0xFFFF => Ok(value as u16),
+        _ => Err(format!("Value {} doesn't fit in a word", value)),
+    }
+}
+
+/// Checks an address is in the zero page
+fn zero_page(addr: i64) -> Result<u8, String> {
+    match addr {
+        0..=0xFF => Ok(addr as u8),
+        _ => Err(format!("Address ${:X} is not in the zero page", addr)),
+    }
+}
+
+/// Returns the offset of a branch from the instruction that follows it
+fn branch_offset(target: i64, next: i64) -> Result<u8, String> {
+    match target - next {
+        offset @ -0x80..=0x7F => Ok(offset as u8),
+        offset => Err(format!(
+            "Branch target is {} bytes away, out of range",
+            offset
+        )),
+    }
+}
+
+/// Names an addressing mode in error messages
+fn mode_name(mode: AddrMode) -> &'static str {
+    match mode {
+        AddrMode::Accumulator => "accumulator",
+        AddrMode::Abs | AddrMode::ZeroPage => "direct",
+        AddrMode::AbsX | AddrMode::ZeroPageX => "X indexed",
+        AddrMode::AbsY | AddrMode::ZeroPageY => "Y indexed",
+        AddrMode::Immediate => "immediate",
+        AddrMode::Impl => "implied",
+        AddrMode::Ind | AddrMode::ZeroPageInd => "indirect",
+        AddrMode::IndX | AddrMode::AbsIndX => "indexed indirect",
+        AddrMode::IndY => "indirect indexed",
+        AddrMode::Rel | AddrMode::ZeroPageRel => "relative",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assemble(variant: CpuVariant, source: &str) -> Result<Vec<Segment>, AsmError> {
+        Assembler::new(variant)
+            .assemble(source)
+            .map(|image| image.segments)
+    }
+
+    fn output(source: &str) -> Vec<Segment> {
+        assemble(CpuVariant::Nmos6502, source).unwrap()
+    }
+
+    fn error(source: &str) -> AsmError {
+        assemble(CpuVariant::Nmos6502, source).unwrap_err()
+    }
+
+    fn segment(addr: u16, data: &[u8]) -> Segment {
+        Segment {
+            addr,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn zero_page_and_absolute_sizing() {
+        let source = "
+zp = $10
+        .org $0200
+        lda zp
+        lda zp,x
+        lda $0300
+        lda later
+        ldx later,y
+later = $20
+";
+
+        assert_eq!(
+            output(source),
+            vec![segment(
+                0x0200,
+                &[0xA5, 0x10, 0xB5, 0x10, 0xAD, 0x00, 0x03, 0xAD, 0x20, 0x00, 0xBE, 0x20, 0x00]
+            )]
+        );
+    }
+
+    #[test]
+    fn forward_references() {
+        let source = "
+        .org $0200
+start:  beq done
+        jmp done
+        .word done, start
+done:   rts
+";
+
+        assert_eq!(
+            output(source),
+            vec![segment(
+                0x0200,
+                &[0xF0, 0x07, 0x4C, 0x09, 0x02, 0x09, 0x02, 0x00, 0x02, 0x60]
+            )]
+        );
+    }
+
+    #[test]
+    fn local_labels() {
+        let source = "
+        .org $0200
+one:    nop
+@loop:  bne @loop
+two:    nop
+@loop:  bne @loop
+        jmp one@loop
+";
+
+        assert_eq!(
+            output(source),
+            vec![segment(
+                0x0200,
+                &[0xEA, 0xD0, 0xFE, 0xEA, 0xD0, 0xFE, 0x4C, 0x01, 0x02]
+            )]
+        );
+    }
+
+    #[test]
+    fn branch_range() {
+        let source = "
+        .org $0200
+        bne far
+        .org $0281
+far:    nop
+";
+        assert_eq!(
+            output(source),
+            vec![segment(0x0200, &[0xD0, 0x7F]), segment(0x0281, &[0xEA])]
+        );
+
+        let error = error(&source.replace("$0281", "$0282"));
+        assert_eq!(error.line, 3);
+        assert_eq!(
+            error.message,
+            "Branch target is 128 bytes away, out of range"
+        );
+    }
+
+    #[test]
+    fn org_overlap() {
+        let error = error(
+            "
+        .org $0200
+        .byte 1, 2, 3
+        .org $0202
+        .byte 4
+",
+        );
+
+        assert_eq!(error.line, 5);
+        assert_eq!(error.message, "Overwrites earlier output at $0202");
+    }
+
+    #[test]
+    fn output_past_end() {
+        assert_eq!(
+            output("\t.org $FFFE\n\t.word $1234\n"),
+            vec![segment(0xFFFE, &[0x34, 0x12])]
+        );
+
+        let error = error("\t.org $FFFF\n\t.word $1234\n");
+        assert_eq!(error.line, 2);
+        assert_eq!(error.message, "Output runs past $FFFF");
+    }
+
+    #[test]
+    fn include_and_incbin() {
+        let dir = std::env::temp_dir().join(format!("rs6502-asm-{}", std::process::id()));
+        fs::create_dir_all(dir.join("inc")).unwrap();
+        fs::write(dir.join("inc/data.bin"), [0xDE, 0xAD]).unwrap();
+        fs::write(
+            dir.join("inc/part.s"),
+            "\tlda #VALUE\n\t.incbin \"data.bin\"\n",
+        )
+        .unwrap();
+        fs::write(dir.join("bad.s"), "\t.byte 1\n\t.byte 256\n").unwrap();
+
+        let main = dir.join("main.s");
+        fs::write(
+            &main,
+            "VALUE = 7\n\t.org $0200\n\t.include \"inc/part.s\"\n",
+        )
+        .unwrap();
+        let image = Assembler::new(CpuVariant::Nmos6502).assemble_file(&main);
+
+        let nested = dir.join("nested.s");
+        fs::write(&nested, "\tnop\n\t.include \"bad.s\"\n").unwrap();
+        let error = Assembler::new(CpuVariant::Nmos6502).assemble_file(&nested);
+
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            image.unwrap().segments,
+            vec![segment(0x0200, &[0xA9, 0x07, 0xDE, 0xAD])]
+        );
+
+        let error = error.unwrap_err();
+        assert_eq!(error.file, dir.join("bad.s"));
+        assert_eq!(error.line, 2);
+        assert_eq!(error.message, "Value 256 doesn't fit in a byte");
+        assert_eq!(
+            error.chain,
+            vec![Origin::Include {
+                file: nested,
+                line: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn missing_include() {
+        let error = error("\t.incbin \"missing/file.bin\"\n");
+
+        assert_eq!(error.line, 1);
+        assert!(error
+            .message
+            .starts_with("Could not read missing/file.bin: "));
+    }
+
+    #[test]
+    fn error_expansion_chain() {
+        let error = error(
+            "
+        .macro inner
+        .byte 300
+        .endmacro
+        .macro outer
+        inner
+        .endmacro
+        outer
+",
+        );
+
+        assert_eq!(error.line, 3);
+        assert_eq!(error.message, "Value 300 doesn't fit in a byte");
+        assert_eq!(
+            error.chain,
+            vec![
+                Origin::Macro {
+                    name: String::from("inner"),
+                    file: PathBuf::from(SOURCE_NAME),
+                    line: 6
+                },
+                Origin::Macro {
+                    name: String::from("outer"),
+                    file: PathBuf::from(SOURCE_NAME),
+                    line: 8
+                },
+            ]
+        );
+        assert_eq!(
+            error.to_string(),
+            "<source>:3: Value 300 doesn't fit in a byte\n    \
+             in macro inner invoked at <source>:6\n    \
+             in macro outer invoked at <source>:8"
+        );
+    }
+
+    #[test]
+    fn conditionals_nested_in_skipped_blocks() {
+        let source = "
+        .org $0200
+        .if 0
+        .if 1
+        .byte 1
+        .else
+        .byte 2
+        .endif
+        .if UNDEFINED
+        .endif
+        .byte 3
+        .else
+        .if 0
+        .byte 4
+        .else
+        .byte 5
+        .endif
+        .endif
+";
+        assert_eq!(output(source), vec![segment(0x0200, &[5])]);
+
+        let error = error("\t.if 0\n\t.if 1\n\t.endif\n");
+        assert_eq!(error.line, 1);
+        assert_eq!(error.message, "Missing .endif");
+    }
+
+    #[test]
+    fn indirect_modes() {
+        let source = "
+        .org $0200
+        jmp ($1234)
+        lda ($10),y
+        lda ($10,x)
+        lda ($10 + 1) * 2
+";
+        assert_eq!(
+            output(source),
+            vec![segment(
+                0x0200,
+                &[0x6C, 0x34, 0x12, 0xB1, 0x10, 0xA1, 0x10, 0xA5, 0x22]
+            )]
+        );
+
+        let source = "\tlda ($10)\n";
+        assert_eq!(
+            assemble(CpuVariant::Wdc65C02, source).unwrap(),
+            vec![segment(0x0000, &[0xB2, 0x10])]
+        );
+        assert_eq!(
+            error(source).message,
+            "LDA has no indirect addressing mode on the MOS 6502"
+        );
+    }
+
+    #[test]
+    fn macro_arguments_use_the_invoking_scope() {
+        let source = "
+        .macro jump target
+        jmp target
+        .endmacro
+        .macro loop_to target
+@loop:  dex
+        bne target
+        .endmacro
+        .org $0200
+start:  nop
+@here:  jump @here
+        loop_to @here
+";
+        assert_eq!(
+            output(source),
+            vec![segment(0x0200, &[0xEA, 0x4C, 0x01, 0x02, 0xCA, 0xD0, 0xFA])]
+        );
+
+        // The label of the expansion is not the one the invoking code names
+        let error = error(&format!("{}        loop_to @loop\n", source));
+        assert_eq!(error.line, 7);
+        assert_eq!(error.message, "Undefined symbol start@loop");
+    }
+
+    #[test]
+    fn macro_parameters_naming_registers() {
+        let source = "
+        .macro load addr
+        lda addr,x
+        ldy addr+1,x
+        .endmacro
+        .org $0200
+        load $10
+";
+        assert_eq!(
+            output(source),
+            vec![segment(0x0200, &[0xB5, 0x10, 0xB4, 0x11])]
+        );
+
+        let x = error(&source.replace("addr", "x"));
+        assert_eq!(x.line, 2);
+        assert_eq!(x.message, "Parameter x names a register");
+
+        let y = error("\t.macro store value, Y\n\tsta value,Y\n\t.endmacro\n");
+        assert_eq!(y.line, 1);
+        assert_eq!(y.message, "Parameter Y names a register");
+    }
+
+    #[test]
+    fn deferred_constants() {
+        let source = "
+A1 = B1 + 1
+B1 = C1
+        .org $0200
+C1:     nop
+        .word A1, B1
+";
+        assert_eq!(
+            output(source),
+            vec![segment(0x0200, &[0xEA, 0x01, 0x02, 0x00, 0x02])]
+        );
+
+        let cycle = error("\t.word A\nA = B\nB = C + 1\nC = A\n");
+        assert_eq!(cycle.line, 2);
+        assert_eq!(cycle.message, "Circular definition A -> B -> C -> A");
+
+        let undefined = error("\t.word A\nA = B\nB = Q\n");
+        assert_eq!(undefined.line, 3);
+        assert_eq!(undefined.message, "Undefined symbol Q");
+    }
+}
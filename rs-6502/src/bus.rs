@@ -0,0 +1,265 @@
+use crate::mapper::BankWindow;
+use crate::save_state::{StateError, StateReader, StateWriter};
+
+/// What an address of the bus is backed by
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum RegionKind {
+    #[default]
+    Ram,
+    /// Read-only, writes from the CPU never change it
+    Rom,
+}
+
+/// A write the CPU attempted on a ROM region
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RomWrite {
+    pub addr: u16,
+    pub data: u8,
+}
+
+/// Anything that can sit on the 6502 address bus
+///
+/// The CPU performs every memory access through this trait, so RAM, ROM, I/O
+/// chips and mirrored regions can be attached without changing the CPU core.
+pub trait Bus {
+    /// Reads a byte as the CPU would, which may trigger side effects on devices
+    ///
+    /// ### Parameters:
+    /// * `addr` - The address to read from
+    ///
+    /// ### Returns:
+    /// * The byte currently on the data bus
+    fn read_byte(&mut self, addr: u16) -> u8;
+
+    /// Writes a byte to the given address
+    ///
+    /// ### Parameters:
+    /// * `addr` - The address to write to
+    /// * `data` - The byte to be written
+    fn write_byte(&mut self, addr: u16, data: u8);
+
+    /// Reads a byte without any side effects, used by the debugger and disassembler
+    ///
+    /// ### Parameters:
+    /// * `addr` - The address to read from
+    ///
+    /// ### Returns:
+    /// * The byte stored at the given address
+    fn peek_byte(&self, addr: u16) -> u8;
+
+    /// Returns a copy of `size` bytes starting at `first_index`, clamped to the end of
+    /// the address space
+    fn peek_range(&self, first_index: usize, size: usize) -> Vec<u8> {
+        let end = (first_index + size).min(0x10000);
+
+        (first_index..end)
+            .map(|addr| self.peek_byte(addr as u16))
+            .collect()
+    }
+
+    /// Advances the devices attached to the bus, called by the CPU after every cycle
+    ///
+    /// ### Parameters:
+    /// * `cycles` - The number of CPU cycles elapsed since the last call
+    fn tick(&mut self, cycles: u64) {
+        let _ = cycles;
+    }
+
+    /// Returns whether a device on the bus pulls the IRQ line
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Returns whether a device on the bus pulls the NMI line
+    fn nmi(&self) -> bool {
+        false
+    }
+}
+
+/// What the debugger, the history and save states need from a bus on top of what the
+/// CPU uses: ROM regions, bank switching, and saving the state of every device
+///
+/// Every method has a default for buses without ROM, banks or state of their own, so
+/// implementing it can be as short as `impl Inspect for MyBus {}`.
+pub trait Inspect: Bus {
+    /// Stores a byte where the CPU would read it back, bypassing ROM protection, mapper
+    /// registers and devices. Used to undo writes.
+    ///
+    /// ### Parameters:
+    /// * `addr` - The address to write to
+    /// * `data` - The byte to be stored
+    fn poke_byte(&mut self, addr: u16, data: u8) {
+        self.write_byte(addr, data);
+    }
+
+    /// Returns the bank windows of the bus and the bank each one shows, empty when
+    /// nothing is bank switched
+    fn bank_windows(&self) -> Vec<BankWindow> {
+        Vec::new()
+    }
+
+    /// Restores a configuration returned by [`Inspect::bank_windows`], used to rewind bank
+    /// switches
+    ///
+    /// ### Parameters:
+    /// * `windows` - The windows as they were
+    fn set_bank_windows(&mut self, windows: &[BankWindow]) {
+        let _ = windows;
+    }
+
+    /// Returns the size of the banked physical memory, 0 when there is none
+    fn physical_len(&self) -> usize {
+        0
+    }
+
+    /// Reads a byte of banked physical memory without side effects, whether or not its
+    /// bank is currently shown
+    ///
+    /// ### Parameters:
+    /// * `addr` - The physical address, below [`Inspect::physical_len`]
+    fn peek_physical(&self, addr: usize) -> u8 {
+        let _ = addr;
+        0
+    }
+
+    /// Returns what backs an address, used by the debugger to tell RAM from ROM
+    ///
+    /// ### Parameters:
+    /// * `addr` - The address to look up
+    fn region(&self, addr: u16) -> RegionKind {
+        let _ = addr;
+        RegionKind::Ram
+    }
+
+    /// Returns the write to ROM that must stop the CPU, if one happened since the last
+    /// call. A CPU set up with [`Cpu::poll_rom_faults`] polls it after every cycle and
+    /// reports it as an error.
+    ///
+    /// [`Cpu::poll_rom_faults`]: crate::cpu::Cpu::poll_rom_faults
+    fn take_rom_fault(&mut self) -> Option<RomWrite> {
+        None
+    }
+
+    /// Returns and forgets the writes to ROM logged since the last call
+    fn take_rom_writes(&mut self) -> Vec<RomWrite> {
+        Vec::new()
+    }
+
+    /// Appends the state of the bus and every device attached to it to a save state.
+    /// Buses without state of their own can keep the default, which saves nothing.
+    ///
+    /// ### Parameters:
+    /// * `writer` - The save state being built
+    fn save_state(&self, writer: &mut StateWriter) {
+        let _ = writer;
+    }
+
+    /// Restores what [`Inspect::save_state`] saved, reading the fields in the same order
+    ///
+    /// ### Parameters:
+    /// * `reader` - The save state, positioned where the bus state starts
+    ///
+    /// ### Returns:
+    /// * `Err(StateError)` if the saved state is truncated or invalid
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let _ = reader;
+        Ok(())
+    }
+}
+
+/// A bus placed in front of another one to observe the CPU writes going through it
+///
+/// Implementing it is enough to get [`Bus`], and [`Inspect`] when the inner bus has
+/// it: everything is forwarded to the inner bus, each write being shown to
+/// [`BusWrapper::observe_write`] first.
+pub trait BusWrapper {
+    /// The bus doing the actual reads and writes
+    type Inner: Bus;
+
+    fn inner(&self) -> &Self::Inner;
+
+    fn inner_mut(&mut self) -> &mut Self::Inner;
+
+    /// Called with every CPU write before it reaches the inner bus
+    ///
+    /// ### Parameters:
+    /// * `addr` - The address written
+    /// * `data` - The byte written
+    fn observe_write(&mut self, addr: u16, data: u8);
+}
+
+impl<W: BusWrapper> Bus for W {
+    fn read_byte(&mut self, addr: u16) -> u8 {
+        self.inner_mut().read_byte(addr)
+    }
+
+    fn write_byte(&mut self, addr: u16, data: u8) {
+        self.observe_write(addr, data);
+        self.inner_mut().write_byte(addr, data);
+    }
+
+    fn peek_byte(&self, addr: u16) -> u8 {
+        self.inner().peek_byte(addr)
+    }
+
+    fn peek_range(&self, first_index: usize, size: usize) -> Vec<u8> {
+        self.inner().peek_range(first_index, size)
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.inner_mut().tick(cycles);
+    }
+
+    fn irq(&self) -> bool {
+        self.inner().irq()
+    }
+
+    fn nmi(&self) -> bool {
+        self.inner().nmi()
+    }
+}
+
+impl<W: BusWrapper> Inspect for W
+where
+    W::Inner: Inspect,
+{
+    fn poke_byte(&mut self, addr: u16, data: u8) {
+        self.inner_mut().poke_byte(addr, data);
+    }
+
+    fn bank_windows(&self) -> Vec<BankWindow> {
+        self.inner().bank_windows()
+    }
+
+    fn set_bank_windows(&mut self, windows: &[BankWindow]) {
+        self.inner_mut().set_bank_windows(windows);
+    }
+
+    fn physical_len(&self) -> usize {
+        self.inner().physical_len()
+    }
+
+    fn peek_physical(&self, addr: usize) -> u8 {
+        self.inner().peek_physical(addr)
+    }
+
+    fn region(&self, addr: u16) -> RegionKind {
+        self.inner().region(addr)
+    }
+
+    fn take_rom_fault(&mut self) -> Option<RomWrite> {
+        self.inner_mut().take_rom_fault()
+    }
+
+    fn take_rom_writes(&mut self) -> Vec<RomWrite> {
+        self.inner_mut().take_rom_writes()
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.inner().save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.inner_mut().load_state(reader)
+    }
+}
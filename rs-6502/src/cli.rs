@@ -0,0 +1,350 @@
+use rs_6502::loader::{parse_addr, ImageFormat, LoadSpec};
+use rs_6502::{CpuVariant, RomWritePolicy, StopConditions};
+use std::path::PathBuf;
+
+/// Printed by `--help`
+pub const HELP: &str = "\
+Usage: rs-6502 [OPTIONS] [<rom> [<variant>]]
+       rs-6502 asm [ASM OPTIONS] <source>
+
+Loads the images and runs them in the terminal debugger, or without it with --headless.
+A <rom> given on its own is loaded first, raw binaries at $0000.
+
+Options:
+  --load <file>[@ADDR][,rom][,reset][,irq][,nmi]
+                        Loads an image, may be repeated. ADDR places raw binaries and
+                        PRG files, rom tags the bytes as ROM and reset/irq/nmi point
+                        the vectors at the image
+  --format <format>     Format of the images that follow: bin, hex, srec or prg.
+                        Detected from the extension and contents by default
+  --variant <variant>   CPU to emulate: nmos, 65c02, r65c02 or 2a03 (default nmos)
+  --reset <ADDR>        Points the reset vector at ADDR before the CPU is reset
+  --pc <ADDR>           Starts executing at ADDR instead of the reset vector
+  --break <ADDR>        Sets a breakpoint, may be repeated
+  --rom-writes <policy> What a write to ROM does: ignore, log or break (default
+                        ignore). The ROM is never changed, log reports the writes
+                        and break stops the CPU at the writing instruction
+  --headless            Runs without the terminal debugger until a stop condition holds,
+                        then prints the final state
+  --max-cycles <N>      Stops a headless run after N cycles
+  --max-instructions <N>
+                        Stops a headless run after N instructions
+  --stop-on-brk         Stops a headless run before a BRK executes
+  --stop-on-trap        Stops a headless run when an instruction jumps onto itself
+  --stop-on-write <ADDR>
+                        Stops a headless run after a write to ADDR
+  --stop-on-illegal     Stops a headless run before an undocumented opcode executes
+  --trace <file>        Writes every instruction of a headless run to a file
+  -h, --help            Prints this help
+
+Addresses are hexadecimal, with or without a $ or 0x prefix.
+
+A headless run exits with a code telling why it stopped: 0 breakpoint, 1 CPU error,
+3 BRK, 4 trap, 5 write, 6 cycle budget, 7 instruction budget, 8 illegal opcode.
+Invalid arguments exit with 2.
+
+Run rs-6502 asm --help to list the options of the assembler.";
+
+/// Printed by `asm --help`
+pub const ASM_HELP: &str = "\
+Usage: rs-6502 asm [OPTIONS] <source>
+
+Assembles a source file into an image the emulator can load.
+
+Options:
+  -o, --output <file>   File to write, the source with a .bin extension by default
+  --format <format>     Format to write: bin, hex, srec or prg. Detected from the
+                        extension of the output by default
+  --variant <variant>   CPU whose instruction set is assembled: nmos, 65c02, r65c02
+                        or 2a03 (default nmos)
+  -h, --help            Prints this help
+
+Binaries hold the bytes from the lowest to the highest address assembled, load them
+at that address. The other formats record the addresses.";
+
+/// Where execution starts
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Entry {
+    /// The reset vector is pointed at the address
+    Reset(u16),
+    /// The PC is set to the address after the reset sequence
+    Pc(u16),
+}
+
+/// Everything the command line asks for
+#[derive(Clone, PartialEq, Debug)]
+pub struct Options {
+    pub images: Vec<LoadSpec>,
+    pub variant: CpuVariant,
+    pub entry: Option<Entry>,
+    pub rom_writes: RomWritePolicy,
+    /// The breakpoints, and the conditions a headless run stops on
+    pub stop: StopConditions,
+    pub headless: bool,
+    pub trace: Option<PathBuf>,
+}
+
+/// What `asm` is asked to assemble
+#[derive(Clone, PartialEq, Debug)]
+pub struct AsmOptions {
+    pub source: PathBuf,
+    pub output: PathBuf,
+    pub format: ImageFormat,
+    pub variant: CpuVariant,
+}
+
+/// What the command line asks the binary to do
+pub enum Command {
+    Run(Options),
+    Assemble(AsmOptions),
+    Help,
+    AsmHelp,
+}
+
+/// Parses the arguments following the program name
+///
+/// ### Parameters:
+/// * `args` - The arguments, without the program name
+///
+/// ### Returns:
+/// * The command to run, or `Err(String)` describing the first invalid argument
+pub fn parse<I: Iterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut args = args.peekable();
+    if args.next_if(|arg| arg == "asm").is_some() {
+        return parse_asm(args);
+    }
+
+    let mut images = Vec::new();
+    let mut rom = None;
+    let mut variant = None;
+    // Applies to the images that follow it, the others are detected
+    let mut format = None;
+    let mut options = Options {
+        images: Vec::new(),
+        variant: CpuVariant::Nmos6502,
+        entry: None,
+        rom_writes: RomWritePolicy::default(),
+        stop: StopConditions::default(),
+        headless: false,
+        trace: None,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("{} needs an argument", arg))
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--load" => images.push(LoadSpec {
+                format,
+                ..value()?.parse()?
+            }),
+            "--format" => format = Some(value()?.parse::<ImageFormat>()?),
+            "--variant" => variant = Some(value()?.parse()?),
+            "--reset" => options.entry = Some(Entry::Reset(parse_addr(&value()?)?)),
+            "--pc" => options.entry = Some(Entry::Pc(parse_addr(&value()?)?)),
+            "--break" => options.stop.breakpoints.push(parse_addr(&value()?)?),
+            "--rom-writes" => options.rom_writes = value()?.parse()?,
+            "--headless" => options.headless = true,
+            "--max-cycles" => options.stop.max_cycles = Some(parse_count(&value()?)?),
+            "--max-instructions" => options.stop.max_instructions = Some(parse_count(&value()?)?),
+            "--stop-on-brk" => options.stop.brk = true,
+            "--stop-on-trap" => options.stop.trap = true,
+            "--stop-on-write" => options.stop.magic_write = Some(parse_addr(&value()?)?),
+            "--stop-on-illegal" => options.stop.illegal = true,
+            "--trace" => options.trace = Some(PathBuf::from(value()?)),
+            _ if arg.starts_with('-') => return Err(format!("Unknown option: {}", arg)),
+            // A plain ROM path is loaded before the other images
+            _ if rom.is_none() => {
+                rom = Some(LoadSpec {
+                    path: PathBuf::from(arg),
+                    addr: None,
+                    format,
+                    rom: false,
+                    vectors: Vec::new(),
+                })
+            }
+            _ if variant.is_none() => variant = Some(arg.parse()?),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+
+    options.images = rom.into_iter().chain(images).collect();
+    if options.images.is_empty() {
+        return Err(String::from("No ROM file specified"));
+    }
+    options.variant = variant.unwrap_or(options.variant);
+
+    Ok(Command::Run(options))
+}
+
+/// Parses the arguments following `asm`
+fn parse_asm<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+    let mut source = None;
+    let mut output = None;
+    let mut format = None;
+    let mut variant = CpuVariant::Nmos6502;
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("{} needs an argument", arg))
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::AsmHelp),
+            "-o" | "--output" => output = Some(PathBuf::from(value()?)),
+            "--format" => format = Some(value()?.parse::<ImageFormat>()?),
+            "--variant" => variant = value()?.parse()?,
+            _ if arg.starts_with('-') => return Err(format!("Unknown option: {}", arg)),
+            _ if source.is_none() => source = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+
+    let source: PathBuf = source.ok_or_else(|| String::from("No source file specified"))?;
+    let output = output.unwrap_or_else(|| source.with_extension("bin"));
+    let format = format.unwrap_or_else(|| ImageFormat::detect(&output, &[]));
+
+    Ok(Command::Assemble(AsmOptions {
+        source,
+        output,
+        format,
+        variant,
+    }))
+}
+
+fn parse_count(count: &str) -> Result<u64, String> {
+    count
+        .parse()
+        .map_err(|_| format!("Invalid count: {}", count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rs_6502::cpu::{IRQ_VECTOR, NMI_VECTOR, RESET_VECTOR};
+
+    fn parse_args(args: &str) -> Result<Command, String> {
+        parse(args.split_whitespace().map(String::from))
+    }
+
+    fn run(args: &str) -> Options {
+        match parse_args(args) {
+            Ok(Command::Run(options)) => options,
+            Ok(_) => panic!("{} doesn't run an image", args),
+            Err(error) => panic!("{} was rejected: {}", args, error),
+        }
+    }
+
+    fn error(args: &str) -> String {
+        match parse_args(args) {
+            Err(error) => error,
+            Ok(_) => panic!("{} was accepted", args),
+        }
+    }
+
+    #[test]
+    fn load_specs() {
+        let options = run("rom.bin --load a.bin@C000,rom,reset --format hex --load b.hex,irq,nmi");
+
+        assert_eq!(
+            options.images,
+            [
+                LoadSpec {
+                    path: PathBuf::from("rom.bin"),
+                    addr: None,
+                    format: None,
+                    rom: false,
+                    vectors: Vec::new(),
+                },
+                LoadSpec {
+                    path: PathBuf::from("a.bin"),
+                    addr: Some(0xC000),
+                    format: None,
+                    rom: true,
+                    vectors: vec![RESET_VECTOR],
+                },
+                LoadSpec {
+                    path: PathBuf::from("b.hex"),
+                    addr: None,
+                    format: Some(ImageFormat::IntelHex),
+                    rom: false,
+                    vectors: vec![IRQ_VECTOR, NMI_VECTOR],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn run_options() {
+        let options = run(
+            "rom.bin --pc $0400 --variant 65c02 --break 0x1234 --break F000 \
+             --max-cycles 1000 --rom-writes log --trace trace.log",
+        );
+
+        assert_eq!(options.entry, Some(Entry::Pc(0x0400)));
+        assert_eq!(options.variant, CpuVariant::Wdc65C02);
+        assert_eq!(options.stop.breakpoints, [0x1234, 0xF000]);
+        assert_eq!(options.stop.max_cycles, Some(1000));
+        assert_eq!(options.rom_writes, RomWritePolicy::Log);
+        assert_eq!(options.trace, Some(PathBuf::from("trace.log")));
+        assert!(!options.headless);
+
+        // The variant may also follow the ROM, and the last entry wins
+        let options = run("rom.bin r65c02 --pc 0400 --reset $E000");
+        assert_eq!(options.entry, Some(Entry::Reset(0xE000)));
+        assert_eq!(options.variant, CpuVariant::Rockwell65C02);
+        assert_eq!(options.rom_writes, RomWritePolicy::default());
+    }
+
+    #[test]
+    fn help_and_asm() {
+        assert!(matches!(parse_args("--help"), Ok(Command::Help)));
+        assert!(matches!(parse_args("rom.bin -h"), Ok(Command::Help)));
+        assert!(matches!(parse_args("asm --help"), Ok(Command::AsmHelp)));
+
+        let Ok(Command::Assemble(asm)) = parse_args("asm prog.s -o prog.hex --variant 65c02")
+        else {
+            panic!("asm was rejected");
+        };
+        assert_eq!(
+            asm,
+            AsmOptions {
+                source: PathBuf::from("prog.s"),
+                output: PathBuf::from("prog.hex"),
+                format: ImageFormat::IntelHex,
+                variant: CpuVariant::Wdc65C02,
+            }
+        );
+
+        let Ok(Command::Assemble(asm)) = parse_args("asm prog.s") else {
+            panic!("asm was rejected");
+        };
+        assert_eq!(asm.output, PathBuf::from("prog.bin"));
+        assert_eq!(asm.format, ImageFormat::Binary);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("rom.bin --x"), "Unknown option: --x");
+        assert_eq!(error("rom.bin --break"), "--break needs an argument");
+        assert_eq!(error("rom.bin --break 12345"), "Invalid address: 12345");
+        assert_eq!(error("rom.bin --max-cycles many"), "Invalid count: many");
+        assert_eq!(error("rom.bin --variant z80"), "Unknown CPU variant: z80");
+        assert_eq!(
+            error("rom.bin --rom-writes panic"),
+            "Unknown ROM write policy: panic"
+        );
+        assert_eq!(
+            error("rom.bin --load a.bin,ram"),
+            "Unknown load option: ram"
+        );
+        assert_eq!(error("rom.bin 6502 extra"), "Unexpected argument: extra");
+        assert_eq!(error("--headless"), "No ROM file specified");
+        assert_eq!(error("asm"), "No source file specified");
+        assert_eq!(error("asm prog.s -o"), "-o needs an argument");
+    }
+}
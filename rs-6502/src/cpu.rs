@@ -1,16 +1,125 @@
+use std::{fs, path::Path};
+
 use crate::{
-    addressing_mode::{get_addr_mode, AddrMode},
+    addressing_mode::{get_branch_target, get_indexed_addr, get_pointer_high_addr, AddrMode},
+    bus::{Bus, Inspect, RomWrite},
+    error::CpuError,
     memory::Memory,
-    stack::Stack,
+    opcode::{opcode_info, Access, Operation},
+    save_state::{StateError, StateReader, StateWriter},
+    stack::{Stack, STACK_PAGE},
     status_register::StatusRegister,
+    variant::CpuVariant,
 };
 
 const MASK_MSB: u8 = 0b10000000;
 const MASK_SIXTH_BIT: u8 = 0b01000000;
 const MASK_LSB: u8 = 0b00000001;
+/// Bits of A that leak into the result of the unstable ANE and LXA opcodes
+const ANE_MAGIC: u8 = 0xEE;
+
+/// Address of the non-maskable interrupt vector
+pub const NMI_VECTOR: u16 = 0xFFFA;
+/// Address of the reset vector
+pub const RESET_VECTOR: u16 = 0xFFFC;
+/// Address of the IRQ/BRK vector
+pub const IRQ_VECTOR: u16 = 0xFFFE;
+
+/// Summary of an instruction or interrupt sequence run by [`Cpu::execute`]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct StepInfo {
+    /// Address the opcode was fetched from
+    pub pc: u16,
+    /// Opcode executed, interrupt sequences report the BRK opcode they are forced into
+    pub opcode: u8,
+    /// Number of clock cycles used
+    pub cycles: u8,
+    /// Whether indexing or a taken branch crossed a page, see
+    /// [`OpcodeInfo::page_cross_penalty`](crate::opcode::OpcodeInfo::page_cross_penalty)
+    /// for the instructions that pay a cycle for it
+    pub page_crossed: bool,
+}
+
+/// Interrupt sequences, all of them run through the same microcode as BRK
+#[derive(Clone, Copy, PartialEq)]
+enum Interrupt {
+    Reset,
+    Nmi,
+    Irq,
+}
+
+/// Progress of the instruction currently going through the cycle-stepped core
+#[derive(Clone)]
+struct InFlight {
+    /// Cycle of the instruction the next tick will run, 0 fetches a new opcode
+    cycle: u8,
+    /// Address the opcode was fetched from
+    opcode_addr: u16,
+    opcode: u8,
+    operation: Operation,
+    addr_mode: AddrMode,
+    /// Interrupt being serviced through the BRK sequence, if any
+    interrupt: Option<Interrupt>,
+    /// Address before indexing, its high byte is used while the carry is still pending
+    base: u16,
+    /// Effective address
+    addr: u16,
+    /// Zero page pointer used by the indirect addressing modes
+    ptr: u8,
+    /// Operand latched by multi-cycle instructions
+    data: u8,
+    /// Whether indexing carried into the high byte of the address, or a taken branch
+    /// landed on another page
+    page_crossed: bool,
+    /// Set by a 65C02 decimal ADC or SBC, which needs one more cycle to finish
+    decimal_fixup: bool,
+}
+
+impl InFlight {
+    fn new() -> InFlight {
+        InFlight {
+            cycle: 0,
+            opcode_addr: 0,
+            opcode: 0xEA,
+            operation: Operation::Nop,
+            addr_mode: AddrMode::Impl,
+            interrupt: None,
+            base: 0,
+            addr: 0,
+            ptr: 0,
+            data: 0,
+            page_crossed: false,
+            decimal_fixup: false,
+        }
+    }
+}
+
+/// Everything inside the CPU but the bus, kept by [`History`](crate::history::History)
+/// to rewind execution
+#[derive(Clone)]
+pub(crate) struct CoreState {
+    a: u8,
+    x: u8,
+    y: u8,
+    pc: u16,
+    sp: u8,
+    status: u8,
+    cycles: u64,
+    irq_line: bool,
+    nmi_line: bool,
+    bus_nmi: bool,
+    nmi_pending: bool,
+    requested_interrupt: Option<Interrupt>,
+    jammed: bool,
+    waiting: bool,
+    state: InFlight,
+}
 
 /// Represents the current CPU state
-pub struct Cpu {
+///
+/// The CPU is generic over the [`Bus`] it is attached to, defaulting to a flat 64KB
+/// [`Memory`]
+pub struct Cpu<B: Bus = Memory> {
     /// Accumulator register - 8 bits
     pub a: u8,
     /// X register - 8 bits
@@ -19,9 +128,9 @@ pub struct Cpu {
     pub y: u8,
     /// Program counter - 16 bits
     pub pc: u16,
-    /// Memory - 64KB
-    pub memory: Memory,
-    /// Stack - 256 bytes
+    /// Address bus - 64KB address space
+    pub bus: B,
+    /// Stack pointer, the stack itself lives in page $01 of the bus
     pub stack: Stack,
     /// Status register - 8 bits
     /// * Bit 0: Carry
@@ -33,1363 +142,2491 @@ pub struct Cpu {
     /// * Bit 6: Overflow
     /// * Bit 7: Negative
     pub sr: StatusRegister,
+    /// Number of clock cycles run since the CPU was created
+    pub cycles: u64,
+    /// Level of the IRQ input pin, serviced while asserted and interrupts are enabled
+    irq_line: bool,
+    /// Level of the NMI input pin, only its rising edge triggers an interrupt
+    nmi_line: bool,
+    /// Level of the NMI line driven by the devices on the bus when last polled
+    bus_nmi: bool,
+    /// Set when an NMI edge was detected and has not been serviced yet
+    nmi_pending: bool,
+    /// Interrupt requested through [`Cpu::reset`]
+    requested_interrupt: Option<Interrupt>,
+    /// Member of the 6502 family being emulated
+    variant: CpuVariant,
+    /// Set by JAM and STP, the CPU stops executing until it is reset
+    jammed: bool,
+    /// Set by WAI, the CPU idles until an interrupt line is asserted
+    waiting: bool,
+    /// Reports undocumented opcodes and invalid BCD operands as errors
+    strict: bool,
+    /// Error raised during the current cycle, returned by [`Cpu::tick`]
+    fault: Option<CpuError>,
+    /// Asks the bus for a write to ROM after every cycle, see [`Cpu::poll_rom_faults`]
+    rom_fault_probe: Option<fn(&mut B) -> Option<RomWrite>>,
+    /// Instruction currently being executed by [`Cpu::tick`]
+    state: InFlight,
 }
 
 impl Cpu {
+    /// Creates an NMOS 6502 attached to 64KB of RAM
     pub fn new() -> Cpu {
+        Cpu::with_bus(Memory::new())
+    }
+}
+
+impl Default for Cpu {
+    fn default() -> Cpu {
+        Cpu::new()
+    }
+}
+
+impl<B: Bus> Cpu<B> {
+    /// Creates an NMOS 6502 attached to the given bus
+    ///
+    /// ### Parameters:
+    /// * `bus` - The bus every memory access will go through
+    pub fn with_bus(bus: B) -> Cpu<B> {
+        Cpu::with_variant(bus, CpuVariant::Nmos6502)
+    }
+
+    /// Creates a CPU of the given variant attached to the given bus
+    ///
+    /// ### Parameters:
+    /// * `bus` - The bus every memory access will go through
+    /// * `variant` - The instruction set and quirks to emulate
+    pub fn with_variant(bus: B, variant: CpuVariant) -> Cpu<B> {
         Cpu {
             a: 0,
             x: 0,
             y: 0,
             pc: 0,
-            memory: Memory::new(),
+            bus,
             stack: Stack::new(),
             sr: StatusRegister::new(),
+            cycles: 0,
+            irq_line: false,
+            nmi_line: false,
+            bus_nmi: false,
+            nmi_pending: false,
+            requested_interrupt: None,
+            variant,
+            jammed: false,
+            waiting: false,
+            strict: false,
+            fault: None,
+            rom_fault_probe: None,
+            state: InFlight::new(),
         }
     }
 
-    fn fetch_byte(&mut self) -> u8 {
-        let instruction = self.memory.read_byte(self.pc);
-        self.pc += 1;
-        instruction
-    }
-
-    fn fetch_word(&mut self) -> u16 {
-        let instruction = self.memory.read_word(self.pc);
-        self.pc += 2;
-        instruction
-    }
-
-    fn set_zero_and_negative_flags(&mut self, data: u8) {
-        self.sr.zero = data == 0;
-        self.sr.negative = (data & MASK_MSB) != 0;
-    }
-
-    fn get_zero_page_addr(&mut self) -> u16 {
-        self.fetch_byte() as u16
-    }
+    /// Runs the reset sequence: the stack pointer is decremented by three without
+    /// writing, interrupts are disabled and the PC is loaded from the reset vector.
+    /// Any instruction in flight is abandoned and a halted CPU starts running again.
+    ///
+    /// ### Returns:
+    /// * The number of cycles taken by the reset sequence
+    pub fn reset(&mut self) -> u8 {
+        self.state.cycle = 0;
+        self.state.decimal_fixup = false;
+        self.waiting = false;
+        self.nmi_pending = false;
+        self.requested_interrupt = Some(Interrupt::Reset);
+
+        // The reset sequence performs no ALU work, so it cannot raise an error
+        let mut cycles = 0;
+        loop {
+            self.run_cycle();
+            cycles += 1;
 
-    fn get_zero_page_x_addr(&mut self) -> u16 {
-        (self.fetch_byte() as u16 + self.x as u16) & 0x00FF
+            if self.at_instruction_boundary() {
+                return cycles;
+            }
+        }
     }
 
-    fn get_zero_page_y_addr(&mut self) -> u16 {
-        (self.fetch_byte() as u16 + self.y as u16) & 0x00FF
+    /// Enables or disables strict mode, where undocumented opcodes and decimal
+    /// arithmetic on invalid BCD operands are reported as errors instead of being
+    /// emulated
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
     }
 
-    fn get_absolute_addr(&mut self) -> u16 {
-        self.fetch_word()
+    /// Drives the level-triggered IRQ pin, the interrupt is taken before the next
+    /// instruction for as long as the line stays asserted and I is clear
+    pub fn set_irq_line(&mut self, asserted: bool) {
+        self.irq_line = asserted;
     }
 
-    fn get_absolute_x_addr(&mut self) -> u16 {
-        self.fetch_word() + self.x as u16
+    /// Returns the level of the IRQ pin
+    pub fn irq_line(&self) -> bool {
+        self.irq_line
     }
 
-    fn get_absolute_y_addr(&mut self) -> u16 {
-        self.fetch_word() + self.y as u16
-    }
+    /// Drives the edge-triggered NMI pin, an interrupt is latched on every transition
+    /// from released to asserted
+    pub fn set_nmi_line(&mut self, asserted: bool) {
+        if asserted && !self.nmi_line {
+            self.nmi_pending = true;
+        }
 
-    fn get_indirect_addr(&mut self) -> u16 {
-        let addr_addr = self.fetch_byte() as u16;
-        self.memory.read_word(addr_addr)
+        self.nmi_line = asserted;
     }
 
-    fn get_indirect_x_addr(&mut self) -> u16 {
-        let addr_addr = (self.fetch_byte() as u16 + self.x as u16) & 0x00FF;
-        self.memory.read_word(addr_addr)
-    }
+    /// Runs cycles until the current instruction or interrupt sequence is finished
+    ///
+    /// ### Returns:
+    /// * Where the instruction was fetched from and the number of cycles used
+    /// * `Err(CpuError)` if the instruction could not be executed. The machine state is
+    ///   left as it was when the error was raised, so it can be inspected.
+    pub fn execute(&mut self) -> Result<StepInfo, CpuError> {
+        if self.jammed && self.requested_interrupt != Some(Interrupt::Reset) {
+            return Err(CpuError::Jammed {
+                pc: self.state.opcode_addr,
+            });
+        }
 
-    fn get_indirect_y_addr(&mut self) -> u16 {
-        let addr_addr = self.fetch_byte() as u16;
-        self.memory.read_word(addr_addr) + self.y as u16
-    }
+        let mut cycles = 0;
 
-    pub fn execute(&mut self) -> u8 {
-        let opcode = self.fetch_byte();
-        let addr_mode = get_addr_mode(opcode);
-
-        match opcode {
-            //BRK
-            0x00 => self.break_interrupt(),
-            // CLC
-            0x18 => self.clear_carry(),
-            // CLD
-            0xD8 => self.clear_decimal(),
-            // CLI
-            0x58 => self.clear_interrupt_dis(),
-            // CLV
-            0xB8 => self.clear_overflow(),
-            // NOP
-            0xEA => 2,
-            // INY
-            0xC8 => self.inc_y(),
-            // INX
-            0xE8 => self.inc_x(),
-            // LDA immediate / zpg / zpg, x / abs / abs, x / abs, y / ind, x / ind, y
-            0xA9 | 0xA5 | 0xB5 | 0xAD | 0xBD | 0xB9 | 0xA1 | 0xB1 => self.lda(addr_mode),
-            // LDX immediate / zpg / zpg, y / abs / abs, y
-            0xA2 | 0xA6 | 0xB6 | 0xAE | 0xBE => self.ldx(addr_mode),
-            // LDY immediate / zpg / zpg, x / abs / abs, x
-            0xA0 | 0xA4 | 0xB4 | 0xAC | 0xBC => self.ldy(addr_mode),
-            // LSR accumulator
-            0x4A => self.lsr_accumulator(),
-            // LSR zpg / zpg, x / abs / abs, x
-            0x46 | 0x56 | 0x4E | 0x5E => self.lsr(addr_mode),
-            // ORA immediate / zpg / abs / zpg, x / abs, x / (ind, x) / (ind), y
-            0x09 | 0x05 | 0x15 | 0x0D | 0x1D | 0x01 | 0x11 => self.ora(addr_mode),
-            // PHA
-            0x48 => self.push_accumulator(),
-            // PHP
-            0x08 => self.push_processor_status(),
-            // PLA
-            0x68 => self.pull_accumulator(),
-            // PLP
-            0x28 => self.pull_processor_status(),
-            // ROL accumulator
-            0x2A => self.rol_accumulator(),
-            // ROL zpg / zpg, x / abs / abs, x
-            0x26 | 0x36 | 0x2E | 0x3E => self.rol(addr_mode),
-            // ASL accumulator
-            0x0A => self.asl_acc(),
-            // ASL zpg / zpg, x / abs / abs, x
-            0x06 | 0x16 | 0x0E | 0x1E => self.asl(addr_mode),
-            // STA zpg / zpg, x / abs / abs, x / abs, y / (ind, x) / (ind), y
-            0x85 | 0x95 | 0x8D | 0x9D | 0x99 | 0x81 | 0x91 => self.sta(addr_mode),
-            // STX zpg / zpg, y / abs
-            0x86 | 0x96 | 0x8E => self.stx(addr_mode),
-            // STY zpg / zpg, x / abs
-            0x84 | 0x94 | 0x8C => self.sty(addr_mode),
-            // TAX
-            0xAA => self.tax(),
-            // TAY
-            0xA8 => self.tay(),
-            // TSX
-            0xBA => self.tsx(),
-            // TXA
-            0x8A => self.txa(),
-            // TXS
-            0x9A => self.txs(),
-            // TYA
-            0x98 => self.tya(),
-            // ADC immediate / zpg / zpg, x / abs / abs, x / abs, y / (ind, x) / (ind), y
-            0x69 | 0x65 | 0x75 | 0x6D | 0x7D | 0x79 | 0x61 | 0x71 => self.adc(addr_mode),
-            // AND immediate / zpg / zpg, x / abs / abs, x / abs, y / (ind, x) / (ind), y
-            0x29 | 0x25 | 0x35 | 0x2D | 0x3D | 0x39 | 0x21 | 0x31 => self.and(addr_mode),
-            // BRANCH IFS
-            0x90 | 0xB0 | 0xF0 | 0x30 | 0xD0 | 0x10 | 0x50 | 0x70 => self.branch(opcode),
-            // BIT zpg / abs
-            0x24 | 0x2c => self.bit_test(addr_mode),
-            // JSR
-            0x20 => self.jsr(),
-            //JMP abs / ind
-            0x4C | 0x6C => self.jmp(addr_mode),
-            // RTS
-            0x60 => self.rts(),
-            // RTI
-            0x40 => self.rti(),
-            // CMP immediate / zpg / zpg, x / abs / abs, x / abs, y / (ind, x) / (ind), y
-            0xC9 | 0xC5 | 0xD5 | 0xCD | 0xDD | 0xD9 | 0xC1 | 0xD1 => self.cmp(addr_mode),
-            // CPX immediate / zpg / abs
-            0xE0 | 0xE4 | 0xEC => self.cpx(addr_mode),
-            // CPY immediate / zpg / abs
-            0xC0 | 0xC4 | 0xCC => self.cpy(addr_mode),
-            // DEC zpg / zpg, x / abs / abs, x
-            0xC6 | 0xD6 | 0xCE | 0xDE => self.dec(addr_mode),
-            // DEX
-            0xCA => self.dex(),
-            // DEY
-            0x88 => self.dey(),
-            // EOR immediate / zpg / zpg, x / abs / abs, x / abs, y / (ind, x) / (ind), y
-            0x49 | 0x45 | 0x55 | 0x4D | 0x5D | 0x59 | 0x41 | 0x51 => self.eor(addr_mode),
-            // INC zpg / zpg, x / abs / abs, x
-            0xE6 | 0xF6 | 0xEE | 0xFE => self.inc(addr_mode),
-            // ROR accumulator
-            0x6A => self.ror_accumulator(),
-            // ROR zpg / zpg, x / abs / abs, x
-            0x66 | 0x76 | 0x6E | 0x7E => self.ror(addr_mode),
-            // SBC immediate / zpg / zpg, x / abs / abs, x / abs, y / (ind, x) / (ind), y
-            0xE9 | 0xE5 | 0xF5 | 0xED | 0xFD | 0xF9 | 0xE1 | 0xF1 => self.sbc(addr_mode),
-            // SEC
-            0x38 => self.sec(),
-            // SED
-            0xF8 => self.sed(),
-            // SEI
-            0x78 => self.sei(),
-            // Illegal instruction
-            _ => panic!("Instruction not implemented: {:#04X}", opcode),
-        }
-    }
-
-    fn break_interrupt(&mut self) -> u8 {
-        self.sr.brk = true;
-
-        self.stack.push_word(self.pc + 1);
-        self.stack.push_byte(self.sr.get_status_byte());
-
-        self.pc = self.memory.read_word(0xFFFE);
-
-        7
-    }
-
-    fn branch(&mut self, opcode: u8) -> u8 {
-        let branch_offset = self.fetch_byte();
-        let old_pc: u16 = self.pc;
-        let branch_condition: bool;
-        let mut cycles = 2;
-
-        match opcode {
-            0x90 => branch_condition = self.sr.carry == false,
-            0xB0 => branch_condition = self.sr.carry == true,
-            0xF0 => branch_condition = self.sr.zero == true,
-            0xD0 => branch_condition = self.sr.zero == false,
-            0x30 => branch_condition = self.sr.negative == true,
-            0x10 => branch_condition = self.sr.negative == false,
-            0x50 => branch_condition = self.sr.overflow == false,
-            0x70 => branch_condition = self.sr.overflow == true,
-            _ => panic!("Invalid branching mode!"),
-        }
-
-        if branch_condition {
-            self.pc += branch_offset as u16;
+        loop {
+            self.tick()?;
             cycles += 1;
 
-            if ((old_pc >> 8) - (self.pc >> 8)) != 0 {
-                cycles += 2;
+            if self.at_instruction_boundary() {
+                break;
             }
         }
 
-        cycles
-    }
-
-    fn bit_test(&mut self, addr_mode: AddrMode) -> u8 {
-        let cycles: u8;
-        let data: u8;
-        let data_addr: u16;
-
-        match addr_mode {
-            AddrMode::ZeroPage => {
-                data_addr = self.get_zero_page_addr();
-                cycles = 3;
-            }
-            AddrMode::Abs => {
-                data_addr = self.get_absolute_addr();
-                cycles = 4;
-            }
-            _ => panic!("Illegal opcode!"),
+        if self.jammed {
+            return Err(CpuError::Jammed {
+                pc: self.state.opcode_addr,
+            });
         }
 
-        data = self.memory.read_byte(data_addr);
+        Ok(StepInfo {
+            pc: self.state.opcode_addr,
+            opcode: self.state.opcode,
+            cycles,
+            page_crossed: self.state.page_crossed,
+        })
+    }
+
+    /// Advances the CPU by exactly one clock cycle, performing the bus read or write the
+    /// real chip does on that cycle, dummy accesses included
+    ///
+    /// ### Returns:
+    /// * `Err(CpuError)` if the cycle raised an error, see [`Cpu::execute`]
+    pub fn tick(&mut self) -> Result<(), CpuError> {
+        self.run_cycle();
+
+        match self.fault.take() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
 
-        self.sr.negative = (data & MASK_MSB) != 0;
-        self.sr.overflow = (data & MASK_SIXTH_BIT) != 0;
+    fn run_cycle(&mut self) {
+        let cycle = self.state.cycle;
+
+        let done = if self.state.decimal_fixup {
+            // The extra cycle of a 65C02 decimal ADC/SBC rereads the next opcode
+            self.state.decimal_fixup = false;
+            self.bus.read_byte(self.pc);
+            true
+        } else if cycle == 0 {
+            self.fetch_cycle()
+        } else {
+            self.instruction_cycle(cycle)
+        };
+        let done = done && !self.state.decimal_fixup;
+
+        // Cycles may skip a step of their sequence by advancing the counter themselves
+        self.state.cycle = if done { 0 } else { self.state.cycle + 1 };
+        self.cycles += 1;
+
+        // Devices run alongside the CPU, an NMI is latched on the edge of their line
+        self.bus.tick(1);
+        let bus_nmi = self.bus.nmi();
+        if bus_nmi && !self.bus_nmi {
+            self.nmi_pending = true;
+        }
+        self.bus_nmi = bus_nmi;
+
+        if let Some(write) = self.rom_fault_probe.and_then(|probe| probe(&mut self.bus)) {
+            self.fault.get_or_insert(CpuError::RomWrite {
+                pc: self.state.opcode_addr,
+                addr: write.addr,
+                data: write.data,
+            });
+        }
+    }
 
-        self.sr.zero = (data & self.a) == 0;
+    /// Returns whether the IRQ pin or a device on the bus requests an interrupt
+    fn irq_asserted(&self) -> bool {
+        self.irq_line || self.bus.irq()
+    }
 
-        cycles
+    /// Returns whether the next tick will fetch a new opcode
+    pub fn at_instruction_boundary(&self) -> bool {
+        self.state.cycle == 0
     }
 
-    fn clear_carry(&mut self) -> u8 {
-        self.sr.carry = false;
+    /// Returns the member of the 6502 family being emulated
+    pub fn variant(&self) -> CpuVariant {
+        self.variant
+    }
 
-        2
+    /// Captures everything but the bus, see [`Cpu::set_core_state`]
+    pub(crate) fn core_state(&self) -> CoreState {
+        CoreState {
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            pc: self.pc,
+            sp: self.stack.sp,
+            status: self.sr.get_status_byte(),
+            cycles: self.cycles,
+            irq_line: self.irq_line,
+            nmi_line: self.nmi_line,
+            bus_nmi: self.bus_nmi,
+            nmi_pending: self.nmi_pending,
+            requested_interrupt: self.requested_interrupt,
+            jammed: self.jammed,
+            waiting: self.waiting,
+            state: self.state.clone(),
+        }
     }
 
-    fn clear_decimal(&mut self) -> u8 {
-        self.sr.decimal = false;
+    /// Puts the CPU back in a state captured by [`Cpu::core_state`]
+    pub(crate) fn set_core_state(&mut self, core: CoreState) {
+        self.a = core.a;
+        self.x = core.x;
+        self.y = core.y;
+        self.pc = core.pc;
+        self.stack.sp = core.sp;
+        self.sr.set_status_byte(core.status);
+        self.cycles = core.cycles;
+        self.irq_line = core.irq_line;
+        self.nmi_line = core.nmi_line;
+        self.bus_nmi = core.bus_nmi;
+        self.nmi_pending = core.nmi_pending;
+        self.requested_interrupt = core.requested_interrupt;
+        self.jammed = core.jammed;
+        self.waiting = core.waiting;
+        self.fault = None;
+        self.state = core.state;
+    }
 
-        2
+    /// Whether ADC and SBC work in BCD, the 2A03 ignores the D flag
+    fn decimal_mode(&self) -> bool {
+        self.sr.decimal && self.variant.has_decimal_mode()
     }
 
-    fn clear_interrupt_dis(&mut self) -> u8 {
-        self.sr.interrupt_disable = false;
+    fn fetch_byte(&mut self) -> u8 {
+        let instruction = self.bus.read_byte(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        instruction
+    }
 
-        2
+    fn set_zero_and_negative_flags(&mut self, data: u8) {
+        self.sr.zero = data == 0;
+        self.sr.negative = (data & MASK_MSB) != 0;
     }
 
-    fn clear_overflow(&mut self) -> u8 {
-        self.sr.overflow = false;
+    /// First cycle of every instruction: fetches the opcode, or starts an interrupt
+    /// sequence if one is pending
+    fn fetch_cycle(&mut self) -> bool {
+        self.state.page_crossed = false;
+
+        let interrupt = if let Some(interrupt) = self.requested_interrupt.take() {
+            Some(interrupt)
+        } else if self.nmi_pending {
+            self.nmi_pending = false;
+            Some(Interrupt::Nmi)
+        } else if self.irq_asserted() && !self.sr.interrupt_disable {
+            Some(Interrupt::Irq)
+        } else {
+            None
+        };
+
+        if self.jammed && interrupt != Some(Interrupt::Reset) {
+            // A jammed CPU keeps the address bus at $FFFF and ignores IRQ and NMI
+            self.bus.read_byte(0xFFFF);
+            return true;
+        }
 
-        2
-    }
+        if self.waiting {
+            // WAI resumes on any interrupt line, even an IRQ masked by the I flag
+            if interrupt.is_none() && !self.irq_asserted() {
+                return true;
+            }
+            self.waiting = false;
+        }
 
-    fn inc_y(&mut self) -> u8 {
-        self.y = self.y.wrapping_add(1);
+        if let Some(interrupt) = interrupt {
+            // The opcode fetch still happens but is discarded, BRK is forced instead
+            self.bus.read_byte(self.pc);
+            self.state.opcode_addr = self.pc;
+            self.state.opcode = 0x00;
+            self.state.operation = Operation::Brk;
+            self.state.addr_mode = AddrMode::Impl;
+            self.state.interrupt = Some(interrupt);
+            self.jammed = false;
+
+            return false;
+        }
 
-        self.set_zero_and_negative_flags(self.y);
+        let opcode_addr = self.pc;
+        let opcode = self.fetch_byte();
+        let info = opcode_info(opcode, self.variant);
+
+        if self.strict && !info.documented {
+            // The instruction never starts, the PC stays on the opcode
+            self.pc = opcode_addr;
+            self.fault = Some(CpuError::IllegalOpcode {
+                pc: opcode_addr,
+                opcode,
+            });
+            return true;
+        }
 
-        2
+        self.state.opcode_addr = opcode_addr;
+        self.state.opcode = opcode;
+        self.state.operation = info.operation;
+        self.state.addr_mode = info.addr_mode;
+        self.state.interrupt = None;
+
+        // Single cycle opcodes, the unused 65C02 NOPs, finish within the fetch
+        info.cycles == 1
+    }
+
+    fn instruction_cycle(&mut self, cycle: u8) -> bool {
+        match self.state.operation {
+            Operation::Brk => self.break_cycle(cycle),
+            Operation::Jsr => self.jsr_cycle(cycle),
+            Operation::Rts => self.rts_cycle(cycle),
+            Operation::Rti => self.rti_cycle(cycle),
+            Operation::Jmp => self.jmp_cycle(cycle),
+            Operation::Jam => self.jam_cycle(),
+            Operation::Wai | Operation::Stp => self.halt_cycle(cycle),
+            Operation::Nop if opcode_info(self.state.opcode, self.variant).cycles == 8 => {
+                self.long_nop_cycle(cycle)
+            }
+            Operation::Pha | Operation::Php | Operation::Phx | Operation::Phy => {
+                self.push_cycle(cycle)
+            }
+            Operation::Pla | Operation::Plp | Operation::Plx | Operation::Ply => {
+                self.pull_cycle(cycle)
+            }
+            Operation::Bbr(_) | Operation::Bbs(_) => self.bit_branch_cycle(cycle),
+            Operation::Bra
+            | Operation::Bcc
+            | Operation::Bcs
+            | Operation::Beq
+            | Operation::Bmi
+            | Operation::Bne
+            | Operation::Bpl
+            | Operation::Bvc
+            | Operation::Bvs => self.branch_cycle(cycle),
+            _ => self.addressing_cycle(cycle),
+        }
     }
 
-    fn inc_x(&mut self) -> u8 {
-        self.x = self.x.wrapping_add(1);
+    /// Resolves the effective address one bus access per cycle, then hands over to
+    /// [`Cpu::access_cycle`]
+    fn addressing_cycle(&mut self, cycle: u8) -> bool {
+        match self.state.addr_mode {
+            AddrMode::Impl => {
+                self.bus.read_byte(self.pc);
+                self.implied_operation();
+                true
+            }
+            AddrMode::Accumulator => {
+                self.bus.read_byte(self.pc);
+                self.a = self.modify_operation(self.a);
+                true
+            }
+            AddrMode::Immediate => {
+                let data = self.fetch_byte();
+                self.read_operation(data);
+                true
+            }
+            AddrMode::ZeroPage => match cycle {
+                1 => {
+                    self.state.addr = self.fetch_byte() as u16;
+                    false
+                }
+                _ => self.access_cycle(cycle - 2),
+            },
+            AddrMode::ZeroPageX | AddrMode::ZeroPageY => match cycle {
+                1 => {
+                    self.state.ptr = self.fetch_byte();
+                    false
+                }
+                2 => {
+                    // The unindexed zero page address is read while the index is added
+                    self.bus.read_byte(self.state.ptr as u16);
+                    let index = match self.state.addr_mode {
+                        AddrMode::ZeroPageX => self.x,
+                        _ => self.y,
+                    };
+                    self.state.addr = self.state.ptr.wrapping_add(index) as u16;
+                    false
+                }
+                _ => self.access_cycle(cycle - 3),
+            },
+            AddrMode::Abs => match cycle {
+                1 => {
+                    self.state.addr = self.fetch_byte() as u16;
+                    false
+                }
+                2 => {
+                    self.state.addr |= (self.fetch_byte() as u16) << 8;
+                    false
+                }
+                _ => self.access_cycle(cycle - 3),
+            },
+            AddrMode::AbsX | AddrMode::AbsY => match cycle {
+                1 => {
+                    self.state.base = self.fetch_byte() as u16;
+                    false
+                }
+                2 => {
+                    self.state.base |= (self.fetch_byte() as u16) << 8;
+                    let index = match self.state.addr_mode {
+                        AddrMode::AbsX => self.x,
+                        _ => self.y,
+                    };
+                    self.index_base(index);
+                    false
+                }
+                3 => self.indexed_cycle(),
+                _ => self.access_cycle(cycle - 4),
+            },
+            AddrMode::IndX => match cycle {
+                1 => {
+                    self.state.ptr = self.fetch_byte();
+                    false
+                }
+                2 => {
+                    self.bus.read_byte(self.state.ptr as u16);
+                    self.state.ptr = self.state.ptr.wrapping_add(self.x);
+                    false
+                }
+                3 => {
+                    self.state.addr = self.bus.read_byte(self.state.ptr as u16) as u16;
+                    false
+                }
+                4 => {
+                    let hi_addr = get_pointer_high_addr(self.state.ptr as u16, true);
+                    let hi = self.bus.read_byte(hi_addr);
+                    self.state.addr |= (hi as u16) << 8;
+                    false
+                }
+                _ => self.access_cycle(cycle - 5),
+            },
+            AddrMode::IndY => match cycle {
+                1 => {
+                    self.state.ptr = self.fetch_byte();
+                    false
+                }
+                2 => {
+                    self.state.base = self.bus.read_byte(self.state.ptr as u16) as u16;
+                    false
+                }
+                3 => {
+                    let hi_addr = get_pointer_high_addr(self.state.ptr as u16, true);
+                    let hi = self.bus.read_byte(hi_addr);
+                    self.state.base |= (hi as u16) << 8;
+                    self.index_base(self.y);
+                    false
+                }
+                4 => self.indexed_cycle(),
+                _ => self.access_cycle(cycle - 5),
+            },
+            AddrMode::ZeroPageInd => match cycle {
+                1 => {
+                    self.state.ptr = self.fetch_byte();
+                    false
+                }
+                2 => {
+                    self.state.addr = self.bus.read_byte(self.state.ptr as u16) as u16;
+                    false
+                }
+                3 => {
+                    let hi_addr = get_pointer_high_addr(self.state.ptr as u16, true);
+                    let hi = self.bus.read_byte(hi_addr);
+                    self.state.addr |= (hi as u16) << 8;
+                    false
+                }
+                _ => self.access_cycle(cycle - 4),
+            },
+            AddrMode::Ind | AddrMode::Rel | AddrMode::AbsIndX | AddrMode::ZeroPageRel => {
+                unreachable!("Addressing mode not supported")
+            }
+        }
+    }
 
-        self.set_zero_and_negative_flags(self.x);
+    fn index_base(&mut self, index: u8) {
+        (self.state.addr, self.state.page_crossed) = get_indexed_addr(self.state.base, index);
+    }
+
+    /// Cycle where an indexed address is read before the carry reaches its high byte.
+    /// Reads that did not cross a page finish here, every other access treats it as a
+    /// dummy read and repeats it on the fixed address.
+    ///
+    /// The 65C02 rereads the last operand byte instead of the invalid address, and its
+    /// shifts and rotates use this read as the real one when no page was crossed.
+    fn indexed_cycle(&mut self) -> bool {
+        let cmos = self.variant.is_cmos();
+
+        if !self.state.page_crossed {
+            let shift = matches!(
+                self.state.operation,
+                Operation::Asl | Operation::Lsr | Operation::Rol | Operation::Ror
+            );
+
+            match self.state.operation.access() {
+                Access::Read => {
+                    let data = self.bus.read_byte(self.state.addr);
+                    self.read_operation(data);
+                    return true;
+                }
+                Access::Modify if cmos && shift => {
+                    self.state.data = self.bus.read_byte(self.state.addr);
+                    self.state.cycle += 1;
+                    return false;
+                }
+                _ => {}
+            }
+        }
 
-        2
+        let dummy_addr = if cmos && self.state.page_crossed {
+            self.pc.wrapping_sub(1)
+        } else {
+            (self.state.base & 0xFF00) | (self.state.addr & 0x00FF)
+        };
+        self.bus.read_byte(dummy_addr);
+
+        false
+    }
+
+    /// Cycles spent on the effective address once it is known
+    fn access_cycle(&mut self, step: u8) -> bool {
+        let addr = self.state.addr;
+
+        match (self.state.operation.access(), step) {
+            (Access::Read, _) => {
+                let data = self.bus.read_byte(addr);
+                self.read_operation(data);
+                true
+            }
+            (Access::Write, _) => {
+                // SHA, SHX, SHY and TAS may redirect the write, so the address is reread
+                let data = self.write_operation();
+                self.bus.write_byte(self.state.addr, data);
+                true
+            }
+            (Access::Modify, 0) => {
+                self.state.data = self.bus.read_byte(addr);
+                false
+            }
+            (Access::Modify, 1) => {
+                // The unmodified value is written back while the ALU works on it, the
+                // 65C02 reads it a second time instead
+                if self.variant.is_cmos() {
+                    self.bus.read_byte(addr);
+                } else {
+                    self.bus.write_byte(addr, self.state.data);
+                }
+                self.state.data = self.modify_operation(self.state.data);
+                false
+            }
+            (Access::Modify, _) => {
+                self.bus.write_byte(addr, self.state.data);
+                true
+            }
+        }
     }
 
-    fn lda(&mut self, addr_mode: AddrMode) -> u8 {
-        let cycles: u8;
-        let data: u8;
+    /// Pushes a byte, or only performs the read and decrement during a reset, where the
+    /// write line is held inactive
+    fn interrupt_push(&mut self, data: u8) {
+        if self.state.interrupt == Some(Interrupt::Reset) {
+            self.bus.read_byte(STACK_PAGE | self.stack.sp as u16);
+            self.stack.sp = self.stack.sp.wrapping_sub(1);
+        } else {
+            self.stack.push_byte(&mut self.bus, data);
+        }
+    }
 
-        match addr_mode {
-            AddrMode::Immediate => {
-                data = self.fetch_byte();
-                cycles = 2;
+    /// BRK, IRQ, NMI and RESET sequences
+    fn break_cycle(&mut self, cycle: u8) -> bool {
+        let interrupt = self.state.interrupt;
+        let vector = match interrupt {
+            Some(Interrupt::Reset) => RESET_VECTOR,
+            Some(Interrupt::Nmi) => NMI_VECTOR,
+            Some(Interrupt::Irq) | None => IRQ_VECTOR,
+        };
+
+        match cycle {
+            1 => {
+                self.bus.read_byte(self.pc);
+
+                // BRK skips a padding byte, so the return address is the opcode address + 2
+                if interrupt.is_none() {
+                    self.pc = self.pc.wrapping_add(1);
+                }
+                false
+            }
+            2 => {
+                self.interrupt_push((self.pc >> 8) as u8);
+                false
+            }
+            3 => {
+                self.interrupt_push(self.pc as u8);
+                false
+            }
+            4 => {
+                let status = self.sr.get_pushed_status_byte(interrupt.is_none());
+                self.interrupt_push(status);
+                false
+            }
+            5 => {
+                self.state.data = self.bus.read_byte(vector);
+                self.sr.interrupt_disable = true;
+                self.sr.brk = interrupt.is_none();
+
+                // The 65C02 also leaves decimal mode when servicing any interrupt
+                if self.variant.is_cmos() {
+                    self.sr.decimal = false;
+                }
+                false
+            }
+            _ => {
+                let hi = self.bus.read_byte(vector.wrapping_add(1));
+                self.pc = ((hi as u16) << 8) | self.state.data as u16;
+                true
             }
-            AddrMode::ZeroPage => {
-                let data_addr = self.get_zero_page_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 3;
+        }
+    }
+
+    fn jsr_cycle(&mut self, cycle: u8) -> bool {
+        match cycle {
+            1 => {
+                self.state.data = self.fetch_byte();
+                false
             }
-            AddrMode::ZeroPageX => {
-                let data_addr = self.get_zero_page_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 3;
+            2 => {
+                self.bus.read_byte(STACK_PAGE | self.stack.sp as u16);
+                false
             }
-            AddrMode::Abs => {
-                let data_addr = self.get_absolute_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4;
+            3 => {
+                // PC points at the high byte of the target, the last byte of the JSR
+                self.stack.push_byte(&mut self.bus, (self.pc >> 8) as u8);
+                false
             }
-            AddrMode::AbsX => {
-                let data_addr = self.get_absolute_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4 as u8 + (data_addr > 0x00FF) as u8;
+            4 => {
+                self.stack.push_byte(&mut self.bus, self.pc as u8);
+                false
             }
-            AddrMode::AbsY => {
-                let data_addr = self.get_absolute_y_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4 as u8 + (data_addr > 0x00FF) as u8;
+            _ => {
+                let hi = self.bus.read_byte(self.pc);
+                self.pc = ((hi as u16) << 8) | self.state.data as u16;
+                true
             }
-            AddrMode::IndX => {
-                let data_addr = self.get_indirect_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 6;
+        }
+    }
+
+    fn rts_cycle(&mut self, cycle: u8) -> bool {
+        match cycle {
+            1 => {
+                self.bus.read_byte(self.pc);
+                false
+            }
+            2 => {
+                self.bus.read_byte(STACK_PAGE | self.stack.sp as u16);
+                false
+            }
+            3 => {
+                self.state.data = self.stack.pop_byte(&mut self.bus);
+                false
             }
-            AddrMode::IndY => {
-                let data_addr = self.get_indirect_y_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 5 as u8 + (data_addr > 0x00FF) as u8;
+            4 => {
+                let hi = self.stack.pop_byte(&mut self.bus);
+                self.pc = ((hi as u16) << 8) | self.state.data as u16;
+                false
+            }
+            _ => {
+                self.fetch_byte();
+                true
             }
-            _ => panic!("Addressing mode not supported"),
         }
-
-        self.a = data;
-        self.set_zero_and_negative_flags(self.a);
-
-        cycles
     }
 
-    fn ldx(&mut self, addr_mode: AddrMode) -> u8 {
-        let cycles: u8;
-        let data: u8;
-
-        match addr_mode {
-            AddrMode::Immediate => {
-                data = self.fetch_byte();
-                cycles = 2;
+    fn rti_cycle(&mut self, cycle: u8) -> bool {
+        match cycle {
+            1 => {
+                self.bus.read_byte(self.pc);
+                false
             }
-            AddrMode::ZeroPage => {
-                let data_addr = self.get_zero_page_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 3;
+            2 => {
+                self.bus.read_byte(STACK_PAGE | self.stack.sp as u16);
+                false
             }
-            AddrMode::ZeroPageY => {
-                let data_addr = self.get_zero_page_y_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4;
+            3 => {
+                let status = self.stack.pop_byte(&mut self.bus);
+                self.sr.set_status_byte(status);
+                self.sr.brk = false;
+                false
             }
-            AddrMode::Abs => {
-                let data_addr = self.get_absolute_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4;
+            4 => {
+                self.state.data = self.stack.pop_byte(&mut self.bus);
+                false
             }
-            AddrMode::AbsY => {
-                let data_addr = self.get_absolute_y_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4 as u8 + (data_addr > 0x00FF) as u8;
+            _ => {
+                let hi = self.stack.pop_byte(&mut self.bus);
+                self.pc = ((hi as u16) << 8) | self.state.data as u16;
+                true
             }
-            _ => panic!("Addressing mode not supported"),
         }
-
-        self.x = data;
-        self.set_zero_and_negative_flags(self.x);
-
-        cycles
     }
 
-    fn ldy(&mut self, addr_mode: AddrMode) -> u8 {
-        let cycles: u8;
-        let data: u8;
+    fn jmp_cycle(&mut self, cycle: u8) -> bool {
+        let fixed = !self.variant.has_indirect_jump_bug();
 
-        match addr_mode {
-            AddrMode::Immediate => {
-                data = self.fetch_byte();
-                cycles = 2;
+        match (self.state.addr_mode, cycle) {
+            (_, 1) => {
+                self.state.addr = self.fetch_byte() as u16;
+                false
+            }
+            (AddrMode::Abs, _) => {
+                let hi = self.bus.read_byte(self.pc);
+                self.pc = ((hi as u16) << 8) | self.state.addr;
+                true
             }
-            AddrMode::ZeroPage => {
-                let data_addr = self.get_zero_page_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 3;
+            (_, 2) => {
+                self.state.addr |= (self.fetch_byte() as u16) << 8;
+                false
             }
-            AddrMode::ZeroPageX => {
-                let data_addr = self.get_zero_page_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4;
+            (_, 3) if fixed => {
+                // The 65C02 spends a cycle adding X or carrying into the pointer's high byte
+                self.bus.read_byte(self.pc.wrapping_sub(1));
+                if self.state.addr_mode == AddrMode::AbsIndX {
+                    self.state.addr = self.state.addr.wrapping_add(self.x as u16);
+                }
+                false
             }
-            AddrMode::Abs => {
-                let data_addr = self.get_absolute_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4;
+            (_, 3) => {
+                self.state.data = self.bus.read_byte(self.state.addr);
+                false
             }
-            AddrMode::AbsX => {
-                let data_addr = self.get_absolute_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4 as u8 + (data_addr > 0x00FF) as u8;
+            (_, 4) if fixed => {
+                self.state.data = self.bus.read_byte(self.state.addr);
+                false
+            }
+            _ => {
+                // Without the fix the pointer's high byte is not incremented, JMP ($xxFF)
+                // reads its high byte from $xx00
+                let hi_addr = get_pointer_high_addr(self.state.addr, !fixed);
+                let hi = self.bus.read_byte(hi_addr);
+                self.pc = ((hi as u16) << 8) | self.state.data as u16;
+                true
             }
-            _ => panic!("Addressing mode not supported"),
         }
-
-        self.y = data;
-        self.set_zero_and_negative_flags(self.y);
-
-        cycles
     }
 
-    fn lsr_accumulator(&mut self) -> u8 {
-        self.sr.carry = (self.a & MASK_LSB) != 0;
+    /// JAM reads the next byte and then halts the CPU
+    fn jam_cycle(&mut self) -> bool {
+        self.bus.read_byte(self.pc);
+        self.jammed = true;
+        true
+    }
 
-        self.a = self.a >> 1;
+    /// WAI and STP run two more cycles before the CPU goes idle
+    fn halt_cycle(&mut self, cycle: u8) -> bool {
+        self.bus.read_byte(self.pc);
 
-        self.set_zero_and_negative_flags(self.a);
+        if cycle < 2 {
+            return false;
+        }
 
-        2
+        match self.state.operation {
+            Operation::Wai => self.waiting = true,
+            _ => self.jammed = true,
+        }
+        true
     }
 
-    fn lsr(&mut self, addr_mode: AddrMode) -> u8 {
-        let cycles: u8;
-        let data_addr: u16;
-        let mut data: u8;
-
-        match addr_mode {
-            AddrMode::ZeroPage => {
-                data_addr = self.get_zero_page_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 5;
+    /// The 65C02 NOP $5C reads its operand and then keeps the bus busy for five cycles
+    fn long_nop_cycle(&mut self, cycle: u8) -> bool {
+        match cycle {
+            1 => {
+                self.state.addr = self.fetch_byte() as u16;
+                false
             }
-            AddrMode::ZeroPageX => {
-                data_addr = self.get_zero_page_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 6;
+            2 => {
+                self.fetch_byte();
+                false
             }
-            AddrMode::Abs => {
-                data_addr = self.get_absolute_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 6;
+            3 => {
+                self.bus.read_byte(0xFF00 | self.state.addr);
+                false
             }
-            AddrMode::AbsX => {
-                data_addr = self.get_absolute_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 7;
+            _ => {
+                self.bus.read_byte(0xFFFF);
+                cycle == 7
             }
-            _ => panic!("Addressing mode not supported"),
         }
-
-        self.sr.carry = (data & MASK_LSB) != 0;
-
-        data = data >> 1;
-
-        self.memory.write_byte(data_addr, data);
-
-        self.set_zero_and_negative_flags(data);
-
-        cycles
     }
 
-    fn ora(&mut self, addr_mode: AddrMode) -> u8 {
-        let data_addr: u16;
-        let data: u8;
-        let cycles;
+    /// PHA, PHP, PHX and PHY
+    fn push_cycle(&mut self, cycle: u8) -> bool {
+        match cycle {
+            1 => {
+                self.bus.read_byte(self.pc);
+                false
+            }
+            _ => {
+                let data = match self.state.operation {
+                    Operation::Pha => self.a,
+                    Operation::Phx => self.x,
+                    Operation::Phy => self.y,
+                    _ => self.sr.get_pushed_status_byte(true),
+                };
+                self.stack.push_byte(&mut self.bus, data);
+                true
+            }
+        }
+    }
 
-        match addr_mode {
-            AddrMode::Immediate => {
-                data = self.fetch_byte();
-                cycles = 2;
+    /// PLA, PLP, PLX and PLY
+    fn pull_cycle(&mut self, cycle: u8) -> bool {
+        match cycle {
+            1 => {
+                self.bus.read_byte(self.pc);
+                false
+            }
+            2 => {
+                self.bus.read_byte(STACK_PAGE | self.stack.sp as u16);
+                false
             }
-            AddrMode::ZeroPage => {
-                data_addr = self.get_zero_page_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 3;
+            _ => {
+                let data = self.stack.pop_byte(&mut self.bus);
+
+                match self.state.operation {
+                    Operation::Pla => self.pull_accumulator(data),
+                    Operation::Plx => self.ldx(data),
+                    Operation::Ply => self.ldy(data),
+                    _ => self.pull_processor_status(data),
+                }
+                true
             }
-            AddrMode::ZeroPageX => {
-                data_addr = self.get_zero_page_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4;
+        }
+    }
+
+    fn branch_cycle(&mut self, cycle: u8) -> bool {
+        match cycle {
+            1 => {
+                self.state.data = self.fetch_byte();
+                !self.branch_condition()
             }
-            AddrMode::Abs => {
-                data_addr = self.get_absolute_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4;
+            _ => self.branch_taken_cycle(cycle - 2),
+        }
+    }
+
+    /// BBR and BBS test a bit of a zero page byte before branching
+    fn bit_branch_cycle(&mut self, cycle: u8) -> bool {
+        match cycle {
+            1 => {
+                self.state.ptr = self.fetch_byte();
+                false
             }
-            AddrMode::AbsX => {
-                data_addr = self.get_absolute_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4 as u8 + (data_addr > 0x00FF) as u8
+            2 => {
+                self.state.data = self.bus.read_byte(self.state.ptr as u16);
+                false
             }
-            AddrMode::IndX => {
-                data_addr = self.get_indirect_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 6;
+            3 => {
+                self.bus.read_byte(self.state.ptr as u16);
+                false
             }
-            AddrMode::IndY => {
-                data_addr = self.get_indirect_y_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 5 as u8 + (data_addr > 0xFF) as u8
+            4 => {
+                let value = self.state.data;
+                self.state.data = self.fetch_byte();
+
+                let taken = match self.state.operation {
+                    Operation::Bbr(bit) => value & (1 << bit) == 0,
+                    Operation::Bbs(bit) => value & (1 << bit) != 0,
+                    _ => unreachable!("Invalid branching mode!"),
+                };
+                !taken
             }
-            _ => panic!("Addressing mode not supported"),
+            _ => self.branch_taken_cycle(cycle - 5),
         }
+    }
 
-        self.a = self.a | data;
+    /// Cycles added by a taken branch, the offset is latched in `state.data`
+    fn branch_taken_cycle(&mut self, step: u8) -> bool {
+        match step {
+            0 => {
+                self.bus.read_byte(self.pc);
 
-        self.set_zero_and_negative_flags(self.a);
+                let (target, page_crossed) = get_branch_target(self.pc, self.state.data);
+                self.state.page_crossed = page_crossed;
+                if !page_crossed {
+                    self.pc = target;
+                    return true;
+                }
 
-        cycles
+                // The low byte is updated first, the high byte needs one more cycle
+                self.state.addr = target;
+                self.pc = (self.pc & 0xFF00) | (target & 0x00FF);
+                false
+            }
+            _ => {
+                self.bus.read_byte(self.pc);
+                self.pc = self.state.addr;
+                true
+            }
+        }
     }
 
-    fn push_accumulator(&mut self) -> u8 {
-        self.stack.push_byte(self.a);
-
-        3
+    fn branch_condition(&self) -> bool {
+        match self.state.operation {
+            Operation::Bcc => !self.sr.carry,
+            Operation::Bcs => self.sr.carry,
+            Operation::Beq => self.sr.zero,
+            Operation::Bne => !self.sr.zero,
+            Operation::Bmi => self.sr.negative,
+            Operation::Bpl => !self.sr.negative,
+            Operation::Bvc => !self.sr.overflow,
+            Operation::Bvs => self.sr.overflow,
+            Operation::Bra => true,
+            _ => unreachable!("Invalid branching mode!"),
+        }
     }
 
-    fn push_processor_status(&mut self) -> u8 {
-        let processor_status = self.sr.get_status_byte();
-        self.stack.push_byte(processor_status);
+    fn read_operation(&mut self, data: u8) {
+        match self.state.operation {
+            Operation::Adc => self.adc(data),
+            Operation::And => self.and(data),
+            Operation::Bit => self.bit_test(data),
+            Operation::Cmp => self.cmp(data),
+            Operation::Cpx => self.cpx(data),
+            Operation::Cpy => self.cpy(data),
+            Operation::Eor => self.eor(data),
+            Operation::Lda => self.lda(data),
+            Operation::Ldx => self.ldx(data),
+            Operation::Ldy => self.ldy(data),
+            Operation::Ora => self.ora(data),
+            Operation::Sbc => self.sbc(data),
+            Operation::Nop => {}
+            Operation::Alr => self.alr(data),
+            Operation::Anc => self.anc(data),
+            Operation::Ane => self.ane(data),
+            Operation::Arr => self.arr(data),
+            Operation::Las => self.las(data),
+            Operation::Lax => self.lax(data),
+            Operation::Lxa => self.lxa(data),
+            Operation::Sbx => self.sbx(data),
+            _ => unreachable!("Addressing mode not supported"),
+        }
+    }
 
-        3
+    fn write_operation(&mut self) -> u8 {
+        match self.state.operation {
+            Operation::Sta => self.a,
+            Operation::Stx => self.x,
+            Operation::Sty => self.y,
+            Operation::Sax => self.a & self.x,
+            Operation::Stz => 0,
+            Operation::Sha => self.unstable_store(self.a & self.x),
+            Operation::Shx => self.unstable_store(self.x),
+            Operation::Shy => self.unstable_store(self.y),
+            Operation::Tas => {
+                self.stack.sp = self.a & self.x;
+                self.unstable_store(self.stack.sp)
+            }
+            _ => unreachable!("Addressing mode not supported"),
+        }
     }
 
-    fn pull_accumulator(&mut self) -> u8 {
-        let popped_acc = self.stack.pop_byte();
-        self.a = popped_acc;
+    /// Value stored by SHA, SHX, SHY and TAS: the register ANDed with the high byte of
+    /// the base address plus one. When indexing crosses a page the value also replaces
+    /// the high byte of the address written to.
+    fn unstable_store(&mut self, data: u8) -> u8 {
+        let data = data & ((self.state.base >> 8) as u8).wrapping_add(1);
 
-        self.set_zero_and_negative_flags(popped_acc);
+        if self.state.page_crossed {
+            self.state.addr = ((data as u16) << 8) | (self.state.addr & 0x00FF);
+        }
 
-        4
+        data
+    }
+
+    fn modify_operation(&mut self, data: u8) -> u8 {
+        match self.state.operation {
+            Operation::Asl => self.asl(data),
+            Operation::Lsr => self.lsr(data),
+            Operation::Rol => self.rol(data),
+            Operation::Ror => self.ror(data),
+            Operation::Inc => self.inc(data),
+            Operation::Dec => self.dec(data),
+            Operation::Slo => {
+                let data = self.asl(data);
+                self.ora(data);
+                data
+            }
+            Operation::Rla => {
+                let data = self.rol(data);
+                self.and(data);
+                data
+            }
+            Operation::Sre => {
+                let data = self.lsr(data);
+                self.eor(data);
+                data
+            }
+            Operation::Rra => {
+                let data = self.ror(data);
+                self.adc(data);
+                data
+            }
+            Operation::Dcp => {
+                let data = data.wrapping_sub(1);
+                self.cmp(data);
+                data
+            }
+            Operation::Isc => {
+                let data = data.wrapping_add(1);
+                self.sbc(data);
+                data
+            }
+            Operation::Trb => {
+                self.sr.zero = data & self.a == 0;
+                data & !self.a
+            }
+            Operation::Tsb => {
+                self.sr.zero = data & self.a == 0;
+                data | self.a
+            }
+            Operation::Rmb(bit) => data & !(1 << bit),
+            Operation::Smb(bit) => data | (1 << bit),
+            _ => unreachable!("Addressing mode not supported"),
+        }
     }
 
-    fn pull_processor_status(&mut self) -> u8 {
-        let popped_sr = self.stack.pop_byte();
-        self.sr.set_status_byte(popped_sr);
-
-        4
+    fn implied_operation(&mut self) {
+        match self.state.operation {
+            Operation::Clc => self.clear_carry(),
+            Operation::Cld => self.clear_decimal(),
+            Operation::Cli => self.clear_interrupt_dis(),
+            Operation::Clv => self.clear_overflow(),
+            Operation::Dex => self.dex(),
+            Operation::Dey => self.dey(),
+            Operation::Inx => self.inc_x(),
+            Operation::Iny => self.inc_y(),
+            Operation::Nop => {}
+            Operation::Sec => self.sec(),
+            Operation::Sed => self.sed(),
+            Operation::Sei => self.sei(),
+            Operation::Tax => self.tax(),
+            Operation::Tay => self.tay(),
+            Operation::Tsx => self.tsx(),
+            Operation::Txa => self.txa(),
+            Operation::Txs => self.txs(),
+            Operation::Tya => self.tya(),
+            _ => unreachable!("Addressing mode not supported"),
+        }
     }
 
-    fn rol(&mut self, addr_mode: AddrMode) -> u8 {
-        let old_carry: u8;
-        let mut data: u8;
-        let data_addr: u16;
-        let cycles;
-
-        match addr_mode {
-            AddrMode::ZeroPage => {
-                old_carry = self.sr.carry as u8;
-                data_addr = self.get_zero_page_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 5;
-            }
-            AddrMode::ZeroPageX => {
-                old_carry = self.sr.carry as u8;
-                data_addr = self.get_zero_page_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 6;
-            }
-            AddrMode::Abs => {
-                old_carry = self.sr.carry as u8;
-                data_addr = self.get_absolute_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 6;
-            }
-            AddrMode::AbsX => {
-                old_carry = self.sr.carry as u8;
-                data_addr = self.get_absolute_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 7;
-            }
-            _ => panic!("Addressing mode not supported"),
+    fn bit_test(&mut self, data: u8) {
+        // BIT #imm on the 65C02 only affects Z
+        if self.state.addr_mode != AddrMode::Immediate {
+            self.sr.negative = (data & MASK_MSB) != 0;
+            self.sr.overflow = (data & MASK_SIXTH_BIT) != 0;
         }
 
-        self.sr.carry = (data & MASK_MSB) != 0;
+        self.sr.zero = (data & self.a) == 0;
+    }
 
-        data = (data << 1) | old_carry;
+    fn clear_carry(&mut self) {
+        self.sr.carry = false;
+    }
 
-        self.set_zero_and_negative_flags(data);
+    fn clear_decimal(&mut self) {
+        self.sr.decimal = false;
+    }
 
-        self.memory.write_byte(data_addr, data);
+    fn clear_interrupt_dis(&mut self) {
+        self.sr.interrupt_disable = false;
+    }
 
-        cycles
+    fn clear_overflow(&mut self) {
+        self.sr.overflow = false;
     }
 
-    fn rol_accumulator(&mut self) -> u8 {
-        let old_carry = self.sr.carry as u8;
+    fn inc_y(&mut self) {
+        self.y = self.y.wrapping_add(1);
 
-        self.sr.carry = (self.a & MASK_MSB) != 0;
+        self.set_zero_and_negative_flags(self.y);
+    }
 
-        self.a = (self.a << 1) | old_carry;
+    fn inc_x(&mut self) {
+        self.x = self.x.wrapping_add(1);
 
-        self.set_zero_and_negative_flags(self.a);
+        self.set_zero_and_negative_flags(self.x);
+    }
 
-        2
+    fn lda(&mut self, data: u8) {
+        self.a = data;
+        self.set_zero_and_negative_flags(self.a);
     }
 
-    fn asl(&mut self, addr_mode: AddrMode) -> u8 {
-        let data_addr: u16;
-        let mut data: u8;
-        let cycles;
+    fn ldx(&mut self, data: u8) {
+        self.x = data;
+        self.set_zero_and_negative_flags(self.x);
+    }
 
-        match addr_mode {
-            AddrMode::ZeroPage => {
-                data_addr = self.get_zero_page_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 5;
-            }
-            AddrMode::ZeroPageX => {
-                data_addr = self.get_zero_page_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 5;
-            }
-            AddrMode::Abs => {
-                data_addr = self.get_absolute_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 6;
-            }
-            AddrMode::AbsX => {
-                data_addr = self.get_absolute_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 7;
-            }
-            _ => panic!("Addressing mode not supported"),
-        }
+    fn ldy(&mut self, data: u8) {
+        self.y = data;
+        self.set_zero_and_negative_flags(self.y);
+    }
 
-        self.sr.carry = (data & MASK_MSB) != 0;
+    fn lsr(&mut self, mut data: u8) -> u8 {
+        self.sr.carry = (data & MASK_LSB) != 0;
 
-        data = data << 1;
+        data >>= 1;
 
         self.set_zero_and_negative_flags(data);
 
-        self.memory.write_byte(data_addr, data);
-
-        cycles
+        data
     }
 
-    fn asl_acc(&mut self) -> u8 {
-        let old_byte = self.a;
-
-        self.sr.carry = (old_byte & MASK_MSB) != 0;
-
-        self.a = self.a << 1;
+    fn ora(&mut self, data: u8) {
+        self.a |= data;
 
         self.set_zero_and_negative_flags(self.a);
-
-        2
     }
 
-    fn sta(&mut self, addr_mode: AddrMode) -> u8 {
-        let data_addr: u16;
-        let cycles: u8;
-
-        match addr_mode {
-            AddrMode::ZeroPage => {
-                data_addr = self.get_zero_page_addr();
-                cycles = 3;
-            }
-            AddrMode::ZeroPageX => {
-                data_addr = self.get_zero_page_x_addr();
-                cycles = 4;
-            }
-            AddrMode::Abs => {
-                data_addr = self.get_absolute_addr();
-                cycles = 4;
-            }
-            AddrMode::AbsX => {
-                data_addr = self.get_absolute_x_addr();
-                cycles = 5;
-            }
-            AddrMode::AbsY => {
-                data_addr = self.get_absolute_y_addr();
-                cycles = 5;
-            }
-            AddrMode::IndX => {
-                data_addr = self.get_indirect_x_addr();
-                cycles = 6;
-            }
-            AddrMode::IndY => {
-                data_addr = self.get_indirect_y_addr();
-                cycles = 6;
-            }
-            _ => panic!("Addressing mode not supported"),
-        }
+    fn pull_accumulator(&mut self, popped_acc: u8) {
+        self.a = popped_acc;
 
-        self.memory.write_byte(data_addr, self.a);
+        self.set_zero_and_negative_flags(popped_acc);
+    }
 
-        cycles
+    fn pull_processor_status(&mut self, popped_sr: u8) {
+        self.sr.set_status_byte(popped_sr);
+        self.sr.brk = false;
     }
 
-    fn stx(&mut self, addr_mode: AddrMode) -> u8 {
-        let data_addr: u16;
-        let cycles: u8;
+    fn rol(&mut self, mut data: u8) -> u8 {
+        let old_carry = self.sr.carry as u8;
 
-        match addr_mode {
-            AddrMode::ZeroPage => {
-                data_addr = self.get_zero_page_addr();
-                cycles = 3;
-            }
-            AddrMode::ZeroPageY => {
-                data_addr = self.get_zero_page_y_addr();
-                cycles = 4;
-            }
-            AddrMode::Abs => {
-                data_addr = self.get_absolute_addr();
-                cycles = 4;
-            }
-            _ => panic!("Addressing mode not supported"),
-        }
+        self.sr.carry = (data & MASK_MSB) != 0;
+
+        data = (data << 1) | old_carry;
 
-        self.memory.write_byte(data_addr, self.x);
+        self.set_zero_and_negative_flags(data);
 
-        cycles
+        data
     }
 
-    fn sty(&mut self, addr_mode: AddrMode) -> u8 {
-        let data_addr: u16;
-        let cycles: u8;
+    fn asl(&mut self, mut data: u8) -> u8 {
+        self.sr.carry = (data & MASK_MSB) != 0;
 
-        match addr_mode {
-            AddrMode::ZeroPage => {
-                data_addr = self.get_zero_page_addr();
-                cycles = 3;
-            }
-            AddrMode::ZeroPageX => {
-                data_addr = self.get_zero_page_x_addr();
-                cycles = 4;
-            }
-            AddrMode::Abs => {
-                data_addr = self.get_absolute_addr();
-                cycles = 4;
-            }
-            _ => panic!("Addressing mode not supported"),
-        }
+        data <<= 1;
 
-        self.memory.write_byte(data_addr, self.y);
+        self.set_zero_and_negative_flags(data);
 
-        cycles
+        data
     }
 
-    fn tax(&mut self) -> u8 {
+    fn tax(&mut self) {
         self.x = self.a;
         self.set_zero_and_negative_flags(self.x);
-
-        2
     }
 
-    fn tay(&mut self) -> u8 {
+    fn tay(&mut self) {
         self.y = self.a;
         self.set_zero_and_negative_flags(self.y);
-
-        2
     }
 
-    fn tsx(&mut self) -> u8 {
+    fn tsx(&mut self) {
         self.x = self.stack.sp;
         self.set_zero_and_negative_flags(self.x);
-
-        2
     }
 
-    fn txa(&mut self) -> u8 {
+    fn txa(&mut self) {
         self.a = self.x;
         self.set_zero_and_negative_flags(self.a);
-
-        2
     }
 
-    fn txs(&mut self) -> u8 {
+    fn txs(&mut self) {
         self.stack.sp = self.x;
-        self.set_zero_and_negative_flags(self.stack.sp);
-
-        2
     }
 
-    fn tya(&mut self) -> u8 {
+    fn tya(&mut self) {
         self.a = self.y;
         self.set_zero_and_negative_flags(self.a);
-
-        2
     }
 
-    fn adc(&mut self, addr_mode: AddrMode) -> u8 {
-        let mut data: u8;
-        let mut temp_result: u8;
-        let cycles: u8;
-
-        match addr_mode {
-            AddrMode::Immediate => {
-                data = self.fetch_byte();
-                cycles = 2;
-            }
-            AddrMode::ZeroPage => {
-                let data_addr = self.get_zero_page_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 3;
-            }
-            AddrMode::ZeroPageX => {
-                let data_addr = self.get_zero_page_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4;
-            }
-            AddrMode::Abs => {
-                let data_addr = self.get_absolute_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4;
+    fn adc(&mut self, data: u8) {
+        if self.decimal_mode() {
+            if self.check_decimal_operands(data) {
+                self.adc_decimal(data);
             }
-            AddrMode::AbsX => {
-                let data_addr = self.get_absolute_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4 + (data_addr > 0x00FF) as u8;
-            }
-            AddrMode::AbsY => {
-                let data_addr = self.get_absolute_y_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4 + (data_addr > 0x00FF) as u8;
-            }
-            AddrMode::IndX => {
-                let data_addr = self.get_indirect_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 6;
-            }
-            AddrMode::IndY => {
-                let data_addr = self.get_indirect_y_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 5 + (data_addr > 0x00FF) as u8;
-            }
-            _ => panic!("Addressing mode not supported"),
+            return;
         }
 
-        if self.sr.decimal && (!is_bcd_valid(data) || !is_bcd_valid(self.a)) {
-            panic!("Illegal BCD operand");
-        }
+        let sum = self.a as u16 + data as u16 + self.sr.carry as u16;
+        let result = sum as u8;
+
+        self.sr.carry = sum > 0xFF;
+        self.sr.overflow = (!(self.a ^ data) & (self.a ^ result) & MASK_MSB) != 0;
+        self.set_zero_and_negative_flags(result);
+
+        self.a = result;
+    }
 
-        if self.sr.decimal {
-            data = hex_as_dec(data);
-            self.a = hex_as_dec(self.a);
+    /// Returns whether a decimal ADC or SBC may go ahead, raising an error for operands
+    /// that are not valid BCD when strict mode is enabled
+    fn check_decimal_operands(&mut self, data: u8) -> bool {
+        if !self.strict || (is_bcd_valid(self.a) && is_bcd_valid(data)) {
+            return true;
         }
 
-        temp_result = self.a.wrapping_add(data).wrapping_add(self.sr.carry as u8);
-        if self.sr.decimal {
-            temp_result = dec_as_hex(temp_result);
+        self.fault = Some(CpuError::InvalidDecimalOperand {
+            pc: self.state.opcode_addr,
+            a: self.a,
+            operand: data,
+        });
+        false
+    }
+
+    /// BCD addition for any operand, valid or not, following the sequences measured on
+    /// silicon. The NMOS 6502 takes N and V before the high nibble is adjusted and Z from
+    /// the binary sum, the 65C02 takes N and Z from the result at the cost of a cycle.
+    fn adc_decimal(&mut self, data: u8) {
+        let a = self.a as u16;
+        let m = data as u16;
+        let carry = self.sr.carry as u16;
+
+        let mut lo = (a & 0x0F) + (m & 0x0F) + carry;
+        if lo >= 0x0A {
+            lo = ((lo + 0x06) & 0x0F) + 0x10;
         }
 
-        self.sr.carry = data as u16 + self.a as u16 + self.sr.carry as u16 > 0xFF;
-        self.sr.overflow = data as u16 + self.a as u16 + self.sr.carry as u16 > 0xFF;
-        self.a = temp_result;
+        let mut sum = (a & 0xF0) + (m & 0xF0) + lo;
+        let intermediate = sum as u8;
 
-        cycles
-    }
+        self.sr.overflow = (!(self.a ^ data) & (self.a ^ intermediate) & MASK_MSB) != 0;
 
-    fn and(&mut self, addr_mode: AddrMode) -> u8 {
-        let data: u8;
-        let cycles: u8;
+        if sum >= 0xA0 {
+            sum += 0x60;
+        }
 
-        match addr_mode {
-            AddrMode::Immediate => {
-                data = self.fetch_byte();
-                cycles = 2;
-            }
-            AddrMode::ZeroPage => {
-                let data_addr = self.get_zero_page_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 3;
-            }
-            AddrMode::ZeroPageX => {
-                let data_addr = self.get_zero_page_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4;
-            }
-            AddrMode::Abs => {
-                let data_addr = self.get_absolute_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4;
-            }
-            AddrMode::AbsX => {
-                let data_addr = self.get_absolute_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4 + (data_addr > 0x00FF) as u8;
-            }
-            AddrMode::AbsY => {
-                let data_addr = self.get_absolute_y_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4 + (data_addr > 0x00FF) as u8;
-            }
-            AddrMode::IndX => {
-                let data_addr = self.get_indirect_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 6;
-            }
-            AddrMode::IndY => {
-                let data_addr = self.get_indirect_y_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 5 + (data_addr > 0x00FF) as u8;
-            }
-            _ => panic!("Addressing mode not supported"),
+        let result = sum as u8;
+        self.sr.carry = sum > 0xFF;
+
+        if self.variant.is_cmos() {
+            self.set_zero_and_negative_flags(result);
+            self.state.decimal_fixup = true;
+        } else {
+            self.sr.zero = self.a.wrapping_add(data).wrapping_add(carry as u8) == 0;
+            self.sr.negative = (intermediate & MASK_MSB) != 0;
         }
 
-        self.a = self.a & data;
+        self.a = result;
+    }
+
+    fn and(&mut self, data: u8) {
+        self.a &= data;
         self.set_zero_and_negative_flags(self.a);
+    }
 
-        cycles
+    fn cmp(&mut self, data: u8) {
+        self.sr.carry = self.a >= data;
+        self.set_zero_and_negative_flags(self.a.wrapping_sub(data));
     }
 
-    fn jsr(&mut self) -> u8 {
-        self.stack.push_word(self.pc + 0x01);
-        self.pc = self.fetch_word();
+    fn cpx(&mut self, data: u8) {
+        self.sr.carry = self.x >= data;
+        self.set_zero_and_negative_flags(self.x.wrapping_sub(data));
+    }
 
-        3
+    fn cpy(&mut self, data: u8) {
+        self.sr.carry = self.y >= data;
+        self.set_zero_and_negative_flags(self.y.wrapping_sub(data));
     }
 
-    fn rts(&mut self) -> u8 {
-        self.pc = self.stack.pop_word() + 1;
+    fn dec(&mut self, data: u8) -> u8 {
+        let data = data.wrapping_sub(1);
+        self.set_zero_and_negative_flags(data);
 
-        6
+        data
     }
 
-    fn jmp(&mut self, addr_mode: AddrMode) -> u8 {
-        let cycles: u8;
-        let new_pc_addr: u16;
-
-        match addr_mode {
-            AddrMode::Abs => {
-                new_pc_addr = self.get_absolute_addr();
-                cycles = 3
-            }
-            AddrMode::Ind => {
-                new_pc_addr = self.get_indirect_addr();
-                cycles = 5
-            }
-            _ => panic!("Addressing mode not supported"),
-        }
+    fn dex(&mut self) {
+        self.x = self.x.wrapping_sub(1);
+        self.set_zero_and_negative_flags(self.x);
+    }
 
-        self.pc = new_pc_addr;
+    fn dey(&mut self) {
+        self.y = self.y.wrapping_sub(1);
+        self.set_zero_and_negative_flags(self.y);
+    }
 
-        cycles
+    fn eor(&mut self, data: u8) {
+        self.a ^= data;
+        self.set_zero_and_negative_flags(self.a);
     }
 
-    fn rti(&mut self) -> u8 {
-        self.sr.set_status_byte(self.stack.pop_byte());
-        self.sr.brk = false;
-        self.pc = self.stack.pop_word();
+    fn inc(&mut self, data: u8) -> u8 {
+        let data = data.wrapping_add(1);
+        self.set_zero_and_negative_flags(data);
 
-        6
+        data
     }
 
-    fn cmp(&mut self, addr_mode: AddrMode) -> u8 {
-        let data: u8;
-        let cycles: u8;
+    fn ror(&mut self, mut data: u8) -> u8 {
+        let old_carry = self.sr.carry as u8;
 
-        match addr_mode {
-            AddrMode::Immediate => {
-                data = self.fetch_byte();
-                cycles = 2;
-            }
-            AddrMode::ZeroPage => {
-                let data_addr = self.get_zero_page_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 3;
-            }
-            AddrMode::ZeroPageX => {
-                let data_addr = self.get_zero_page_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4;
-            }
-            AddrMode::Abs => {
-                let data_addr = self.get_absolute_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4;
-            }
-            AddrMode::AbsX => {
-                let data_addr = self.get_absolute_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4 + (data_addr > 0x00FF) as u8;
-            }
-            AddrMode::AbsY => {
-                let data_addr = self.get_absolute_y_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4 + (data_addr > 0x00FF) as u8;
-            }
-            AddrMode::IndX => {
-                let data_addr = self.get_indirect_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 6;
-            }
-            AddrMode::IndY => {
-                let data_addr = self.get_indirect_y_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 5 + (data_addr > 0x00FF) as u8;
-            }
-            _ => panic!("Addressing mode not supported"),
-        }
+        self.sr.carry = (data & MASK_LSB) != 0;
 
-        self.sr.carry = self.a >= data;
-        self.set_zero_and_negative_flags(self.a.wrapping_sub(data));
+        data = (data >> 1) | (old_carry << 7);
 
-        cycles
-    }
+        self.set_zero_and_negative_flags(data);
 
-    fn cpx(&mut self, addr_mode: AddrMode) -> u8 {
-        let data: u8;
-        let cycles: u8;
+        data
+    }
 
-        match addr_mode {
-            AddrMode::Immediate => {
-                data = self.fetch_byte();
-                cycles = 2;
-            }
-            AddrMode::ZeroPage => {
-                let data_addr = self.get_zero_page_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 3;
-            }
-            AddrMode::Abs => {
-                let data_addr = self.get_absolute_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4;
+    fn sbc(&mut self, data: u8) {
+        if self.decimal_mode() {
+            if self.check_decimal_operands(data) {
+                self.sbc_decimal(data);
             }
-            _ => panic!("Addressing mode not supported"),
+            return;
         }
 
-        self.sr.carry = self.x >= data;
-        self.set_zero_and_negative_flags(self.x.wrapping_sub(data));
+        // Subtraction is an addition of the operand's one's complement
+        let data = !data;
+        let sum = self.a as u16 + data as u16 + self.sr.carry as u16;
+        let result = sum as u8;
 
-        cycles
-    }
+        self.sr.carry = sum > 0xFF;
+        self.sr.overflow = (!(self.a ^ data) & (self.a ^ result) & MASK_MSB) != 0;
+        self.set_zero_and_negative_flags(result);
 
-    fn cpy(&mut self, addr_mode: AddrMode) -> u8 {
-        let data: u8;
-        let cycles: u8;
+        self.a = result;
+    }
 
-        match addr_mode {
-            AddrMode::Immediate => {
-                data = self.fetch_byte();
-                cycles = 2;
-            }
-            AddrMode::ZeroPage => {
-                let data_addr = self.get_zero_page_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 3;
-            }
-            AddrMode::Abs => {
-                let data_addr = self.get_absolute_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4;
-            }
-            _ => panic!("Addressing mode not supported"),
-        }
+    /// BCD subtraction for any operand. C and V always come from the binary subtraction,
+    /// and so do N and Z on the NMOS 6502. The 65C02 adjusts the result differently and
+    /// takes N and Z from it at the cost of a cycle.
+    fn sbc_decimal(&mut self, data: u8) {
+        let a = self.a as i16;
+        let m = data as i16;
+        let borrow = 1 - self.sr.carry as i16;
 
-        self.sr.carry = self.y >= data;
-        self.set_zero_and_negative_flags(self.y.wrapping_sub(data));
-
-        cycles
-    }
+        let binary = a - m - borrow;
+        let mut lo = (a & 0x0F) - (m & 0x0F) - borrow;
 
-    fn dec(&mut self, addr_mode: AddrMode) -> u8 {
-        let data_addr: u16;
-        let mut data: u8;
-        let cycles: u8;
+        self.sr.carry = binary >= 0;
+        self.sr.overflow = ((self.a ^ data) & (self.a ^ binary as u8) & MASK_MSB) != 0;
 
-        match addr_mode {
-            AddrMode::ZeroPage => {
-                data_addr = self.get_zero_page_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 5;
+        let result = if self.variant.is_cmos() {
+            let mut result = binary;
+            if result < 0 {
+                result -= 0x60;
             }
-            AddrMode::ZeroPageX => {
-                data_addr = self.get_zero_page_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 6;
+            if lo < 0 {
+                result -= 0x06;
             }
-            AddrMode::Abs => {
-                data_addr = self.get_absolute_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 6;
+
+            self.set_zero_and_negative_flags(result as u8);
+            self.state.decimal_fixup = true;
+            result
+        } else {
+            if lo < 0 {
+                lo = ((lo - 0x06) & 0x0F) - 0x10;
             }
-            AddrMode::AbsX => {
-                data_addr = self.get_absolute_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 7;
+            let mut result = (a & 0xF0) - (m & 0xF0) + lo;
+            if result < 0 {
+                result -= 0x60;
             }
-            _ => panic!("Addressing mode not supported"),
-        }
 
-        data = data.wrapping_sub(1);
-        self.set_zero_and_negative_flags(data);
-        self.memory.write_byte(data_addr, data);
+            self.set_zero_and_negative_flags(binary as u8);
+            result
+        };
 
-        cycles
+        self.a = result as u8;
     }
 
-    fn dex(&mut self) -> u8 {
-        self.x = self.x.wrapping_sub(1);
-        self.set_zero_and_negative_flags(self.x);
-
-        2
+    fn sec(&mut self) {
+        self.sr.carry = true;
     }
 
-    fn dey(&mut self) -> u8 {
-        self.y = self.y.wrapping_sub(1);
-        self.set_zero_and_negative_flags(self.y);
+    fn sed(&mut self) {
+        self.sr.decimal = true;
+    }
 
-        2
+    fn sei(&mut self) {
+        self.sr.interrupt_disable = true;
     }
 
-    fn eor(&mut self, addr_mode: AddrMode) -> u8 {
-        let data_addr: u16;
-        let data: u8;
-        let cycles: u8;
+    fn alr(&mut self, data: u8) {
+        self.a &= data;
+        self.a = self.lsr(self.a);
+    }
 
-        match addr_mode {
-            AddrMode::Immediate => {
-                data = self.fetch_byte();
-                cycles = 2;
-            }
-            AddrMode::ZeroPage => {
-                data_addr = self.get_zero_page_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 3;
-            }
-            AddrMode::ZeroPageX => {
-                data_addr = self.get_zero_page_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4;
-            }
-            AddrMode::Abs => {
-                data_addr = self.get_absolute_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4;
-            }
-            AddrMode::AbsX => {
-                data_addr = self.get_absolute_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4 + (data_addr > 0x00FF) as u8;
-            }
-            AddrMode::AbsY => {
-                data_addr = self.get_absolute_y_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 4 + (data_addr > 0x00FF) as u8;
-            }
-            AddrMode::IndX => {
-                data_addr = self.get_indirect_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 6;
-            }
-            AddrMode::IndY => {
-                data_addr = self.get_indirect_y_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 5 + (data_addr > 0x00FF) as u8;
-            }
-            _ => panic!("Addressing mode not supported"),
-        }
+    fn anc(&mut self, data: u8) {
+        self.and(data);
+        self.sr.carry = self.sr.negative;
+    }
 
-        self.a = self.a ^ data;
+    /// Unstable on real hardware, modelled with the commonly observed magic constant $EE
+    fn ane(&mut self, data: u8) {
+        self.a = (self.a | ANE_MAGIC) & self.x & data;
         self.set_zero_and_negative_flags(self.a);
-
-        cycles
     }
 
-    fn inc(&mut self, addr_mode: AddrMode) -> u8 {
-        let data_addr: u16;
-        let mut data: u8;
-        let cycles: u8;
+    fn arr(&mut self, data: u8) {
+        let and = self.a & data;
+        let mut result = (and >> 1) | ((self.sr.carry as u8) << 7);
 
-        match addr_mode {
-            AddrMode::ZeroPage => {
-                data_addr = self.get_zero_page_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 5;
-            }
-            AddrMode::ZeroPageX => {
-                data_addr = self.get_zero_page_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 6;
-            }
-            AddrMode::Abs => {
-                data_addr = self.get_absolute_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 6;
-            }
-            AddrMode::AbsX => {
-                data_addr = self.get_absolute_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 7;
-            }
-            _ => panic!("Addressing mode not supported"),
+        self.set_zero_and_negative_flags(result);
+
+        if !self.decimal_mode() {
+            self.sr.carry = (result & MASK_SIXTH_BIT) != 0;
+            self.sr.overflow = ((result >> 6) ^ (result >> 5)) & MASK_LSB != 0;
+            self.a = result;
+            return;
         }
 
-        data = data.wrapping_add(1);
-        self.set_zero_and_negative_flags(data);
-        self.memory.write_byte(data_addr, data);
+        // In decimal mode each nibble of the rotated value gets a BCD fixup
+        self.sr.overflow = ((and ^ result) & MASK_SIXTH_BIT) != 0;
+
+        if (and & 0x0F) + (and & 0x01) > 0x05 {
+            result = (result & 0xF0) | (result.wrapping_add(0x06) & 0x0F);
+        }
 
-        cycles
+        self.sr.carry = (and & 0xF0) as u16 + (and & 0x10) as u16 > 0x50;
+        if self.sr.carry {
+            result = result.wrapping_add(0x60);
+        }
+
+        self.a = result;
     }
 
-    fn ror_accumulator(&mut self) -> u8 {
-        let old_carry = self.sr.carry as u8;
+    fn las(&mut self, data: u8) {
+        let data = data & self.stack.sp;
 
-        self.sr.carry = (self.a & MASK_LSB) != 0;
+        self.a = data;
+        self.x = data;
+        self.stack.sp = data;
+        self.set_zero_and_negative_flags(data);
+    }
 
-        self.a = (self.a >> 1) | (old_carry << 7);
+    fn lax(&mut self, data: u8) {
+        self.a = data;
+        self.x = data;
+        self.set_zero_and_negative_flags(data);
+    }
 
-        self.set_zero_and_negative_flags(self.a);
+    /// Unstable on real hardware, modelled with the same magic constant as ANE
+    fn lxa(&mut self, data: u8) {
+        self.lax((self.a | ANE_MAGIC) & data);
+    }
+
+    fn sbx(&mut self, data: u8) {
+        let value = self.a & self.x;
 
-        2
+        self.sr.carry = value >= data;
+        self.x = value.wrapping_sub(data);
+        self.set_zero_and_negative_flags(self.x);
     }
+}
 
-    fn ror(&mut self, addr_mode: AddrMode) -> u8 {
-        let old_carry: u8;
-        let mut data: u8;
-        let data_addr: u16;
-        let cycles;
+impl<B: Inspect> Cpu<B> {
+    /// Makes the CPU ask the bus for a write to ROM after every cycle, and stop with
+    /// [`CpuError::RomWrite`] when there is one. Needed for
+    /// [`RomWritePolicy::Break`](crate::memory::RomWritePolicy::Break) to stop the CPU.
+    pub fn poll_rom_faults(&mut self) {
+        self.rom_fault_probe = Some(B::take_rom_fault);
+    }
+
+    /// Serializes the whole machine: registers, flags, interrupt lines, the instruction
+    /// in flight, the cycle counter and everything the bus saves
+    ///
+    /// ### Returns:
+    /// * The versioned save state
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+
+        writer.write_u8(variant_to_byte(self.variant));
+        writer.write_u8(self.a);
+        writer.write_u8(self.x);
+        writer.write_u8(self.y);
+        writer.write_u16(self.pc);
+        writer.write_u8(self.stack.sp);
+        writer.write_u8(self.sr.get_status_byte());
+        writer.write_u64(self.cycles);
+        writer.write_bool(self.irq_line);
+        writer.write_bool(self.nmi_line);
+        writer.write_bool(self.bus_nmi);
+        writer.write_bool(self.nmi_pending);
+        writer.write_u8(interrupt_to_byte(self.requested_interrupt));
+        writer.write_bool(self.jammed);
+        writer.write_bool(self.waiting);
+
+        // The operation and addressing mode are decoded again from the opcode
+        writer.write_u8(self.state.cycle);
+        writer.write_u16(self.state.opcode_addr);
+        writer.write_u8(self.state.opcode);
+        writer.write_u8(interrupt_to_byte(self.state.interrupt));
+        writer.write_u16(self.state.base);
+        writer.write_u16(self.state.addr);
+        writer.write_u8(self.state.ptr);
+        writer.write_u8(self.state.data);
+        writer.write_bool(self.state.page_crossed);
+        writer.write_bool(self.state.decimal_fixup);
+
+        self.bus.save_state(&mut writer);
+        writer.into_bytes()
+    }
+
+    /// Restores a machine serialized by [`Cpu::snapshot`], the variant included.
+    /// Strict mode is a debugger setting and is left as it is.
+    ///
+    /// ### Parameters:
+    /// * `data` - The save state
+    ///
+    /// ### Returns:
+    /// * `Err(StateError)` if the save state is invalid, the CPU and the bus are then
+    ///   left untouched
+    pub fn restore(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader::new(data)?;
+
+        let variant = byte_to_variant(reader.read_u8()?)?;
+        let a = reader.read_u8()?;
+        let x = reader.read_u8()?;
+        let y = reader.read_u8()?;
+        let pc = reader.read_u16()?;
+        let sp = reader.read_u8()?;
+        let status = reader.read_u8()?;
+        let cycles = reader.read_u64()?;
+        let irq_line = reader.read_bool()?;
+        let nmi_line = reader.read_bool()?;
+        let bus_nmi = reader.read_bool()?;
+        let nmi_pending = reader.read_bool()?;
+        let requested_interrupt = byte_to_interrupt(reader.read_u8()?)?;
+        let jammed = reader.read_bool()?;
+        let waiting = reader.read_bool()?;
+
+        let mut state = InFlight::new();
+        state.cycle = reader.read_u8()?;
+        state.opcode_addr = reader.read_u16()?;
+        state.opcode = reader.read_u8()?;
+        state.interrupt = byte_to_interrupt(reader.read_u8()?)?;
+        state.base = reader.read_u16()?;
+        state.addr = reader.read_u16()?;
+        state.ptr = reader.read_u8()?;
+        state.data = reader.read_u8()?;
+        state.page_crossed = reader.read_bool()?;
+        state.decimal_fixup = reader.read_bool()?;
+
+        if state.interrupt.is_some() {
+            state.operation = Operation::Brk;
+            state.addr_mode = AddrMode::Impl;
+        } else {
+            let info = opcode_info(state.opcode, variant);
+            state.operation = info.operation;
+            state.addr_mode = info.addr_mode;
+        }
 
-        match addr_mode {
-            AddrMode::ZeroPage => {
-                old_carry = self.sr.carry as u8;
-                data_addr = self.get_zero_page_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 5;
-            }
-            AddrMode::ZeroPageX => {
-                old_carry = self.sr.carry as u8;
-                data_addr = self.get_zero_page_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 6;
-            }
-            AddrMode::Abs => {
-                old_carry = self.sr.carry as u8;
-                data_addr = self.get_absolute_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 6;
-            }
-            AddrMode::AbsX => {
-                old_carry = self.sr.carry as u8;
-                data_addr = self.get_absolute_x_addr();
-                data = self.memory.read_byte(data_addr);
-                cycles = 7;
-            }
-            _ => panic!("Addressing mode not supported"),
+        // The bus only turns out to be invalid once restored, e.g. when trailing data
+        // follows its state, it is then put back as it was
+        let mut backup = StateWriter::new();
+        self.bus.save_state(&mut backup);
+        let result = self
+            .bus
+            .load_state(&mut reader)
+            .and_then(|()| match reader.remaining() {
+                0 => Ok(()),
+                _ => Err(StateError::Invalid("trailing data")),
+            });
+        if let Err(error) = result {
+            let backup = backup.into_bytes();
+            StateReader::new(&backup)
+                .and_then(|mut reader| self.bus.load_state(&mut reader))
+                .expect("The bus accepts the state it just saved");
+            return Err(error);
         }
 
-        self.sr.carry = (data & MASK_LSB) != 0;
+        self.variant = variant;
+        self.a = a;
+        self.x = x;
+        self.y = y;
+        self.pc = pc;
+        self.stack.sp = sp;
+        self.sr.set_status_byte(status);
+        self.cycles = cycles;
+        self.irq_line = irq_line;
+        self.nmi_line = nmi_line;
+        self.bus_nmi = bus_nmi;
+        self.nmi_pending = nmi_pending;
+        self.requested_interrupt = requested_interrupt;
+        self.jammed = jammed;
+        self.waiting = waiting;
+        self.fault = None;
+        self.state = state;
+
+        Ok(())
+    }
+
+    /// Writes the machine to a save state file, see [`Cpu::snapshot`]
+    ///
+    /// ### Parameters:
+    /// * `path` - The file to create or overwrite
+    pub fn save_state<P: AsRef<Path>>(&self, path: P) -> Result<(), StateError> {
+        fs::write(path, self.snapshot())?;
+        Ok(())
+    }
+
+    /// Restores the machine from a save state file, see [`Cpu::restore`]
+    ///
+    /// ### Parameters:
+    /// * `path` - The file written by [`Cpu::save_state`]
+    pub fn load_state<P: AsRef<Path>>(&mut self, path: P) -> Result<(), StateError> {
+        let data = fs::read(path)?;
+        self.restore(&data)
+    }
+}
 
-        data = (data >> 1) | (old_carry << 7);
+fn variant_to_byte(variant: CpuVariant) -> u8 {
+    CpuVariant::ALL
+        .iter()
+        .position(|&candidate| candidate == variant)
+        .unwrap() as u8
+}
 
-        self.set_zero_and_negative_flags(data);
+fn byte_to_variant(byte: u8) -> Result<CpuVariant, StateError> {
+    CpuVariant::ALL
+        .get(byte as usize)
+        .copied()
+        .ok_or(StateError::Invalid("CPU variant"))
+}
 
-        self.memory.write_byte(data_addr, data);
+fn interrupt_to_byte(interrupt: Option<Interrupt>) -> u8 {
+    match interrupt {
+        None => 0,
+        Some(Interrupt::Reset) => 1,
+        Some(Interrupt::Nmi) => 2,
+        Some(Interrupt::Irq) => 3,
+    }
+}
 
-        cycles
+fn byte_to_interrupt(byte: u8) -> Result<Option<Interrupt>, StateError> {
+    match byte {
+        0 => Ok(None),
+        1 => Ok(Some(Interrupt::Reset)),
+        2 => Ok(Some(Interrupt::Nmi)),
+        3 => Ok(Some(Interrupt::Irq)),
+        _ => Err(StateError::Invalid("interrupt")),
     }
+}
 
-    fn sbc(&mut self, addr_mode: AddrMode) -> u8 {
-        let cycles: u8;
-        let data_addr: u16;
-        let result: u16;
-        let mut data: u8;
+fn is_bcd_valid(value: u8) -> bool {
+    ((value >> 4) <= 0x09) && ((value & 0x0F) <= 0x09)
+}
 
-        match addr_mode {
-            AddrMode::Immediate => {
-                data_addr = self.pc;
-                self.pc += 1;
-                cycles = 2;
-            }
-            AddrMode::ZeroPage => {
-                data_addr = self.get_zero_page_addr();
-                cycles = 3;
-            }
-            AddrMode::ZeroPageX => {
-                data_addr = self.get_zero_page_x_addr();
-                cycles = 4;
-            }
-            AddrMode::Abs => {
-                data_addr = self.get_absolute_addr();
-                cycles = 4;
-            }
-            AddrMode::AbsX => {
-                data_addr = self.get_absolute_x_addr();
-                cycles = 4 + (data_addr > 0x00FF) as u8;
-            }
-            AddrMode::AbsY => {
-                data_addr = self.get_zero_page_y_addr();
-                cycles = 4 + (data_addr > 0x00FF) as u8;
-            }
-            AddrMode::IndX => {
-                data_addr = self.get_indirect_x_addr();
-                cycles = 6;
-            }
-            AddrMode::IndY => {
-                data_addr = self.get_indirect_y_addr();
-                cycles = 5 + (data_addr > 0x00FF) as u8;
-            }
-            _ => panic!("Adressing mode not supported"),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::RegionKind;
+    use crate::memory::RomWritePolicy;
+    use crate::save_state::{STATE_MAGIC, STATE_VERSION};
+    use crate::status_register::MASK_DECIMAL;
+
+    const ADC_IMMEDIATE: u8 = 0x69;
+    const SBC_IMMEDIATE: u8 = 0xE9;
+
+    /// A, carry, negative, overflow and zero after an instruction
+    type Outcome = (u8, bool, bool, bool, bool);
+
+    /// Runs an immediate ADC or SBC in decimal mode
+    fn decimal(cpu: &mut Cpu, opcode: u8, a: u8, b: u8, carry: bool) -> (Outcome, u8) {
+        cpu.bus.load(0x0200, &[opcode, b]).unwrap();
+        cpu.pc = 0x0200;
+        cpu.a = a;
+        cpu.sr.decimal = true;
+        cpu.sr.carry = carry;
+
+        let step = cpu.execute().unwrap();
+        let outcome = (
+            cpu.a,
+            cpu.sr.carry,
+            cpu.sr.negative,
+            cpu.sr.overflow,
+            cpu.sr.zero,
+        );
+        (outcome, step.cycles)
+    }
+
+    fn to_bcd(value: i32) -> u8 {
+        let value = value.rem_euclid(100) as u8;
+        ((value / 10) << 4) | (value % 10)
+    }
+
+    fn from_bcd(value: u8) -> i32 {
+        (value >> 4) as i32 * 10 + (value & 0x0F) as i32
+    }
+
+    /// Memory running `LDA #$55; STA $E000; NOP` from $0200, with ROM at $E000-$FFFF
+    fn rom_writer(policy: RomWritePolicy) -> Cpu {
+        let mut memory = Memory::new();
+        memory
+            .load(0x0200, &[0xA9, 0x55, 0x8D, 0x00, 0xE0, 0xEA])
+            .unwrap();
+        memory.load(0xE000, &[0xAA]).unwrap();
+        memory.set_region(0xE000..=0xFFFF, RegionKind::Rom);
+        memory.set_rom_write_policy(policy);
+
+        let mut cpu = Cpu::with_bus(memory);
+        cpu.pc = 0x0200;
+        cpu
+    }
+
+    #[test]
+    fn rom_write_breaks_the_cpu() {
+        let mut cpu = rom_writer(RomWritePolicy::Break);
+        cpu.poll_rom_faults();
+
+        cpu.execute().unwrap();
+        assert_eq!(
+            cpu.execute(),
+            Err(CpuError::RomWrite {
+                pc: 0x0202,
+                addr: 0xE000,
+                data: 0x55
+            })
+        );
+        assert_eq!(cpu.bus.peek_byte(0xE000), 0xAA);
+
+        // The write was the last cycle of the store, execution goes on after it
+        assert!(cpu.at_instruction_boundary());
+        assert_eq!(cpu.execute().unwrap().pc, 0x0205);
+        assert_eq!(cpu.bus.peek_byte(0xE000), 0xAA);
+    }
+
+    #[test]
+    fn rom_write_ignored_or_logged() {
+        // Without polling, Break only drops the write
+        for policy in [
+            RomWritePolicy::Ignore,
+            RomWritePolicy::Log,
+            RomWritePolicy::Break,
+        ] {
+            let mut cpu = rom_writer(policy);
+            cpu.execute().unwrap();
+            cpu.execute().unwrap();
+
+            assert_eq!(cpu.pc, 0x0205);
+            assert_eq!(cpu.bus.peek_byte(0xE000), 0xAA);
+            assert_eq!(
+                cpu.bus.take_rom_writes().len(),
+                (policy == RomWritePolicy::Log) as usize
+            );
         }
+    }
 
-        data = self.memory.read_byte(data_addr);
+    const R: bool = false;
+    const W: bool = true;
 
-        if self.sr.decimal && (!is_bcd_valid(data) || !is_bcd_valid(self.a)) {
-            panic!("Illegal BCD operand");
-        }
+    /// 64KB of RAM listing every access as (address, data, write)
+    struct Tracer {
+        ram: Vec<u8>,
+        accesses: Vec<(u16, u8, bool)>,
+    }
 
-        if self.sr.decimal {
-            data = hex_as_dec(data);
-            self.a = hex_as_dec(self.a);
+    impl Bus for Tracer {
+        fn read_byte(&mut self, addr: u16) -> u8 {
+            let data = self.ram[addr as usize];
+            self.accesses.push((addr, data, R));
+            data
         }
 
-        result = self.a.wrapping_sub(data) as u16;
+        fn write_byte(&mut self, addr: u16, data: u8) {
+            self.ram[addr as usize] = data;
+            self.accesses.push((addr, data, W));
+        }
 
-        self.sr.carry = result < 0x100;
-        self.sr.overflow = ((self.a | result as u8) & (data | result as u8) & 0x80) != 0;
-        self.set_zero_and_negative_flags(result as u8);
+        fn peek_byte(&self, addr: u16) -> u8 {
+            self.ram[addr as usize]
+        }
+    }
 
-        self.a = result as u8;
+    /// A CPU about to run `program` from $0200, with the given bytes in RAM
+    fn traced(variant: CpuVariant, program: &[u8], ram: &[(u16, u8)]) -> Cpu<Tracer> {
+        let mut tracer = Tracer {
+            ram: vec![0; 0x10000],
+            accesses: Vec::new(),
+        };
+        tracer.ram[0x0200..0x0200 + program.len()].copy_from_slice(program);
+        for &(addr, data) in ram {
+            tracer.ram[addr as usize] = data;
+        }
 
-        cycles
+        let mut cpu = Cpu::with_variant(tracer, variant);
+        cpu.pc = 0x0200;
+        cpu.stack.sp = 0xFD;
+        cpu
+    }
+
+    /// Executes one instruction and returns its cycle count and bus accesses
+    fn trace(cpu: &mut Cpu<Tracer>) -> (u8, Vec<(u16, u8, bool)>) {
+        cpu.bus.accesses.clear();
+        let step = cpu.execute().unwrap();
+        (step.cycles, std::mem::take(&mut cpu.bus.accesses))
+    }
+
+    #[test]
+    fn nmos_read_modify_write_writes_twice() {
+        // INC $1000
+        let mut cpu = traced(CpuVariant::Nmos6502, &[0xEE, 0x00, 0x10], &[(0x1000, 0x41)]);
+
+        assert_eq!(
+            trace(&mut cpu),
+            (
+                6,
+                vec![
+                    (0x0200, 0xEE, R),
+                    (0x0201, 0x00, R),
+                    (0x0202, 0x10, R),
+                    (0x1000, 0x41, R),
+                    (0x1000, 0x41, W),
+                    (0x1000, 0x42, W),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn cmos_read_modify_write_reads_twice() {
+        // INC $1000
+        let mut cpu = traced(CpuVariant::Wdc65C02, &[0xEE, 0x00, 0x10], &[(0x1000, 0x41)]);
+
+        assert_eq!(
+            trace(&mut cpu),
+            (
+                6,
+                vec![
+                    (0x0200, 0xEE, R),
+                    (0x0201, 0x00, R),
+                    (0x0202, 0x10, R),
+                    (0x1000, 0x41, R),
+                    (0x1000, 0x41, R),
+                    (0x1000, 0x42, W),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn ticks_run_one_cycle_each() {
+        // ASL $10,X
+        let mut cpu = traced(CpuVariant::Nmos6502, &[0x16, 0x10], &[(0x0011, 0x81)]);
+        cpu.x = 1;
+
+        for _ in 0..5 {
+            cpu.tick().unwrap();
+            assert!(!cpu.at_instruction_boundary());
+        }
+        cpu.tick().unwrap();
+
+        assert!(cpu.at_instruction_boundary());
+        assert_eq!(cpu.cycles, 6);
+        assert_eq!(cpu.bus.ram[0x0011], 0x02);
+        assert!(cpu.sr.carry);
+        assert_eq!(
+            cpu.bus.accesses,
+            vec![
+                (0x0200, 0x16, R),
+                (0x0201, 0x10, R),
+                (0x0010, 0x00, R),
+                (0x0011, 0x81, R),
+                (0x0011, 0x81, W),
+                (0x0011, 0x02, W),
+            ]
+        );
+    }
+
+    #[test]
+    fn nmos_page_crossing_reads_the_unfixed_address() {
+        // LDA $02FF,X
+        let mut cpu = traced(CpuVariant::Nmos6502, &[0xBD, 0xFF, 0x02], &[(0x0300, 0x07)]);
+        cpu.x = 1;
+
+        assert_eq!(
+            trace(&mut cpu),
+            (
+                5,
+                vec![
+                    (0x0200, 0xBD, R),
+                    (0x0201, 0xFF, R),
+                    (0x0202, 0x02, R),
+                    (0x0200, 0xBD, R),
+                    (0x0300, 0x07, R),
+                ]
+            )
+        );
+        assert_eq!(cpu.a, 0x07);
+    }
+
+    #[test]
+    fn cmos_page_crossing_rereads_the_operand() {
+        // LDA $02FF,X
+        let mut cpu = traced(CpuVariant::Wdc65C02, &[0xBD, 0xFF, 0x02], &[(0x0300, 0x07)]);
+        cpu.x = 1;
+
+        assert_eq!(
+            trace(&mut cpu),
+            (
+                5,
+                vec![
+                    (0x0200, 0xBD, R),
+                    (0x0201, 0xFF, R),
+                    (0x0202, 0x02, R),
+                    (0x0202, 0x02, R),
+                    (0x0300, 0x07, R),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn indexed_store_always_reads_first() {
+        // STA $0280,X, without crossing a page
+        let mut cpu = traced(CpuVariant::Nmos6502, &[0x9D, 0x80, 0x02], &[]);
+        cpu.x = 1;
+        cpu.a = 0x5A;
+
+        assert_eq!(
+            trace(&mut cpu),
+            (
+                5,
+                vec![
+                    (0x0200, 0x9D, R),
+                    (0x0201, 0x80, R),
+                    (0x0202, 0x02, R),
+                    (0x0281, 0x00, R),
+                    (0x0281, 0x5A, W),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn page_crossing_penalties() {
+        // Program, X, Y, cycles, page crossed
+        let expected: [(&[u8], u8, u8, u8, bool); 12] = [
+            // BEQ with Z clear, not taken
+            (&[0xF0, 0x80], 0, 0, 2, false),
+            // BNE to the same page, then to the previous one
+            (&[0xD0, 0x02], 0, 0, 3, false),
+            (&[0xD0, 0x80], 0, 0, 4, true),
+            // LDA $12F0, X
+            (&[0xBD, 0xF0, 0x12], 0x0F, 0, 4, false),
+            (&[0xBD, 0xF0, 0x12], 0x10, 0, 5, true),
+            // LDA $12F0, Y
+            (&[0xB9, 0xF0, 0x12], 0, 0x0F, 4, false),
+            (&[0xB9, 0xF0, 0x12], 0, 0x10, 5, true),
+            // LDA ($10), Y with $10 pointing to $12F0
+            (&[0xB1, 0x10], 0, 0x0F, 5, false),
+            (&[0xB1, 0x10], 0, 0x10, 6, true),
+            // STA $12F0, X always takes the fix-up cycle
+            (&[0x9D, 0xF0, 0x12], 0x0F, 0, 5, false),
+            (&[0x9D, 0xF0, 0x12], 0x10, 0, 5, true),
+            // INC $12F0, X as well
+            (&[0xFE, 0xF0, 0x12], 0x10, 0, 7, true),
+        ];
+
+        for variant in [CpuVariant::Nmos6502, CpuVariant::Wdc65C02] {
+            for (program, x, y, cycles, page_crossed) in expected {
+                let mut cpu = traced(variant, program, &[(0x0010, 0xF0), (0x0011, 0x12)]);
+                cpu.x = x;
+                cpu.y = y;
+
+                let step = cpu.execute().unwrap();
+                assert_eq!(
+                    (step.cycles, step.page_crossed),
+                    (cycles, page_crossed),
+                    "{} {:02X?} with X=${:02X}, Y=${:02X}",
+                    variant,
+                    program,
+                    x,
+                    y
+                );
+            }
+        }
     }
 
-    fn sec(&mut self) -> u8 {
-        self.sr.carry = true;
+    #[test]
+    fn indirect_jump_page_wrap() {
+        // JMP ($10FF)
+        let ram = [(0x10FF, 0x34), (0x1000, 0x56), (0x1100, 0x12)];
 
-        2
-    }
+        let mut nmos = traced(CpuVariant::Nmos6502, &[0x6C, 0xFF, 0x10], &ram);
+        nmos.execute().unwrap();
+        assert_eq!(nmos.pc, 0x5634);
 
-    fn sed(&mut self) -> u8 {
-        self.sr.decimal = true;
+        for variant in [CpuVariant::Wdc65C02, CpuVariant::Rockwell65C02] {
+            let mut cmos = traced(variant, &[0x6C, 0xFF, 0x10], &ram);
+            cmos.execute().unwrap();
+            assert_eq!(cmos.pc, 0x1234, "{}", variant);
+        }
+    }
 
-        2
+    #[test]
+    fn address_wrap_around() {
+        for variant in [CpuVariant::Nmos6502, CpuVariant::Wdc65C02] {
+            // LDA ($FF), Y takes its pointer from $FF and $00
+            let mut cpu = traced(
+                variant,
+                &[0xB1, 0xFF],
+                &[
+                    (0x00FF, 0x33),
+                    (0x0000, 0x12),
+                    (0x0100, 0x56),
+                    (0x1234, 0x42),
+                ],
+            );
+            cpu.y = 0x01;
+            cpu.execute().unwrap();
+            assert_eq!(cpu.a, 0x42, "{}", variant);
+
+            // LDA $FFF0, X reads $0010
+            let mut cpu = traced(variant, &[0xBD, 0xF0, 0xFF], &[(0x0010, 0x42)]);
+            cpu.x = 0x20;
+            cpu.execute().unwrap();
+            assert_eq!(cpu.a, 0x42, "{}", variant);
+
+            // LDA #$42 at $FFFF takes its operand from $0000
+            let mut cpu = traced(variant, &[], &[(0xFFFF, 0xA9), (0x0000, 0x42)]);
+            cpu.pc = 0xFFFF;
+            cpu.execute().unwrap();
+            assert_eq!((cpu.pc, cpu.a), (0x0001, 0x42), "{}", variant);
+        }
     }
 
-    fn sei(&mut self) -> u8 {
-        self.sr.interrupt_disable = true;
+    #[test]
+    fn interrupt_sequence_cycles() {
+        // NOP, the IRQ is taken before it
+        let mut cpu = traced(
+            CpuVariant::Nmos6502,
+            &[0xEA],
+            &[(IRQ_VECTOR, 0x00), (IRQ_VECTOR + 1, 0x80)],
+        );
+        cpu.sr.set_status_byte(0x20);
+        cpu.set_irq_line(true);
+
+        assert_eq!(
+            trace(&mut cpu),
+            (
+                7,
+                vec![
+                    (0x0200, 0xEA, R),
+                    (0x0200, 0xEA, R),
+                    (0x01FD, 0x02, W),
+                    (0x01FC, 0x00, W),
+                    (0x01FB, 0x20, W),
+                    (0xFFFE, 0x00, R),
+                    (0xFFFF, 0x80, R),
+                ]
+            )
+        );
+        assert_eq!(cpu.pc, 0x8000);
+        assert_eq!(cpu.cycles, 7);
+
+        // RESET reads the stack instead of writing it
+        let mut cpu = traced(
+            CpuVariant::Nmos6502,
+            &[],
+            &[(RESET_VECTOR, 0x34), (RESET_VECTOR + 1, 0x12)],
+        );
+        cpu.bus.accesses.clear();
+        assert_eq!(cpu.reset(), 7);
+        assert_eq!(
+            cpu.bus.accesses,
+            vec![
+                (0x0200, 0x00, R),
+                (0x0200, 0x00, R),
+                (0x01FD, 0x00, R),
+                (0x01FC, 0x00, R),
+                (0x01FB, 0x00, R),
+                (0xFFFC, 0x34, R),
+                (0xFFFD, 0x12, R),
+            ]
+        );
+        assert_eq!(cpu.pc, 0x1234);
+        assert_eq!(cpu.stack.sp, 0xFA);
+    }
+
+    /// A CPU about to run NOPs from $0200, with handlers at $8000 (IRQ/BRK), $9000 (NMI)
+    /// and $A000 (RESET)
+    fn interrupted() -> Cpu {
+        let mut memory = Memory::new();
+        memory.load(0x0200, &[0xEA; 4]).unwrap();
+        memory.load(0x8000, &[0xEA; 4]).unwrap();
+        memory.load(0x9000, &[0xEA; 4]).unwrap();
+        memory
+            .load(NMI_VECTOR, &[0x00, 0x90, 0x00, 0xA0, 0x00, 0x80])
+            .unwrap();
+
+        let mut cpu = Cpu::with_bus(memory);
+        cpu.pc = 0x0200;
+        cpu.stack.sp = 0xFD;
+        cpu
+    }
+
+    /// Runs the interrupt started by `trigger`, from a status register with every flag
+    /// cleared
+    fn interrupt(trigger: fn(&mut Cpu)) -> Cpu {
+        let mut cpu = interrupted();
+        cpu.sr.set_status_byte(0x00);
+        trigger(&mut cpu);
+        cpu.execute().unwrap();
+
+        assert_eq!(cpu.stack.sp, 0xFA);
+        assert!(cpu.sr.interrupt_disable);
+        cpu
+    }
+
+    /// Returns the return address and the status pushed by an interrupt
+    fn pushed(cpu: &Cpu) -> (u16, u8) {
+        let low = cpu.bus.peek_byte(0x01FC);
+        let high = cpu.bus.peek_byte(0x01FD);
+        (u16::from_le_bytes([low, high]), cpu.bus.peek_byte(0x01FB))
+    }
+
+    #[test]
+    fn pushed_status_bits() {
+        // B is only set by BRK, bit 5 always reads as set
+        let brk = interrupt(|cpu| cpu.bus.write_byte(0x0200, 0x00));
+        assert_eq!((brk.pc, pushed(&brk)), (0x8000, (0x0202, 0x30)));
+
+        let irq = interrupt(|cpu| cpu.set_irq_line(true));
+        assert_eq!((irq.pc, pushed(&irq)), (0x8000, (0x0200, 0x20)));
+
+        let nmi = interrupt(|cpu| cpu.set_nmi_line(true));
+        assert_eq!((nmi.pc, pushed(&nmi)), (0x9000, (0x0200, 0x20)));
+
+        // PHP pushes B set as well
+        let mut cpu = interrupted();
+        cpu.bus.write_byte(0x0200, 0x08);
+        cpu.sr.set_status_byte(0xC3);
+        cpu.execute().unwrap();
+        assert_eq!(cpu.bus.peek_byte(0x01FD), 0xF3);
+    }
+
+    #[test]
+    fn irq_is_masked_by_i() {
+        let mut cpu = interrupted();
+        cpu.sr.interrupt_disable = true;
+        cpu.set_irq_line(true);
+
+        assert_eq!(cpu.execute().unwrap().opcode, 0xEA);
+        assert_eq!(cpu.pc, 0x0201);
+
+        // The line is a level, the interrupt is taken as soon as I clears
+        cpu.sr.interrupt_disable = false;
+        cpu.execute().unwrap();
+        assert_eq!(cpu.pc, 0x8000);
+
+        // The handler runs with I set, so it isn't interrupted again
+        cpu.execute().unwrap();
+        assert_eq!(cpu.pc, 0x8001);
+    }
+
+    #[test]
+    fn nmi_triggers_once_per_edge() {
+        let mut cpu = interrupted();
+        cpu.sr.interrupt_disable = true;
+        cpu.set_nmi_line(true);
+
+        // I doesn't mask NMI
+        cpu.execute().unwrap();
+        assert_eq!(cpu.pc, 0x9000);
+
+        // Holding the line asserted doesn't trigger it again
+        cpu.set_nmi_line(true);
+        cpu.execute().unwrap();
+        cpu.execute().unwrap();
+        assert_eq!(cpu.pc, 0x9002);
+
+        cpu.set_nmi_line(false);
+        cpu.execute().unwrap();
+        assert_eq!(cpu.pc, 0x9003);
+
+        cpu.set_nmi_line(true);
+        cpu.execute().unwrap();
+        assert_eq!(cpu.pc, 0x9000);
+        assert_eq!(cpu.stack.sp, 0xF7);
+    }
+
+    #[test]
+    fn reset_loads_the_vector_and_sets_i() {
+        let mut cpu = interrupted();
+        cpu.sr.set_status_byte(0x00);
+        cpu.a = 0x12;
+
+        assert_eq!(cpu.reset(), 7);
+        assert_eq!(cpu.pc, 0xA000);
+        assert!(cpu.sr.interrupt_disable);
+        assert_eq!(cpu.stack.sp, 0xFA);
+        assert_eq!(cpu.a, 0x12);
+        // Nothing was pushed
+        assert_eq!(cpu.bus.peek_byte(0x01FB), 0x00);
+    }
+
+    #[test]
+    fn ricoh_2a03_ignores_decimal_mode() {
+        let mut ricoh = Cpu::with_variant(Memory::new(), CpuVariant::Ricoh2A03);
+        let mut nmos = Cpu::new();
+
+        for opcode in [ADC_IMMEDIATE, SBC_IMMEDIATE] {
+            for a in 0..=255 {
+                for b in 0..=255 {
+                    for carry in [false, true] {
+                        // D is set on the 2A03 only, both must compute in binary
+                        let results =
+                            [(&mut ricoh, true), (&mut nmos, false)].map(|(cpu, decimal)| {
+                                cpu.bus.load(0x0200, &[opcode, b]).unwrap();
+                                cpu.pc = 0x0200;
+                                cpu.a = a;
+                                cpu.sr.decimal = decimal;
+                                cpu.sr.carry = carry;
+
+                                let cycles = cpu.execute().unwrap().cycles;
+                                (cpu.a, cpu.sr.get_status_byte() & !MASK_DECIMAL, cycles)
+                            });
+
+                        assert_eq!(
+                            results[0], results[1],
+                            "opcode ${:02X} with A=${:02X}, operand ${:02X}, carry {}",
+                            opcode, a, b, carry
+                        );
+                    }
+                }
+            }
+        }
 
-        2
+        // The flag itself still exists
+        assert!(ricoh.sr.decimal);
+    }
+
+    #[test]
+    fn decimal_valid_operands() {
+        let digits = (0..100).map(to_bcd);
+
+        for variant in [CpuVariant::Nmos6502, CpuVariant::Wdc65C02] {
+            let mut cpu = Cpu::with_variant(Memory::new(), variant);
+            let cycles = if variant.is_cmos() { 3 } else { 2 };
+
+            for a in digits.clone() {
+                for b in digits.clone() {
+                    for carry in [false, true] {
+                        let c = carry as i32;
+
+                        // A and C follow decimal arithmetic. Z comes from the binary
+                        // result on the NMOS 6502, and N and Z from A on the 65C02.
+                        let sum = from_bcd(a) + from_bcd(b) + c;
+                        let binary = a.wrapping_add(b).wrapping_add(c as u8);
+                        let ((result, carry_out, negative, _, zero), step_cycles) =
+                            decimal(&mut cpu, ADC_IMMEDIATE, a, b, carry);
+                        let context = format!("{} ${:02X} + ${:02X} + {}", variant, a, b, c);
+                        assert_eq!(
+                            (result, carry_out),
+                            (to_bcd(sum), sum >= 100),
+                            "{}",
+                            context
+                        );
+                        if variant.is_cmos() {
+                            assert_eq!(
+                                (negative, zero),
+                                (result >= 0x80, result == 0),
+                                "{}",
+                                context
+                            );
+                        } else {
+                            assert_eq!(zero, binary == 0, "{}", context);
+                        }
+                        assert_eq!(step_cycles, cycles, "{}", context);
+
+                        // SBC takes C and V from the binary difference on both
+                        let difference = from_bcd(a) - from_bcd(b) - (1 - c);
+                        let binary = a.wrapping_sub(b).wrapping_sub(1 - c as u8);
+                        let ((result, carry_out, negative, overflow, zero), step_cycles) =
+                            decimal(&mut cpu, SBC_IMMEDIATE, a, b, carry);
+                        let context = format!("{} ${:02X} - ${:02X} - {}", variant, a, b, 1 - c);
+                        let flags = if variant.is_cmos() { result } else { binary };
+                        assert_eq!(
+                            (result, carry_out, negative, overflow, zero),
+                            (
+                                to_bcd(difference),
+                                difference >= 0,
+                                flags >= 0x80,
+                                (a ^ b) & (a ^ binary) & 0x80 != 0,
+                                flags == 0
+                            ),
+                            "{}",
+                            context
+                        );
+                        assert_eq!(step_cycles, cycles, "{}", context);
+                    }
+                }
+            }
+        }
     }
-}
-
-fn hex_as_dec(value: u8) -> u8 {
-    10 * (value >> 4) + (value & 0x0F)
-}
 
-fn dec_as_hex(value: u8) -> u8 {
-    16 * (value / 10) + (value % 10)
-}
+    #[test]
+    fn decimal_flags_and_invalid_operands() {
+        // Opcode, A, operand, carry, then A, C, N, V, Z on the NMOS 6502 and the
+        // 65C02, worked out by hand from the sequences Bruce Clark measured on
+        // silicon in "Decimal Mode" on 6502.org
+        #[rustfmt::skip]
+        let expected = [
+            (ADC_IMMEDIATE, 0x58, 0x46, true, (0x05, true, true, true, false), (0x05, true, false, true, false)),
+            (ADC_IMMEDIATE, 0x99, 0x01, false, (0x00, true, true, false, false), (0x00, true, false, false, true)),
+            (ADC_IMMEDIATE, 0x79, 0x00, true, (0x80, false, true, true, false), (0x80, false, true, true, false)),
+            (ADC_IMMEDIATE, 0x0F, 0x00, false, (0x15, false, false, false, false), (0x15, false, false, false, false)),
+            (ADC_IMMEDIATE, 0xA0, 0x00, false, (0x00, true, true, false, false), (0x00, true, false, false, true)),
+            (ADC_IMMEDIATE, 0xFF, 0xFF, true, (0x55, true, true, false, false), (0x55, true, false, false, false)),
+            (SBC_IMMEDIATE, 0x12, 0x21, true, (0x91, false, true, false, false), (0x91, false, true, false, false)),
+            (SBC_IMMEDIATE, 0x00, 0x01, true, (0x99, false, true, false, false), (0x99, false, true, false, false)),
+            (SBC_IMMEDIATE, 0x80, 0x01, true, (0x79, true, false, true, false), (0x79, true, false, true, false)),
+            (SBC_IMMEDIATE, 0x20, 0x0F, true, (0x1B, true, false, false, false), (0x0B, true, false, false, false)),
+            (SBC_IMMEDIATE, 0x00, 0x0A, true, (0x90, false, true, false, false), (0x90, false, true, false, false)),
+        ];
+
+        for (opcode, a, b, carry, nmos, cmos) in expected {
+            for (variant, outcome) in [(CpuVariant::Nmos6502, nmos), (CpuVariant::Wdc65C02, cmos)] {
+                let mut cpu = Cpu::with_variant(Memory::new(), variant);
+                assert_eq!(
+                    decimal(&mut cpu, opcode, a, b, carry).0,
+                    outcome,
+                    "{} opcode ${:02X} with A=${:02X}, operand ${:02X}, carry {}",
+                    variant,
+                    opcode,
+                    a,
+                    b,
+                    carry
+                );
+            }
+        }
+    }
 
-fn is_bcd_valid(value: u8) -> bool {
-    ((value >> 4) <= 0x09) && ((value & 0x0F) <= 0x09)
+    /// 65C02 running `INC $1000` from $0200, stopped on the fifth of its six cycles with
+    /// an IRQ raised during the instruction
+    fn mid_instruction() -> Cpu {
+        let mut memory = Memory::new();
+        memory.load(0x0200, &[0xEE, 0x00, 0x10]).unwrap();
+        memory.load(0x1000, &[0x41]).unwrap();
+        memory.load(IRQ_VECTOR, &[0x00, 0x80]).unwrap();
+
+        let mut cpu = Cpu::with_variant(memory, CpuVariant::Wdc65C02);
+        cpu.pc = 0x0200;
+        (cpu.a, cpu.x, cpu.y, cpu.stack.sp) = (0x12, 0x34, 0x56, 0xF0);
+        cpu.sr.set_status_byte(0xE1);
+        cpu.tick().unwrap();
+        cpu.set_irq_line(true);
+        for _ in 0..4 {
+            cpu.tick().unwrap();
+        }
+        assert!(!cpu.at_instruction_boundary());
+        cpu
+    }
+
+    /// Registers, cycle count and the byte at $1000
+    fn machine_state(cpu: &Cpu) -> (u16, u8, u8, u8, u8, u8, u64, u8) {
+        (
+            cpu.pc,
+            cpu.a,
+            cpu.x,
+            cpu.y,
+            cpu.stack.sp,
+            cpu.sr.get_status_byte(),
+            cpu.cycles,
+            cpu.bus.peek_byte(0x1000),
+        )
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let mut original = mid_instruction();
+        let snapshot = original.snapshot();
+
+        let mut restored = Cpu::new();
+        restored.restore(&snapshot).unwrap();
+        assert_eq!(restored.variant(), CpuVariant::Wdc65C02);
+        assert_eq!(machine_state(&restored), machine_state(&original));
+        assert_eq!(restored.snapshot(), snapshot);
+
+        // Both finish the INC the same way, then take the pending IRQ
+        for cpu in [&mut original, &mut restored] {
+            cpu.tick().unwrap();
+            assert!(cpu.at_instruction_boundary());
+            assert_eq!(cpu.bus.peek_byte(0x1000), 0x42);
+            cpu.execute().unwrap();
+        }
+        assert_eq!(machine_state(&restored), machine_state(&original));
+        assert_eq!(restored.pc, 0x8000);
+    }
+
+    #[test]
+    fn restore_rejects_foreign_data() {
+        let mut cpu = mid_instruction();
+        let before = machine_state(&cpu);
+
+        let mut snapshot = cpu.snapshot();
+        snapshot[0] ^= 0xFF;
+        assert!(matches!(cpu.restore(&snapshot), Err(StateError::NotAState)));
+
+        let mut snapshot = cpu.snapshot();
+        let version = STATE_MAGIC.len();
+        snapshot[version..version + 2].copy_from_slice(&(STATE_VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            cpu.restore(&snapshot),
+            Err(StateError::UnsupportedVersion(version)) if version == STATE_VERSION + 1
+        ));
+
+        let snapshot = cpu.snapshot();
+        assert!(matches!(
+            cpu.restore(&snapshot[..snapshot.len() - 1]),
+            Err(StateError::Truncated)
+        ));
+        assert_eq!(machine_state(&cpu), before);
+    }
+
+    #[test]
+    fn failed_restore_rolls_back() {
+        let mut cpu = mid_instruction();
+        let mut snapshot = cpu.snapshot();
+        snapshot.push(0x00);
+
+        cpu.bus.poke_byte(0x1000, 0x99);
+        cpu.a = 0x00;
+        let before = machine_state(&cpu);
+
+        // The bus state is read before the trailing byte is found
+        assert!(matches!(
+            cpu.restore(&snapshot),
+            Err(StateError::Invalid("trailing data"))
+        ));
+        assert_eq!(machine_state(&cpu), before);
+        assert!(!cpu.at_instruction_boundary());
+    }
 }
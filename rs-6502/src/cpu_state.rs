@@ -1,6 +1,6 @@
 use std::fmt;
 
-use crate::cpu::Cpu;
+use crate::{bus::Bus, cpu::Cpu};
 
 /// Represents the state of the CPU at a given point in time, used to display the
 /// current and previous state of the CPU
@@ -30,13 +30,12 @@ pub struct CpuState {
     // Carry flag
     pub carry: bool,
     // Number of cycles used
-    pub cycles: u32,
-    /// Next instruction to be executed.
-    pub next_instruction: u8,
+    pub cycles: u64,
 }
 
 impl CpuState {
-    pub fn new(cpu: &Cpu) -> CpuState {
+    /// Takes a snapshot of the CPU registers
+    pub fn new<B: Bus>(cpu: &Cpu<B>) -> CpuState {
         CpuState {
             a: cpu.a,
             x: cpu.x,
@@ -50,8 +49,7 @@ impl CpuState {
             interrupt_disable: cpu.sr.get_interrupt_disable(),
             zero: cpu.sr.get_zero(),
             carry: cpu.sr.get_carry(),
-            cycles: 0,
-            next_instruction: cpu.memory.read_byte(cpu.pc),
+            cycles: cpu.cycles,
         }
     }
 }
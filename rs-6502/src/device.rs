@@ -0,0 +1,72 @@
+use crate::save_state::{StateError, StateReader, StateWriter};
+
+/// A memory-mapped peripheral, attached to an address range with [`Memory::map`]
+///
+/// Offsets are relative to the first address of the range the device is mapped at,
+/// so the same device can be mapped anywhere.
+///
+/// [`Memory::map`]: crate::memory::Memory::map
+pub trait Device {
+    /// Handles a CPU read, which may have side effects such as clearing a status flag
+    ///
+    /// ### Parameters:
+    /// * `offset` - The address read, relative to the start of the device range
+    ///
+    /// ### Returns:
+    /// * The byte the device puts on the data bus
+    fn read(&mut self, offset: u16) -> u8;
+
+    /// Handles a CPU write
+    ///
+    /// ### Parameters:
+    /// * `offset` - The address written, relative to the start of the device range
+    /// * `value` - The byte written
+    fn write(&mut self, offset: u16, value: u8);
+
+    /// Returns what a read would return without its side effects, used by the debugger
+    /// and the disassembler. Devices that can't tell default to 0.
+    ///
+    /// ### Parameters:
+    /// * `offset` - The address read, relative to the start of the device range
+    fn peek(&self, offset: u16) -> u8 {
+        let _ = offset;
+        0
+    }
+
+    /// Advances the device alongside the CPU
+    ///
+    /// ### Parameters:
+    /// * `cycles` - The number of CPU cycles elapsed since the last call
+    fn tick(&mut self, cycles: u64) {
+        let _ = cycles;
+    }
+
+    /// Returns whether the device pulls the IRQ line, the CPU polls it before every
+    /// instruction
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Returns whether the device pulls the NMI line, the CPU takes an interrupt on
+    /// every transition to asserted
+    fn nmi(&self) -> bool {
+        false
+    }
+
+    /// Appends the device state to a save state
+    ///
+    /// ### Parameters:
+    /// * `writer` - The save state being built
+    fn save_state(&self, writer: &mut StateWriter) {
+        let _ = writer;
+    }
+
+    /// Restores what [`Device::save_state`] saved
+    ///
+    /// ### Parameters:
+    /// * `reader` - The save state, positioned where the device state starts
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let _ = reader;
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use std::{error::Error, fmt};
+
+/// Enumerates the conditions that stop the CPU from executing an instruction
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CpuError {
+    /// An undocumented opcode was fetched while strict mode is enabled. The PC is left
+    /// pointing at the opcode.
+    IllegalOpcode { pc: u16, opcode: u8 },
+    /// JAM or STP halted the CPU, only a reset gets it running again
+    Jammed { pc: u16 },
+    /// ADC or SBC ran in decimal mode on a byte that is not valid BCD while strict mode
+    /// is enabled. The accumulator and flags are left untouched.
+    InvalidDecimalOperand { pc: u16, a: u8, operand: u8 },
+    /// The instruction wrote to ROM while the bus stops on such writes. The write was
+    /// ignored and the instruction is left unfinished, the next step completes it.
+    RomWrite { pc: u16, addr: u16, data: u8 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuError::IllegalOpcode { pc, opcode } => {
+                write!(f, "Illegal opcode 0x{:02X} at 0x{:04X}", opcode, pc)
+            }
+            CpuError::Jammed { pc } => write!(f, "CPU halted at 0x{:04X}", pc),
+            CpuError::InvalidDecimalOperand { pc, a, operand } => write!(
+                f,
+                "Invalid BCD operands A: 0x{:02X}, M: 0x{:02X} at 0x{:04X}",
+                a, operand, pc
+            ),
+            CpuError::RomWrite { pc, addr, data } => write!(
+                f,
+                "Write of 0x{:02X} to ROM at 0x{:04X} from 0x{:04X}",
+                data, addr, pc
+            ),
+        }
+    }
+}
+
+impl Error for CpuError {}
+
+/// Enumerates the reasons an address range could not be mapped on the bus
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MapError {
+    /// The range ends before it starts
+    EmptyRange { start: u16, end: u16 },
+    /// The range overlaps the range of something already mapped
+    Overlap { start: u16, end: u16 },
+    /// A mapper was attached without physical memory for its banks
+    NoPhysicalMemory,
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapError::EmptyRange { start, end } => {
+                write!(f, "Empty range 0x{:04X}-0x{:04X}", start, end)
+            }
+            MapError::Overlap { start, end } => write!(
+                f,
+                "Range overlaps the mapping at 0x{:04X}-0x{:04X}",
+                start, end
+            ),
+            MapError::NoPhysicalMemory => write!(f, "No physical memory to bank"),
+        }
+    }
+}
+
+impl Error for MapError {}
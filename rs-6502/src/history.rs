@@ -0,0 +1,286 @@
+use std::collections::VecDeque;
+
+use crate::{
+    bus::{BusWrapper, Inspect, RegionKind},
+    cpu::{CoreState, Cpu, StepInfo},
+    error::CpuError,
+    mapper::BankWindow,
+};
+
+/// Number of instructions kept by [`History::default`]
+pub const DEFAULT_HISTORY_CAPACITY: usize = 10_000;
+
+/// Bus wrapper remembering the value every written byte held before the write, so the
+/// writes of an instruction can be undone
+///
+/// Only the bytes and the banks shown are restored when rewinding: side effects a write
+/// had on a device, such as a character sent to a terminal, cannot be taken back, and
+/// neither can mapper registers not reflected by the bank windows.
+pub struct HistoryBus<B: Inspect> {
+    inner: B,
+    /// Address and previous value of every write since the journal was last taken
+    journal: Vec<(u16, u8)>,
+    /// Writes are only journaled while a [`History`] is recording
+    recording: bool,
+}
+
+impl<B: Inspect> HistoryBus<B> {
+    /// Wraps a bus, writes are not journaled until a [`History`] steps the CPU
+    ///
+    /// ### Parameters:
+    /// * `inner` - The bus doing the actual reads and writes
+    pub fn new(inner: B) -> HistoryBus<B> {
+        HistoryBus {
+            inner,
+            journal: Vec::new(),
+            recording: false,
+        }
+    }
+}
+
+impl<B: Inspect> BusWrapper for HistoryBus<B> {
+    type Inner = B;
+
+    fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    fn inner_mut(&mut self) -> &mut B {
+        &mut self.inner
+    }
+
+    fn observe_write(&mut self, addr: u16, _data: u8) {
+        // Writes to ROM change nothing, there is nothing to undo
+        if self.recording && self.inner.region(addr) != RegionKind::Rom {
+            self.journal.push((addr, self.inner.peek_byte(addr)));
+        }
+    }
+}
+
+/// What one instruction changed: the CPU and the bank windows before it ran, and the
+/// bytes it overwrote
+struct HistoryEntry {
+    core: CoreState,
+    banks: Vec<BankWindow>,
+    writes: Vec<(u16, u8)>,
+}
+
+/// Bounded record of the last instructions executed, used to step backwards. Once
+/// full, the oldest instruction is forgotten for every new one.
+pub struct History {
+    entries: VecDeque<HistoryEntry>,
+    capacity: usize,
+}
+
+impl History {
+    /// Creates an empty history
+    ///
+    /// ### Parameters:
+    /// * `capacity` - The number of instructions that can be undone
+    pub fn new(capacity: usize) -> History {
+        History {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Executes one instruction and records how to undo it
+    ///
+    /// ### Parameters:
+    /// * `cpu` - The CPU to step, attached to a [`HistoryBus`]
+    ///
+    /// ### Returns:
+    /// * What [`Cpu::execute`] returned. The instruction is recorded even when it
+    ///   failed, since it may still have changed the machine.
+    pub fn step<B: Inspect>(&mut self, cpu: &mut Cpu<HistoryBus<B>>) -> Result<StepInfo, CpuError> {
+        let core = cpu.core_state();
+        let banks = cpu.bus.inner.bank_windows();
+
+        cpu.bus.journal.clear();
+        cpu.bus.recording = true;
+        let result = cpu.execute();
+        cpu.bus.recording = false;
+
+        if self.capacity > 0 {
+            if self.entries.len() == self.capacity {
+                self.entries.pop_front();
+            }
+
+            self.entries.push_back(HistoryEntry {
+                core,
+                banks,
+                writes: std::mem::take(&mut cpu.bus.journal),
+            });
+        }
+
+        result
+    }
+
+    /// Undoes the last recorded instruction
+    ///
+    /// ### Parameters:
+    /// * `cpu` - The CPU the instruction was recorded on
+    ///
+    /// ### Returns:
+    /// * `false` if there was nothing left to undo
+    pub fn step_back<B: Inspect>(&mut self, cpu: &mut Cpu<HistoryBus<B>>) -> bool {
+        let Some(entry) = self.entries.pop_back() else {
+            return false;
+        };
+
+        // Memory writes never switch banks, so they all landed in the banks shown before
+        // the instruction. Later writes to the same address must be undone first.
+        cpu.bus.inner.set_bank_windows(&entry.banks);
+        for &(addr, data) in entry.writes.iter().rev() {
+            cpu.bus.inner.poke_byte(addr, data);
+        }
+        cpu.set_core_state(entry.core);
+
+        true
+    }
+
+    /// Undoes instructions until the PC lands on a breakpoint or the history runs out
+    ///
+    /// ### Parameters:
+    /// * `cpu` - The CPU the instructions were recorded on
+    /// * `is_breakpoint` - Tells whether an address is a breakpoint
+    ///
+    /// ### Returns:
+    /// * The number of instructions undone
+    pub fn run_back<B: Inspect, F: Fn(u16) -> bool>(
+        &mut self,
+        cpu: &mut Cpu<HistoryBus<B>>,
+        is_breakpoint: F,
+    ) -> usize {
+        let mut undone = 0;
+
+        while self.step_back(cpu) {
+            undone += 1;
+
+            if is_breakpoint(cpu.pc) {
+                break;
+            }
+        }
+
+        undone
+    }
+
+    /// Forgets every recorded instruction, needed whenever the machine is changed
+    /// outside of [`History::step`], e.g. by a reset or a loaded save state
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Returns the number of instructions that can be undone
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Default for History {
+    fn default() -> History {
+        History::new(DEFAULT_HISTORY_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::mapper::LatchMapper;
+    use crate::memory::Memory;
+
+    /// CPU running `program` from $0200, with a latch at $C000 selecting the bank shown
+    /// at $8000-$BFFF
+    fn machine(program: &[u8]) -> Cpu<HistoryBus<Memory>> {
+        let mut memory = Memory::new();
+        let mapper = LatchMapper::new(
+            0xC000..=0xC000,
+            vec![BankWindow::new(0x8000..=0xBFFF, 0, RegionKind::Ram)],
+            0,
+            2,
+        );
+        memory
+            .set_mapper(Box::new(mapper), vec![0; 2 * 0x4000])
+            .unwrap();
+        memory.load(0x0200, program).unwrap();
+
+        let mut cpu = Cpu::with_bus(HistoryBus::new(memory));
+        cpu.pc = 0x0200;
+        cpu
+    }
+
+    #[test]
+    fn step_back_restores_registers_memory_and_banks() {
+        // LDX #$05; STX $10; LDA #$01; STA $C000; STX $8000
+        let mut cpu = machine(&[
+            0xA2, 0x05, 0x86, 0x10, 0xA9, 0x01, 0x8D, 0x00, 0xC0, 0x8E, 0x00, 0x80,
+        ]);
+        let mut history = History::new(10);
+        for _ in 0..5 {
+            history.step(&mut cpu).unwrap();
+        }
+        assert_eq!(cpu.bus.peek_physical(0x4000), 0x05);
+        assert_eq!(cpu.bus.bank_windows()[0].bank, 1);
+
+        // STX $8000
+        assert!(history.step_back(&mut cpu));
+        assert_eq!(cpu.pc, 0x0209);
+        assert_eq!(cpu.bus.peek_physical(0x4000), 0x00);
+        assert_eq!(cpu.bus.bank_windows()[0].bank, 1);
+
+        // STA $C000
+        assert!(history.step_back(&mut cpu));
+        assert_eq!(cpu.pc, 0x0206);
+        assert_eq!(cpu.bus.bank_windows()[0].bank, 0);
+
+        // LDA #$01
+        assert!(history.step_back(&mut cpu));
+        assert_eq!((cpu.pc, cpu.a, cpu.x), (0x0204, 0x00, 0x05));
+
+        // STX $10
+        assert!(history.step_back(&mut cpu));
+        assert_eq!(cpu.bus.peek_byte(0x0010), 0x00);
+
+        // LDX #$05
+        assert!(history.step_back(&mut cpu));
+        assert_eq!((cpu.pc, cpu.x, cpu.cycles), (0x0200, 0x00, 0));
+        assert!(!history.step_back(&mut cpu));
+    }
+
+    #[test]
+    fn run_back_stops_on_breakpoint() {
+        let mut cpu = machine(&[0xEA; 5]);
+        let mut history = History::new(10);
+        for _ in 0..5 {
+            history.step(&mut cpu).unwrap();
+        }
+
+        assert_eq!(history.run_back(&mut cpu, |addr| addr == 0x0202), 3);
+        assert_eq!(cpu.pc, 0x0202);
+
+        // Without a breakpoint it runs out of history
+        assert_eq!(history.run_back(&mut cpu, |_| false), 2);
+        assert_eq!(cpu.pc, 0x0200);
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn oldest_entry_dropped_at_capacity() {
+        let mut cpu = machine(&[0xEA; 3]);
+        let mut history = History::new(2);
+        for _ in 0..3 {
+            history.step(&mut cpu).unwrap();
+        }
+        assert_eq!(history.len(), 2);
+
+        assert!(history.step_back(&mut cpu));
+        assert!(history.step_back(&mut cpu));
+        assert_eq!(cpu.pc, 0x0201);
+        assert!(!history.step_back(&mut cpu));
+    }
+}
@@ -1,111 +1,71 @@
-use crate::addressing_mode::{get_addr_mode, AddrMode};
+use crate::addressing_mode::AddrMode;
+use crate::bus::Bus;
 use crate::cpu::Cpu;
+use crate::opcode::opcode_info;
 use std::fmt;
 
+/// The instruction at the program counter, decoded for display
 pub struct Instruction {
     name: &'static str,
     description: &'static str,
     opcode: u8,
     addr_mode: String,
+    bytes: u8,
+    cycles: String,
+    flags: String,
     assembly: String,
 }
 
 impl Instruction {
-    pub fn new(cpu: &Cpu) -> Instruction {
-        let next_instruction = cpu.memory.read_byte(cpu.pc);
-        let name_and_desc = instr_get_name_and_description(next_instruction);
-        let addr_mode = instr_addr_mode_str(get_addr_mode(next_instruction));
-        let assembly = instr_disassemble(cpu, name_and_desc.0, &addr_mode);
+    /// Decodes the instruction the CPU is about to execute
+    ///
+    /// ### Parameters:
+    /// * `cpu` - The CPU whose program counter points at the instruction
+    pub fn new<B: Bus>(cpu: &Cpu<B>) -> Instruction {
+        let next_instruction = cpu.bus.peek_byte(cpu.pc);
+        let info = opcode_info(next_instruction, cpu.variant());
+        let assembly = instr_disassemble(cpu, info.mnemonic, info.addr_mode);
+        let cycles = if info.page_cross_penalty {
+            format!("{} (+1 on page cross)", info.cycles)
+        } else {
+            info.cycles.to_string()
+        };
 
         Instruction {
-            name: name_and_desc.0,
-            description: name_and_desc.1,
+            name: info.mnemonic,
+            description: info.description,
             opcode: next_instruction,
-            addr_mode: addr_mode,
-            assembly: assembly,
+            addr_mode: instr_addr_mode_str(info.addr_mode),
+            bytes: info.bytes,
+            cycles,
+            flags: instr_flags_str(info.flags),
+            assembly,
         }
     }
+
+    /// Returns the instruction in assembly syntax, e.g. `LDA #$42`
+    pub fn assembly(&self) -> &str {
+        &self.assembly
+    }
 }
 
 impl fmt::Display for Instruction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "{} - {}\nOPCODE: 0x{:02X}\nADDR: {}\nASSEMBLY:\n{}",
-            self.name, self.description, self.opcode, self.addr_mode, self.assembly
+            "{} - {}\nOPCODE: 0x{:02X}\nADDR: {}\nBYTES: {} CYCLES: {}\nFLAGS: {}\nASSEMBLY:\n{}",
+            self.name,
+            self.description,
+            self.opcode,
+            self.addr_mode,
+            self.bytes,
+            self.cycles,
+            self.flags,
+            self.assembly
         )
     }
 }
 
-/// Returns the name and description of the instruction based on the provided opcode
-///
-/// ### Parameters:
-/// * `opcode` - The opcode of the instruction
-///
-/// ### Returns:
-/// * A tuple containing the name and description of the instruction
-fn instr_get_name_and_description(opcode: u8) -> (&'static str, &'static str) {
-    match opcode {
-        0x00 => ("BRK", "Break"),
-        0x18 => ("CLC", "Clear Carry"),
-        0xD8 => ("CLD", "Clear Decimal"),
-        0x58 => ("CLI", "Clear Interrupt"),
-        0xB8 => ("CLV", "Clear Overflow"),
-        0xEA => ("NOP", "No operation"),
-        0xC8 => ("INY", "Increment Y"),
-        0xE8 => ("INX", "Increment X"),
-        0xA9 | 0xA5 | 0xB5 | 0xAD | 0xBD | 0xB9 | 0xA1 | 0xB1 => ("LDA", "Load Acc"),
-        0xA2 | 0xA6 | 0xB6 | 0xAE | 0xBE => ("LDX", "Load X Reg"),
-        0xA0 | 0xA4 | 0xB4 | 0xAC | 0xBC => ("LDY", "Load Y Reg"),
-        0x4A | 0x46 | 0x56 | 0x4E | 0x5E => ("LSR", "Shift Right"),
-        0x09 | 0x05 | 0x15 | 0x0D | 0x1D | 0x01 | 0x11 => ("ORA", "Inclusive OR"),
-        0x48 => ("PHA", "Push Acc"),
-        0x08 => ("PHP", "Push Proc Status"),
-        0x68 => ("PLA", "Pull Acc"),
-        0x28 => ("PLP", "Pull Proc Status"),
-        0x2A | 0x26 | 0x36 | 0x2E | 0x3E => ("ROL", "Rotate Left"),
-        0x0A | 0x06 | 0x16 | 0x0E | 0x1E => ("ASL", "Shift Left"),
-        0x85 | 0x95 | 0x8D | 0x9D | 0x99 | 0x81 | 0x91 => ("STA", "Store Acc"),
-        0x86 | 0x96 | 0x8E => ("STX", "Store X"),
-        0x84 | 0x94 | 0x8C => ("STY", "Store Y"),
-        0xAA => ("TAX", "Acc -> X"),
-        0xA8 => ("TAY", "Acc -> Y"),
-        0xBA => ("TSX", "SP -> X"),
-        0x8A => ("TXA", "X -> Acc"),
-        0x9A => ("TXS", "X -> SP"),
-        0x98 => ("TYA", "Y -> Acc"),
-        0x69 | 0x65 | 0x75 | 0x6D | 0x7D | 0x79 | 0x61 | 0x71 => ("ADC", "Add w/ Carry"),
-        0x29 | 0x25 | 0x35 | 0x2D | 0x3D | 0x39 | 0x21 | 0x31 => ("AND", "Logical AND"),
-        0x90 => ("BCC", "Branch Carry Clear"),
-        0xB0 => ("BCS", "Branch Carry Set"),
-        0xF0 => ("BEQ", "Branch Zero"),
-        0x30 => ("BMI", "Branch Minus"),
-        0xD0 => ("BNE", "Branch Not Zero"),
-        0x10 => ("BPL", "Branch Plus"),
-        0x50 => ("BVC", "Branch Overflow Clr"),
-        0x70 => ("BVS", "Branch Overflow Set"),
-        0x24 | 0x2c => ("BIT", "Bit Test"),
-        0x20 => ("JSR", "Jump Subroutine"),
-        0x4C | 0x6C => ("JMP", "Jump"),
-        0x60 => ("RTS", "Ret Subroutine"),
-        0x40 => ("RTI", "Ret Interrupt"),
-        0xC9 | 0xC5 | 0xD5 | 0xCD | 0xDD | 0xD9 | 0xC1 | 0xD1 => ("CMP", "Compare"),
-        0xE0 | 0xE4 | 0xEC => ("CPX", "Compare X"),
-        0xC0 | 0xC4 | 0xCC => ("CPY", "Compare Y"),
-        0xC6 | 0xD6 | 0xCE | 0xDE => ("DEC", "Decrement"),
-        0xCA => ("DEX", "Decrement X"),
-        0x88 => ("DEY", "Decrement Y"),
-        0x49 | 0x45 | 0x55 | 0x4D | 0x5D | 0x59 | 0x41 | 0x51 => ("EOR", "Exclusive OR"),
-        0xE6 | 0xF6 | 0xEE | 0xFE => ("INC", "Increment"),
-        0x6A | 0x66 | 0x76 | 0x6E | 0x7E => ("ROR", "Rotate Right"),
-        0xE9 | 0xE5 | 0xF5 | 0xED | 0xFD | 0xF9 | 0xE1 | 0xF1 => ("SBC", "Sub w/ Carry"),
-        0x38 => ("SEC", "Set Carry"),
-        0xF8 => ("SED", "Set Decimal"),
-        0x78 => ("SEI", "Set Interrupt"),
-        _ => panic!("Instruction not implemented: {:#02X}", opcode),
-    }
-}
-
 /// Returns the string representation of the addressing mode
 ///
 /// ### Parameters:
@@ -128,70 +88,186 @@ fn instr_addr_mode_str(addr_mode: AddrMode) -> String {
         AddrMode::ZeroPage => "ZPG",
         AddrMode::ZeroPageX => "ZPG, X",
         AddrMode::ZeroPageY => "ZPG, Y",
+        AddrMode::ZeroPageInd => "ZPG Indirect",
+        AddrMode::AbsIndX => "Abs Idx Indirect",
+        AddrMode::ZeroPageRel => "ZPG, Relative",
     }
     .to_string()
 }
 
+/// Returns the status flags an instruction may change, in status register order
+///
+/// ### Parameters:
+/// * `flags` - The mask of affected flags
+///
+/// ### Returns:
+/// * A string such as `NZC`, or `-` if no flag is affected
+fn instr_flags_str(flags: u8) -> String {
+    let names: String = "NV-BDIZC"
+        .chars()
+        .enumerate()
+        .filter(|(bit, _)| flags & (0x80 >> bit) != 0)
+        .map(|(_, name)| name)
+        .collect();
+
+    if names.is_empty() {
+        "-".to_string()
+    } else {
+        names
+    }
+}
+
 /// Returns the disassembled instruction as a string
 ///
 /// ### Parameters:
 /// * `cpu` - The CPU state
 /// * `operation_name` - The name of the operation
-/// * `addr_mode` - The addressing mode
+/// * `addr_mode` - The addressing mode of the instruction
 ///
 /// ### Returns:
 /// * A string containing the disassembled instruction
-fn instr_disassemble(cpu: &Cpu, operation_name: &str, addr_mode: &String) -> String {
+fn instr_disassemble<B: Bus>(cpu: &Cpu<B>, operation_name: &str, addr_mode: AddrMode) -> String {
     let assembly = String::from(operation_name);
 
-    match addr_mode.as_str() {
-        "Abs" => {
-            let lo = cpu.memory.read_byte(cpu.pc + 1);
-            let hi = cpu.memory.read_byte(cpu.pc + 2);
+    match addr_mode {
+        AddrMode::Abs => {
+            let lo = cpu.bus.peek_byte(cpu.pc.wrapping_add(1));
+            let hi = cpu.bus.peek_byte(cpu.pc.wrapping_add(2));
             format!("{} ${:02X}{:02X}", assembly, hi, lo)
         }
-        "Abs, X" => {
-            let lo = cpu.memory.read_byte(cpu.pc + 1);
-            let hi = cpu.memory.read_byte(cpu.pc + 2);
+        AddrMode::AbsX => {
+            let lo = cpu.bus.peek_byte(cpu.pc.wrapping_add(1));
+            let hi = cpu.bus.peek_byte(cpu.pc.wrapping_add(2));
             format!("{} ${:02X}{:02X}, X", assembly, hi, lo)
         }
-        "Abs, Y" => {
-            let lo = cpu.memory.read_byte(cpu.pc + 1);
-            let hi = cpu.memory.read_byte(cpu.pc + 2);
+        AddrMode::AbsY => {
+            let lo = cpu.bus.peek_byte(cpu.pc.wrapping_add(1));
+            let hi = cpu.bus.peek_byte(cpu.pc.wrapping_add(2));
             format!("{} ${:02X}{:02X}, Y", assembly, hi, lo)
         }
-        "Acc" => format!("{}", assembly),
-        "Imm" => {
-            let value = cpu.memory.read_byte(cpu.pc + 1);
+        AddrMode::Accumulator => assembly,
+        AddrMode::Immediate => {
+            let value = cpu.bus.peek_byte(cpu.pc.wrapping_add(1));
             format!("{} #${:02X}", assembly, value)
         }
-        "Implied" => format!("{}", assembly),
-        "Indirect" => {
-            let lo = cpu.memory.read_byte(cpu.pc + 1);
-            let hi = cpu.memory.read_byte(cpu.pc + 2);
+        AddrMode::Impl => assembly,
+        AddrMode::Ind => {
+            let lo = cpu.bus.peek_byte(cpu.pc.wrapping_add(1));
+            let hi = cpu.bus.peek_byte(cpu.pc.wrapping_add(2));
             format!("{} (${:02X}{:02X})", assembly, hi, lo)
         }
-        "Idx Indirect" => {
-            let value = cpu.memory.read_byte(cpu.pc + 1);
+        AddrMode::IndX => {
+            let value = cpu.bus.peek_byte(cpu.pc.wrapping_add(1));
             format!("{} (${:02X}, X)", assembly, value)
         }
-        "Indirect Idx" => {
-            let value = cpu.memory.read_byte(cpu.pc + 1);
+        AddrMode::IndY => {
+            let value = cpu.bus.peek_byte(cpu.pc.wrapping_add(1));
             format!("{} (${:02X}), Y", assembly, value)
         }
-        "Relative" => {
-            let offset = cpu.memory.read_byte(cpu.pc + 1);
-            let target = cpu.pc.wrapping_add(2).wrapping_add(offset as u16);
+        AddrMode::Rel => {
+            let offset = cpu.bus.peek_byte(cpu.pc.wrapping_add(1));
+            let target = cpu.pc.wrapping_add(2).wrapping_add(offset as i8 as u16);
             format!("{} ${:04X}", assembly, target)
         }
-        "ZPG" => {
-            let value = cpu.memory.read_byte(cpu.pc + 1);
+        AddrMode::ZeroPage => {
+            let value = cpu.bus.peek_byte(cpu.pc.wrapping_add(1));
             format!("{} ${:02X}", assembly, value)
         }
-        "ZPG, X" => {
-            let value = cpu.memory.read_byte(cpu.pc + 1);
+        AddrMode::ZeroPageX => {
+            let value = cpu.bus.peek_byte(cpu.pc.wrapping_add(1));
             format!("{} ${:02X}, X", assembly, value)
         }
-        _ => panic!("Addressing mode not implemented: {}", addr_mode),
+        AddrMode::ZeroPageY => {
+            let value = cpu.bus.peek_byte(cpu.pc.wrapping_add(1));
+            format!("{} ${:02X}, Y", assembly, value)
+        }
+        AddrMode::ZeroPageInd => {
+            let value = cpu.bus.peek_byte(cpu.pc.wrapping_add(1));
+            format!("{} (${:02X})", assembly, value)
+        }
+        AddrMode::AbsIndX => {
+            let lo = cpu.bus.peek_byte(cpu.pc.wrapping_add(1));
+            let hi = cpu.bus.peek_byte(cpu.pc.wrapping_add(2));
+            format!("{} (${:02X}{:02X}, X)", assembly, hi, lo)
+        }
+        AddrMode::ZeroPageRel => {
+            let value = cpu.bus.peek_byte(cpu.pc.wrapping_add(1));
+            let offset = cpu.bus.peek_byte(cpu.pc.wrapping_add(2));
+            let target = cpu.pc.wrapping_add(3).wrapping_add(offset as i8 as u16);
+            format!("{} ${:02X}, ${:04X}", assembly, value, target)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Memory;
+    use crate::variant::CpuVariant;
+
+    /// Disassembles an instruction placed at $0200
+    fn disassemble(variant: CpuVariant, bytes: &[u8]) -> String {
+        let mut memory = Memory::new();
+        memory.load(0x0200, bytes).unwrap();
+
+        let mut cpu = Cpu::with_variant(memory, variant);
+        cpu.pc = 0x0200;
+        Instruction::new(&cpu).assembly().to_string()
+    }
+
+    #[test]
+    fn undocumented_nmos_disassembly() {
+        let expected: [(&[u8], &str); 11] = [
+            (&[0xB3, 0x10], "LAX ($10), Y"),
+            (&[0x97, 0x10], "SAX $10, Y"),
+            (&[0xDF, 0x34, 0x12], "DCP $1234, X"),
+            (&[0xE3, 0x10], "ISC ($10, X)"),
+            (&[0x0F, 0x34, 0x12], "SLO $1234"),
+            (&[0x3B, 0x34, 0x12], "RLA $1234, Y"),
+            (&[0x57, 0x10], "SRE $10, X"),
+            (&[0x73, 0x10], "RRA ($10), Y"),
+            (&[0x2B, 0x80], "ANC #$80"),
+            (&[0x4B, 0x0F], "ALR #$0F"),
+            (&[0x6B, 0xFF], "ARR #$FF"),
+        ];
+
+        for (bytes, assembly) in expected {
+            assert_eq!(disassemble(CpuVariant::Nmos6502, bytes), assembly);
+        }
+    }
+
+    #[test]
+    fn disassembly_per_variant() {
+        let expected: [(&[u8], [&str; 3]); 8] = [
+            (&[0x80, 0x02], ["NOP #$02", "BRA $0204", "BRA $0204"]),
+            (&[0x07, 0x10], ["SLO $10", "RMB0 $10", "RMB0 $10"]),
+            (
+                &[0x0F, 0x10, 0xFD],
+                ["SLO $FD10", "BBR0 $10, $0200", "BBR0 $10, $0200"],
+            ),
+            (&[0xB2, 0x10], ["JAM", "LDA ($10)", "LDA ($10)"]),
+            (
+                &[0x7C, 0x34, 0x12],
+                ["NOP $1234, X", "JMP ($1234, X)", "JMP ($1234, X)"],
+            ),
+            (&[0x89, 0x80], ["NOP #$80", "BIT #$80", "BIT #$80"]),
+            (&[0xCB], ["SBX #$00", "WAI", "NOP"]),
+            (
+                &[0x6C, 0xFF, 0x10],
+                ["JMP ($10FF)", "JMP ($10FF)", "JMP ($10FF)"],
+            ),
+        ];
+        let variants = [
+            CpuVariant::Nmos6502,
+            CpuVariant::Wdc65C02,
+            CpuVariant::Rockwell65C02,
+        ];
+
+        for (bytes, assembly) in expected {
+            for (variant, assembly) in variants.into_iter().zip(assembly) {
+                assert_eq!(disassemble(variant, bytes), assembly, "{}", variant);
+            }
+            assert_eq!(disassemble(CpuVariant::Ricoh2A03, bytes), assembly[0]);
+        }
     }
 }
@@ -0,0 +1,61 @@
+//! Cycle-stepped emulator of the MOS 6502 family
+//!
+//! The core is independent from the terminal front-end: a [`Cpu`] runs against any
+//! [`Bus`] implementation, [`Memory`] being the flat 64KB RAM used by default.
+//! [`Instruction`] disassembles the next instruction and [`CpuState`] takes a snapshot
+//! of the registers, both are what the TUI is built on.
+//!
+//! ```
+//! use rs_6502::{Bus, Cpu, Memory};
+//!
+//! let mut memory = Memory::new();
+//! // LDA #$42, with the reset vector pointing at $0200
+//! memory.write_byte(0x0200, 0xA9);
+//! memory.write_byte(0x0201, 0x42);
+//! memory.write_byte(0xFFFC, 0x00);
+//! memory.write_byte(0xFFFD, 0x02);
+//!
+//! let mut cpu = Cpu::with_bus(memory);
+//! cpu.reset();
+//! let step = cpu.execute().unwrap();
+//!
+//! assert_eq!(cpu.a, 0x42);
+//! assert_eq!(step.cycles, 2);
+//! ```
+
+pub mod addressing_mode;
+pub mod asm;
+pub mod bus;
+pub mod cpu;
+pub mod cpu_state;
+pub mod device;
+pub mod error;
+pub mod history;
+pub mod instruction;
+pub mod loader;
+pub mod mapper;
+pub mod memory;
+pub mod opcode;
+pub mod run;
+pub mod save_state;
+pub mod stack;
+pub mod status_register;
+pub mod test_rom;
+pub mod variant;
+
+pub use addressing_mode::AddrMode;
+pub use asm::{AsmError, Assembler};
+pub use bus::{Bus, BusWrapper, Inspect, RegionKind, RomWrite};
+pub use cpu::{Cpu, StepInfo};
+pub use cpu_state::CpuState;
+pub use device::Device;
+pub use error::{CpuError, MapError};
+pub use history::{History, HistoryBus};
+pub use instruction::Instruction;
+pub use loader::{ImageFormat, LoadError, LoadSpec};
+pub use mapper::{BankWindow, LatchMapper, Mapper};
+pub use memory::{Memory, RomWritePolicy};
+pub use opcode::{opcode_info, OpcodeInfo};
+pub use run::{Runner, StopConditions, StopReason, WatchBus};
+pub use save_state::StateError;
+pub use variant::CpuVariant;
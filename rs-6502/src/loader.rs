@@ -0,0 +1,993 @@
+use std::{error::Error, fmt, fs, io, path::Path, path::PathBuf, str::FromStr};
+
+use crate::bus::RegionKind;
+use crate::cpu::{IRQ_VECTOR, NMI_VECTOR, RESET_VECTOR};
+use crate::memory::Memory;
+
+/// Enumerates the reasons an image could not be loaded
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file could not be read
+    Io(io::Error),
+    /// The image runs past the end of the address space
+    TooLarge { addr: u16, len: usize },
+    /// A load address was given for a format holding its own addresses
+    UnexpectedAddress(ImageFormat),
+    /// A PRG file is too short to hold its load address
+    MissingHeader,
+    /// A line of a HEX or S-record file could not be parsed
+    Malformed { line: usize, reason: &'static str },
+    /// The checksum of a record doesn't match its contents
+    Checksum {
+        line: usize,
+        expected: u8,
+        found: u8,
+    },
+    /// A record is placed outside of the 64KB address space
+    OutOfRange { line: usize, addr: u32 },
+    /// A record overwrites bytes of an earlier record of the same file
+    Overlap { line: usize, addr: u16 },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(error) => write!(f, "{}", error),
+            LoadError::TooLarge { addr, len } => write!(
+                f,
+                "Image of {} bytes at 0x{:04X} runs {} bytes past 0xFFFF",
+                len,
+                addr,
+                *addr as usize + len - 0x10000
+            ),
+            LoadError::UnexpectedAddress(format) => {
+                write!(f, "{} files hold their own load addresses", format)
+            }
+            LoadError::MissingHeader => write!(f, "PRG file is missing its load address"),
+            LoadError::Malformed { line, reason } => write!(f, "Line {}: {}", line, reason),
+            LoadError::Checksum {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Line {}: checksum is 0x{:02X}, expected 0x{:02X}",
+                line, found, expected
+            ),
+            LoadError::OutOfRange { line, addr } => write!(
+                f,
+                "Line {}: record at 0x{:X} is outside the 64KB address space",
+                line, addr
+            ),
+            LoadError::Overlap { line, addr } => write!(
+                f,
+                "Line {}: record overlaps an earlier one at 0x{:04X}",
+                line, addr
+            ),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(error: io::Error) -> LoadError {
+        LoadError::Io(error)
+    }
+}
+
+/// Enumerates the file formats images can be loaded from
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ImageFormat {
+    /// Raw bytes, loaded at the address given with the file or at `$0000`
+    Binary,
+    /// Intel HEX records
+    IntelHex,
+    /// Motorola S-records
+    SRecord,
+    /// Commodore program, a little endian load address followed by the bytes
+    Prg,
+}
+
+impl ImageFormat {
+    /// Guesses the format from the file extension, then from the contents. PRG files
+    /// can only be told apart by their extension.
+    ///
+    /// ### Parameters:
+    /// * `path` - The path of the file
+    /// * `data` - The contents of the file
+    pub fn detect(path: &Path, data: &[u8]) -> ImageFormat {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+
+        match extension.as_str() {
+            "hex" | "ihex" | "ihx" => return ImageFormat::IntelHex,
+            "srec" | "s19" | "s28" | "s37" | "mot" => return ImageFormat::SRecord,
+            "prg" => return ImageFormat::Prg,
+            _ => {}
+        }
+
+        match data {
+            [b':', rest @ ..] if rest.is_ascii() => ImageFormat::IntelHex,
+            [b'S', b'0'..=b'9', rest @ ..] if rest.is_ascii() => ImageFormat::SRecord,
+            _ => ImageFormat::Binary,
+        }
+    }
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ImageFormat::Binary => "Binary",
+            ImageFormat::IntelHex => "Intel HEX",
+            ImageFormat::SRecord => "S-record",
+            ImageFormat::Prg => "PRG",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<ImageFormat, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "bin" | "binary" => Ok(ImageFormat::Binary),
+            "hex" | "ihex" => Ok(ImageFormat::IntelHex),
+            "srec" | "s19" => Ok(ImageFormat::SRecord),
+            "prg" => Ok(ImageFormat::Prg),
+            _ => Err(format!("Unknown image format: {}", name)),
+        }
+    }
+}
+
+/// A run of bytes placed at an address
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Segment {
+    pub addr: u16,
+    pub data: Vec<u8>,
+}
+
+/// The contents of a file, ready to be copied into memory
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Image {
+    pub segments: Vec<Segment>,
+    /// Start address recorded in the file, if its format has one
+    pub entry: Option<u16>,
+}
+
+impl Image {
+    /// Parses the contents of a file
+    ///
+    /// ### Parameters:
+    /// * `data` - The contents of the file
+    /// * `format` - The format of the file
+    /// * `addr` - The load address of a raw binary, `$0000` if not given, or one
+    ///   replacing the address stored in a PRG file
+    ///
+    /// ### Returns:
+    /// * The segments of the file, or `Err(LoadError)` if it is malformed
+    pub fn parse(data: &[u8], format: ImageFormat, addr: Option<u16>) -> Result<Image, LoadError> {
+        match format {
+            ImageFormat::Binary => Image::single(addr.unwrap_or(0x0000), data),
+            ImageFormat::Prg => {
+                let [low, high, data @ ..] = data else {
+                    return Err(LoadError::MissingHeader);
+                };
+                Image::single(addr.unwrap_or(u16::from_le_bytes([*low, *high])), data)
+            }
+            ImageFormat::IntelHex | ImageFormat::SRecord if addr.is_some() => {
+                Err(LoadError::UnexpectedAddress(format))
+            }
+            ImageFormat::IntelHex => parse_records(data, parse_intel_hex_record),
+            ImageFormat::SRecord => parse_records(data, parse_srecord),
+        }
+    }
+
+    /// Returns an image made of one segment
+    fn single(addr: u16, data: &[u8]) -> Result<Image, LoadError> {
+        if addr as usize + data.len() > 0x10000 {
+            return Err(LoadError::TooLarge {
+                addr,
+                len: data.len(),
+            });
+        }
+
+        Ok(Image {
+            segments: vec![Segment {
+                addr,
+                data: data.to_vec(),
+            }],
+            entry: None,
+        })
+    }
+
+    /// Returns the lowest address the image is placed at
+    pub fn start(&self) -> Option<u16> {
+        self.segments.iter().map(|segment| segment.addr).min()
+    }
+
+    /// Writes the image in a file format, the inverse of [`Image::parse`]. Binaries and
+    /// PRG files hold one block from the lowest to the highest address, the gaps
+    /// between segments are filled with zeros.
+    ///
+    /// ### Parameters:
+    /// * `format` - The format to write
+    ///
+    /// ### Returns:
+    /// * The contents of the file
+    pub fn encode(&self, format: ImageFormat) -> Vec<u8> {
+        match format {
+            ImageFormat::Binary => self.flatten(),
+            ImageFormat::Prg => {
+                let mut data = self.start().unwrap_or_default().to_le_bytes().to_vec();
+                data.extend(self.flatten());
+                data
+            }
+            ImageFormat::IntelHex => {
+                let mut text = String::new();
+                for (addr, chunk) in self.chunks() {
+                    let [high, low] = addr.to_be_bytes();
+                    let mut record = vec![chunk.len() as u8, high, low, 0x00];
+                    record.extend_from_slice(chunk);
+                    text += &intel_hex_line(&record);
+                }
+                if let Some(entry) = self.entry {
+                    let [high, low] = entry.to_be_bytes();
+                    text += &intel_hex_line(&[0x04, 0x00, 0x00, 0x05, 0x00, 0x00, high, low]);
+                }
+                text += &intel_hex_line(&[0x00, 0x00, 0x00, 0x01]);
+                text.into_bytes()
+            }
+            ImageFormat::SRecord => {
+                let mut text = String::new();
+                for (addr, chunk) in self.chunks() {
+                    let mut record = addr.to_be_bytes().to_vec();
+                    record.extend_from_slice(chunk);
+                    text += &srecord_line('1', &record);
+                }
+                let entry = self.entry.unwrap_or_default();
+                text += &srecord_line('9', &entry.to_be_bytes());
+                text.into_bytes()
+            }
+        }
+    }
+
+    /// Returns the bytes from the lowest to the highest address of the image
+    fn flatten(&self) -> Vec<u8> {
+        let Some(start) = self.start() else {
+            return Vec::new();
+        };
+        let end = self
+            .segments
+            .iter()
+            .map(|segment| segment.addr as usize + segment.data.len())
+            .max()
+            .unwrap_or_default();
+
+        let mut data = vec![0; end - start as usize];
+        for segment in &self.segments {
+            let offset = (segment.addr - start) as usize;
+            data[offset..offset + segment.data.len()].copy_from_slice(&segment.data);
+        }
+
+        data
+    }
+
+    /// Splits the segments into runs of at most 16 bytes, one per record
+    fn chunks(&self) -> impl Iterator<Item = (u16, &[u8])> {
+        self.segments.iter().flat_map(|segment| {
+            segment
+                .data
+                .chunks(16)
+                .enumerate()
+                .map(|(index, chunk)| (segment.addr + (index * 16) as u16, chunk))
+        })
+    }
+}
+
+/// Formats an Intel HEX record from its bytes, the checksum is appended
+fn intel_hex_line(record: &[u8]) -> String {
+    let checksum = record
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+        .wrapping_neg();
+
+    let digits: String = record.iter().map(|byte| format!("{:02X}", byte)).collect();
+    format!(":{}{:02X}\n", digits, checksum)
+}
+
+/// Formats an S-record from its address and data, the count and checksum are added
+fn srecord_line(kind: char, record: &[u8]) -> String {
+    let count = record.len() as u8 + 1;
+    let checksum = !record
+        .iter()
+        .fold(count, |sum, byte| sum.wrapping_add(*byte));
+
+    let digits: String = record.iter().map(|byte| format!("{:02X}", byte)).collect();
+    format!("S{}{:02X}{}{:02X}\n", kind, count, digits, checksum)
+}
+
+/// What one line of a HEX or S-record file holds
+enum Record {
+    /// Bytes to place at an address, which may exceed 16 bits until it is checked
+    Data(u32, Vec<u8>),
+    /// Start address of the program
+    Entry(u32),
+    /// Base added to the address of the following data records
+    Base(u32),
+    /// Header, record count and other lines without contents
+    Other,
+    /// End of file, anything after it is ignored
+    End,
+}
+
+/// Splits a string of hexadecimal digits into bytes
+fn hex_bytes(digits: &str, line: usize) -> Result<Vec<u8>, LoadError> {
+    if digits.len() % 2 != 0 || !digits.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+        return Err(LoadError::Malformed {
+            line,
+            reason: "invalid hexadecimal digits",
+        });
+    }
+
+    Ok((0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap())
+        .collect())
+}
+
+/// Parses `:LLAAAATT<data>CC`, where the bytes and the checksum add up to 0
+fn parse_intel_hex_record(text: &str, line: usize) -> Result<Record, LoadError> {
+    let Some(digits) = text.strip_prefix(':') else {
+        return Err(LoadError::Malformed {
+            line,
+            reason: "record doesn't start with ':'",
+        });
+    };
+
+    let bytes = hex_bytes(digits, line)?;
+    if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+        return Err(LoadError::Malformed {
+            line,
+            reason: "record length doesn't match its byte count",
+        });
+    }
+
+    let (contents, checksum) = bytes.split_at(bytes.len() - 1);
+    let expected = contents
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+        .wrapping_neg();
+    if checksum[0] != expected {
+        return Err(LoadError::Checksum {
+            line,
+            expected,
+            found: checksum[0],
+        });
+    }
+
+    let addr = u16::from_be_bytes([contents[1], contents[2]]) as u32;
+    let data = &contents[4..];
+    let word = |index: usize| u16::from_be_bytes([data[index], data[index + 1]]) as u32;
+
+    let record = match (contents[3], data.len()) {
+        (0x00, _) => Record::Data(addr, data.to_vec()),
+        (0x01, _) => Record::End,
+        // Segment addresses count in paragraphs of 16 bytes
+        (0x02, 2) => Record::Base(word(0) << 4),
+        (0x03, 4) => Record::Entry((word(0) << 4) + word(2)),
+        (0x04, 2) => Record::Base(word(0) << 16),
+        (0x05, 4) => Record::Entry(word(0) << 16 | word(2)),
+        (0x02..=0x05, _) => {
+            return Err(LoadError::Malformed {
+                line,
+                reason: "address record of the wrong length",
+            })
+        }
+        _ => {
+            return Err(LoadError::Malformed {
+                line,
+                reason: "unknown record type",
+            })
+        }
+    };
+
+    Ok(record)
+}
+
+/// Parses `S<type><count><address><data><checksum>`, where the checksum is the ones'
+/// complement of the sum of the other bytes
+fn parse_srecord(text: &str, line: usize) -> Result<Record, LoadError> {
+    let mut chars = text.chars();
+    let (Some('S'), Some(kind)) = (chars.next(), chars.next()) else {
+        return Err(LoadError::Malformed {
+            line,
+            reason: "record doesn't start with 'S'",
+        });
+    };
+
+    let bytes = hex_bytes(chars.as_str(), line)?;
+    if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+        return Err(LoadError::Malformed {
+            line,
+            reason: "record length doesn't match its byte count",
+        });
+    }
+
+    let (contents, checksum) = bytes.split_at(bytes.len() - 1);
+    let expected = !contents
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    if checksum[0] != expected {
+        return Err(LoadError::Checksum {
+            line,
+            expected,
+            found: checksum[0],
+        });
+    }
+
+    let addr_len = match kind {
+        '0' | '1' | '5' | '9' => 2,
+        '2' | '6' | '8' => 3,
+        '3' | '7' => 4,
+        _ => {
+            return Err(LoadError::Malformed {
+                line,
+                reason: "unknown record type",
+            })
+        }
+    };
+    if contents.len() < 1 + addr_len {
+        return Err(LoadError::Malformed {
+            line,
+            reason: "record too short for its address",
+        });
+    }
+
+    let addr = contents[1..=addr_len]
+        .iter()
+        .fold(0u32, |addr, byte| addr << 8 | *byte as u32);
+
+    let record = match kind {
+        '1' | '2' | '3' => Record::Data(addr, contents[1 + addr_len..].to_vec()),
+        '7' | '8' | '9' => Record::Entry(addr),
+        _ => Record::Other,
+    };
+
+    Ok(record)
+}
+
+/// Parses a record file line by line, checking every byte lands in the address space
+/// and no two records overlap
+fn parse_records(
+    data: &[u8],
+    parse_record: fn(&str, usize) -> Result<Record, LoadError>,
+) -> Result<Image, LoadError> {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return Err(LoadError::Malformed {
+            line: 1,
+            reason: "file is not text",
+        });
+    };
+
+    let mut image = Image::default();
+    let mut base = 0;
+    let mut written = vec![false; 0x10000];
+
+    for (index, text) in text.lines().enumerate() {
+        let line = index + 1;
+        let text = text.trim();
+        if text.is_empty() {
+            continue;
+        }
+
+        match parse_record(text, line)? {
+            Record::Data(addr, data) => {
+                let addr = base + addr;
+                if addr as usize + data.len() > 0x10000 {
+                    return Err(LoadError::OutOfRange { line, addr });
+                }
+
+                let range = addr as usize..addr as usize + data.len();
+                if let Some(offset) = written[range.clone()].iter().position(|&used| used) {
+                    return Err(LoadError::Overlap {
+                        line,
+                        addr: (range.start + offset) as u16,
+                    });
+                }
+                written[range].fill(true);
+
+                image.segments.push(Segment {
+                    addr: addr as u16,
+                    data,
+                });
+            }
+            Record::Entry(addr) => {
+                if addr > 0xFFFF {
+                    return Err(LoadError::OutOfRange { line, addr });
+                }
+                image.entry = Some(addr as u16);
+            }
+            Record::Base(addr) => base = addr,
+            Record::Other => {}
+            Record::End => break,
+        }
+    }
+
+    Ok(image)
+}
+
+/// Parses an address written in hexadecimal, with or without a `$` or `0x` prefix
+///
+/// ### Parameters:
+/// * `text` - The address, e.g. `E000`, `$E000` or `0xE000`
+pub fn parse_addr(text: &str) -> Result<u16, String> {
+    let digits = text
+        .strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text);
+
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid address: {}", text))
+}
+
+/// A file to load and where, written `path[@ADDR][,rom][,reset][,irq][,nmi]`
+///
+/// The address places raw binaries, which otherwise start at `$0000`, replaces the one
+/// stored in PRG files and is not allowed for HEX and S-record files. `rom` tags the
+/// loaded bytes as ROM, and `reset`, `irq` and `nmi` point the matching vectors at the
+/// load address, or at the start address recorded in the file.
+#[derive(Clone, PartialEq, Debug)]
+pub struct LoadSpec {
+    pub path: PathBuf,
+    pub addr: Option<u16>,
+    /// The format of the file, detected when `None`
+    pub format: Option<ImageFormat>,
+    pub rom: bool,
+    /// Addresses of the vectors pointed at the image
+    pub vectors: Vec<u16>,
+}
+
+impl FromStr for LoadSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<LoadSpec, String> {
+        // The path may contain '@' itself, what follows the last one is only taken as
+        // the address if it parses as one
+        let with_addr = spec.rsplit_once('@').and_then(|(path, rest)| {
+            let (addr, options) = rest.split_once(',').unwrap_or((rest, ""));
+            parse_addr(addr)
+                .ok()
+                .map(|addr| (path, Some(addr), options))
+        });
+        let (path, addr, options) = with_addr.unwrap_or_else(|| {
+            let (path, options) = spec.split_once(',').unwrap_or((spec, ""));
+            (path, None, options)
+        });
+
+        let mut load_spec = LoadSpec {
+            path: PathBuf::from(path),
+            addr,
+            format: None,
+            rom: false,
+            vectors: Vec::new(),
+        };
+
+        for option in options.split(',').filter(|option| !option.is_empty()) {
+            match option.to_ascii_lowercase().as_str() {
+                "rom" => load_spec.rom = true,
+                "reset" => load_spec.vectors.push(RESET_VECTOR),
+                "irq" => load_spec.vectors.push(IRQ_VECTOR),
+                "nmi" => load_spec.vectors.push(NMI_VECTOR),
+                _ => return Err(format!("Unknown load option: {}", option)),
+            }
+        }
+
+        Ok(load_spec)
+    }
+}
+
+impl LoadSpec {
+    /// Reads and parses the file, detecting its format unless one was given
+    ///
+    /// ### Returns:
+    /// * `Err(LoadError)` if the file can't be read or is malformed
+    pub fn read(&self) -> Result<Image, LoadError> {
+        let data = fs::read(&self.path)?;
+        let format = self
+            .format
+            .unwrap_or_else(|| ImageFormat::detect(&self.path, &data));
+
+        Image::parse(&data, format, self.addr)
+    }
+
+    /// Copies a parsed image into memory, tagging it as ROM if asked to
+    ///
+    /// ### Parameters:
+    /// * `image` - The image read from the file
+    /// * `memory` - The memory to load the image into
+    ///
+    /// ### Returns:
+    /// * `Err(LoadError)` if a segment doesn't fit
+    pub fn load(&self, image: &Image, memory: &mut Memory) -> Result<(), LoadError> {
+        for segment in image
+            .segments
+            .iter()
+            .filter(|segment| !segment.data.is_empty())
+        {
+            memory.load(segment.addr, &segment.data)?;
+
+            if self.rom {
+                let end = segment.addr + (segment.data.len() - 1) as u16;
+                memory.set_region(segment.addr..=end, RegionKind::Rom);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Points the requested vectors at the load address, the start address recorded in
+    /// the file or the lowest address of the image, in that order of preference
+    ///
+    /// ### Parameters:
+    /// * `image` - The image read from the file
+    /// * `memory` - The memory holding the vectors
+    pub fn fill_vectors(&self, image: &Image, memory: &mut Memory) {
+        let Some(target) = self.addr.or(image.entry).or(image.start()) else {
+            return;
+        };
+
+        for &vector in self.vectors.iter() {
+            memory
+                .load(vector, &target.to_le_bytes())
+                .expect("Vectors fit in the address space");
+        }
+    }
+}
+
+/// Loads every image in order, then fills in the vectors, so images covering the
+/// vectors don't overwrite the requested ones
+///
+/// ### Parameters:
+/// * `memory` - The memory to load the images into
+/// * `specs` - The images, later ones overwrite earlier ones where they overlap
+///
+/// ### Returns:
+/// * `Err` holding the first image that failed and why
+pub fn load_all<'a>(
+    memory: &mut Memory,
+    specs: &'a [LoadSpec],
+) -> Result<(), (&'a LoadSpec, LoadError)> {
+    let mut images = Vec::new();
+
+    for spec in specs {
+        let image = spec.read().map_err(|error| (spec, error))?;
+        spec.load(&image, memory).map_err(|error| (spec, error))?;
+        images.push(image);
+    }
+
+    for (spec, image) in specs.iter().zip(images.iter()) {
+        spec.fill_vectors(image, memory);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(text: &str) -> LoadSpec {
+        text.parse().unwrap()
+    }
+
+    fn hex(lines: &[&[u8]]) -> Result<Image, LoadError> {
+        let text: String = lines.iter().map(|record| intel_hex_line(record)).collect();
+        Image::parse(text.as_bytes(), ImageFormat::IntelHex, None)
+    }
+
+    fn srec(lines: &[(char, &[u8])]) -> Result<Image, LoadError> {
+        let text: String = lines
+            .iter()
+            .map(|(kind, record)| srecord_line(*kind, record))
+            .collect();
+        Image::parse(text.as_bytes(), ImageFormat::SRecord, None)
+    }
+
+    /// Returns every byte of an image with its address, in address order
+    fn bytes(image: &Image) -> Vec<(u16, u8)> {
+        let mut bytes: Vec<(u16, u8)> = image
+            .segments
+            .iter()
+            .flat_map(|segment| {
+                (segment.addr..)
+                    .zip(segment.data.iter().copied())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        bytes.sort();
+        bytes
+    }
+
+    #[test]
+    fn intel_hex_records() {
+        let image = hex(&[
+            &[0x03, 0x02, 0x00, 0x00, 0xA9, 0x01, 0x60],
+            &[0x04, 0x00, 0x00, 0x05, 0x00, 0x00, 0x02, 0x00],
+            &[0x00, 0x00, 0x00, 0x01],
+        ])
+        .unwrap();
+
+        assert_eq!(
+            image.segments,
+            vec![Segment {
+                addr: 0x0200,
+                data: vec![0xA9, 0x01, 0x60]
+            }]
+        );
+        assert_eq!(image.entry, Some(0x0200));
+
+        // Anything after the end of file record is ignored
+        let text = ":00000001FF\nnot a record\n";
+        assert!(Image::parse(text.as_bytes(), ImageFormat::IntelHex, None).is_ok());
+    }
+
+    #[test]
+    fn intel_hex_checksum_mismatch() {
+        let text = ":0102000000FD\n:0102010000FD\n";
+
+        assert!(matches!(
+            Image::parse(text.as_bytes(), ImageFormat::IntelHex, None),
+            Err(LoadError::Checksum {
+                line: 2,
+                expected: 0xFC,
+                found: 0xFD
+            })
+        ));
+    }
+
+    #[test]
+    fn intel_hex_extended_addresses() {
+        // A linear base of 0 and a segment base of $0100 paragraphs, i.e. $1000
+        let image = hex(&[
+            &[0x02, 0x00, 0x00, 0x04, 0x00, 0x00],
+            &[0x01, 0x00, 0x10, 0x00, 0xEA],
+            &[0x02, 0x00, 0x00, 0x02, 0x01, 0x00],
+            &[0x01, 0x00, 0x20, 0x00, 0xEA],
+            &[0x04, 0x00, 0x00, 0x03, 0x01, 0x00, 0x00, 0x20],
+        ])
+        .unwrap();
+        assert_eq!(bytes(&image), vec![(0x0010, 0xEA), (0x1020, 0xEA)]);
+        assert_eq!(image.entry, Some(0x1020));
+
+        let result = hex(&[
+            &[0x02, 0x00, 0x00, 0x04, 0x00, 0x01],
+            &[0x01, 0x00, 0x00, 0x00, 0xEA],
+        ]);
+        assert!(matches!(
+            result,
+            Err(LoadError::OutOfRange {
+                line: 2,
+                addr: 0x10000
+            })
+        ));
+
+        let result = hex(&[&[0x01, 0x00, 0x00, 0x04, 0x00]]);
+        assert!(matches!(
+            result,
+            Err(LoadError::Malformed {
+                line: 1,
+                reason: "address record of the wrong length"
+            })
+        ));
+    }
+
+    #[test]
+    fn overlapping_records() {
+        let result = hex(&[
+            &[0x04, 0x02, 0x00, 0x00, 1, 2, 3, 4],
+            &[0x02, 0x02, 0x04, 0x00, 5, 6],
+            &[0x02, 0x02, 0x03, 0x00, 7, 8],
+        ]);
+        assert!(matches!(
+            result,
+            Err(LoadError::Overlap {
+                line: 3,
+                addr: 0x0203
+            })
+        ));
+
+        let result = srec(&[('1', &[0x02, 0x00, 1, 2]), ('1', &[0x01, 0xFF, 3, 4])]);
+        assert!(matches!(
+            result,
+            Err(LoadError::Overlap {
+                line: 2,
+                addr: 0x0200
+            })
+        ));
+    }
+
+    #[test]
+    fn srecord_address_widths() {
+        let image = srec(&[
+            ('0', &[0x00, 0x00, b'h', b'i']),
+            ('1', &[0x02, 0x00, 0x01]),
+            ('2', &[0x00, 0xC0, 0x00, 0x02]),
+            ('3', &[0x00, 0x00, 0xE0, 0x00, 0x03]),
+            ('5', &[0x00, 0x03]),
+            ('8', &[0x00, 0xC0, 0x00]),
+        ])
+        .unwrap();
+        assert_eq!(
+            bytes(&image),
+            vec![(0x0200, 0x01), (0xC000, 0x02), (0xE000, 0x03)]
+        );
+        assert_eq!(image.entry, Some(0xC000));
+
+        let image = srec(&[('7', &[0x00, 0x00, 0x12, 0x34])]).unwrap();
+        assert_eq!(image.entry, Some(0x1234));
+
+        let result = srec(&[('2', &[0x01, 0x00, 0x00, 0xEA])]);
+        assert!(matches!(
+            result,
+            Err(LoadError::OutOfRange {
+                line: 1,
+                addr: 0x10000
+            })
+        ));
+
+        let result = srec(&[('3', &[0x00, 0x00, 0x02])]);
+        assert!(matches!(
+            result,
+            Err(LoadError::Malformed {
+                line: 1,
+                reason: "record too short for its address"
+            })
+        ));
+    }
+
+    #[test]
+    fn srecord_checksum_mismatch() {
+        let text = "S1040200EA0F\nS1040201EA0D\n";
+
+        assert!(matches!(
+            Image::parse(text.as_bytes(), ImageFormat::SRecord, None),
+            Err(LoadError::Checksum {
+                line: 2,
+                expected: 0x0E,
+                found: 0x0D
+            })
+        ));
+    }
+
+    #[test]
+    fn prg_header() {
+        let image = Image::parse(&[0x01, 0x08, 0xA9, 0x00], ImageFormat::Prg, None).unwrap();
+        assert_eq!(bytes(&image), vec![(0x0801, 0xA9), (0x0802, 0x00)]);
+
+        // The address given replaces the one of the header
+        let image = Image::parse(&[0x01, 0x08, 0xEA], ImageFormat::Prg, Some(0xC000)).unwrap();
+        assert_eq!(bytes(&image), vec![(0xC000, 0xEA)]);
+
+        assert!(matches!(
+            Image::parse(&[0x01], ImageFormat::Prg, None),
+            Err(LoadError::MissingHeader)
+        ));
+        assert!(matches!(
+            Image::parse(&[0xFF, 0xFF, 0xEA, 0xEA], ImageFormat::Prg, None),
+            Err(LoadError::TooLarge {
+                addr: 0xFFFF,
+                len: 2
+            })
+        ));
+        assert!(matches!(
+            Image::parse(b":00000001FF", ImageFormat::IntelHex, Some(0x0200)),
+            Err(LoadError::UnexpectedAddress(ImageFormat::IntelHex))
+        ));
+    }
+
+    #[test]
+    fn detect_format() {
+        let detect = |path: &str, data: &[u8]| ImageFormat::detect(Path::new(path), data);
+
+        assert_eq!(detect("prog.HEX", b""), ImageFormat::IntelHex);
+        assert_eq!(detect("prog.ihx", b""), ImageFormat::IntelHex);
+        assert_eq!(detect("prog.s19", b""), ImageFormat::SRecord);
+        assert_eq!(detect("prog.mot", b""), ImageFormat::SRecord);
+        // PRG files are only recognised by their extension, whatever they hold
+        assert_eq!(detect("prog.prg", b":00000001FF"), ImageFormat::Prg);
+        assert_eq!(detect("prog.bin", b":00000001FF"), ImageFormat::IntelHex);
+        assert_eq!(detect("prog", b"S9030000FC"), ImageFormat::SRecord);
+        assert_eq!(detect("prog.bin", b":\xA9\x00"), ImageFormat::Binary);
+        assert_eq!(detect("prog.bin", b"SX"), ImageFormat::Binary);
+        assert_eq!(detect("prog.rom", &[0xA9, 0x00]), ImageFormat::Binary);
+    }
+
+    #[test]
+    fn encode_parse_round_trip() {
+        let image = Image {
+            segments: vec![
+                Segment {
+                    addr: 0x0200,
+                    data: (0..40).collect(),
+                },
+                Segment {
+                    addr: 0x0300,
+                    data: vec![0xEA, 0xEA, 0x60],
+                },
+            ],
+            entry: Some(0x0200),
+        };
+
+        for format in [ImageFormat::IntelHex, ImageFormat::SRecord] {
+            let parsed = Image::parse(&image.encode(format), format, None).unwrap();
+            assert_eq!(bytes(&parsed), bytes(&image), "{}", format);
+            assert_eq!(parsed.entry, image.entry, "{}", format);
+        }
+
+        // Binaries and PRG files fill the gap between the segments with zeros
+        let mut flat = image.clone();
+        flat.segments[0].data.resize(0x100, 0);
+        flat.segments[0].data.extend([0xEA, 0xEA, 0x60]);
+        flat.segments.pop();
+
+        let parsed = Image::parse(
+            &image.encode(ImageFormat::Binary),
+            ImageFormat::Binary,
+            Some(0x0200),
+        )
+        .unwrap();
+        assert_eq!(bytes(&parsed), bytes(&flat));
+
+        let parsed = Image::parse(&image.encode(ImageFormat::Prg), ImageFormat::Prg, None).unwrap();
+        assert_eq!(bytes(&parsed), bytes(&flat));
+    }
+
+    #[test]
+    fn load_spec_options() {
+        let load_spec = spec("rom.bin@$E000,rom,reset,NMI");
+
+        assert_eq!(load_spec.path, PathBuf::from("rom.bin"));
+        assert_eq!(load_spec.addr, Some(0xE000));
+        assert!(load_spec.rom);
+        assert_eq!(load_spec.vectors, vec![RESET_VECTOR, NMI_VECTOR]);
+
+        let load_spec = spec("prog.prg,irq");
+        assert_eq!(load_spec.path, PathBuf::from("prog.prg"));
+        assert_eq!(load_spec.addr, None);
+        assert_eq!(load_spec.vectors, vec![IRQ_VECTOR]);
+
+        assert_eq!(
+            "rom.bin,fast".parse::<LoadSpec>(),
+            Err("Unknown load option: fast".to_string())
+        );
+    }
+
+    #[test]
+    fn load_spec_path_with_at_sign() {
+        let load_spec = spec("roms@v2/prog.bin");
+        assert_eq!(load_spec.path, PathBuf::from("roms@v2/prog.bin"));
+        assert_eq!(load_spec.addr, None);
+
+        let load_spec = spec("roms@v2/prog.bin,rom");
+        assert_eq!(load_spec.path, PathBuf::from("roms@v2/prog.bin"));
+        assert!(load_spec.rom);
+
+        let load_spec = spec("roms@v2/prog.bin@0x0800,reset");
+        assert_eq!(load_spec.path, PathBuf::from("roms@v2/prog.bin"));
+        assert_eq!(load_spec.addr, Some(0x0800));
+        assert_eq!(load_spec.vectors, vec![RESET_VECTOR]);
+    }
+}
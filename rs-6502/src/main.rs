@@ -1,30 +1,177 @@
-use crate::{cpu::Cpu, tui::App};
-use std::{env, io};
-
-mod addressing_mode;
-mod cpu;
-mod cpu_state;
-mod instruction;
-mod memory;
-mod stack;
-mod status_register;
+use crate::cli::{AsmOptions, Command, Entry, Options};
+#[cfg(feature = "tui")]
+use crate::tui::App;
+use rs_6502::cpu::RESET_VECTOR;
+use rs_6502::loader;
+#[cfg(feature = "tui")]
+use rs_6502::HistoryBus;
+use rs_6502::{Assembler, Cpu, CpuState, Inspect, Instruction, Memory, Runner, WatchBus};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::{env, process};
+
+mod cli;
+#[cfg(feature = "tui")]
 mod tui;
 
-fn main() -> io::Result<()> {
-    if env::args().len() < 2 {
-        println!("Usage: cargo run <path-to-rom>");
-        panic!("No ROM file specified")
+/// Prints the problem and points at `--help`, then exits with an error
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    eprintln!("Run with --help to list the options");
+    process::exit(2)
+}
+
+/// Loads every image, exiting with an error if one can't be loaded
+fn load_memory(options: &Options) -> Memory {
+    let mut memory = Memory::new();
+    memory.set_rom_write_policy(options.rom_writes);
+
+    if let Err((spec, error)) = loader::load_all(&mut memory, &options.images) {
+        eprintln!("Could not load {}: {}", spec.path.display(), error);
+        process::exit(1);
+    }
+    if let Some(Entry::Reset(addr)) = options.entry {
+        memory
+            .load(RESET_VECTOR, &addr.to_le_bytes())
+            .expect("The reset vector fits in the address space");
+    }
+
+    memory
+}
+
+/// Resets the CPU and moves the PC to the entry point asked for, if any
+fn start<B: Inspect>(cpu: &mut Cpu<B>, options: &Options) {
+    cpu.poll_rom_faults();
+    cpu.reset();
+
+    if let Some(Entry::Pc(addr)) = options.entry {
+        cpu.pc = addr;
+    }
+}
+
+/// Runs without the debugger until a stop condition holds, then prints why and the
+/// state of the CPU
+///
+/// ### Returns:
+/// * The exit code telling which condition stopped the run
+fn run_headless(options: &Options) -> io::Result<i32> {
+    let mut cpu = Cpu::with_variant(WatchBus::new(load_memory(options)), options.variant);
+    start(&mut cpu, options);
+
+    let mut trace = match &options.trace {
+        Some(path) => Some(BufWriter::new(File::create(path)?)),
+        None => None,
+    };
+
+    let mut runner = Runner::new(options.stop.clone());
+    let reason = loop {
+        if let Some(reason) = runner.check(&cpu) {
+            break reason;
+        }
+
+        if let Some(trace) = trace.as_mut() {
+            let instruction = Instruction::new(&cpu);
+            writeln!(
+                trace,
+                "{:04X}  {:<16} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+                cpu.pc,
+                instruction.assembly(),
+                cpu.a,
+                cpu.x,
+                cpu.y,
+                cpu.sr.get_status_byte(),
+                cpu.stack.sp,
+                cpu.cycles
+            )?;
+        }
+
+        if let Some(reason) = runner.execute(&mut cpu) {
+            break reason;
+        }
+    };
+
+    if let Some(mut trace) = trace {
+        trace.flush()?;
+    }
+    println!("{} after {} instructions", reason, runner.instructions());
+    for write in cpu.bus.take_rom_writes() {
+        println!(
+            "Ignored write of 0x{:02X} to ROM at 0x{:04X}",
+            write.data, write.addr
+        );
     }
+    println!("{}", CpuState::new(&cpu));
+
+    Ok(reason.exit_code())
+}
 
-    let rom_path = env::args().nth(1).unwrap();
-    let rom = std::fs::read(rom_path).unwrap();
-    let mut cpu = Cpu::new();
+/// Assembles a source file and writes the image
+///
+/// ### Returns:
+/// * The exit code, 1 if the source could not be read or assembled
+fn assemble(options: &AsmOptions) -> io::Result<i32> {
+    let image = match Assembler::new(options.variant).assemble_file(&options.source) {
+        Ok(image) => image,
+        Err(error) => {
+            eprintln!("{}", error);
+            return Ok(1);
+        }
+    };
+
+    fs::write(&options.output, image.encode(options.format))?;
+    for segment in &image.segments {
+        println!(
+            "${:04X}-${:04X} {} bytes",
+            segment.addr,
+            segment.addr as usize + segment.data.len() - 1,
+            segment.data.len()
+        );
+    }
+    println!("Wrote {} to {}", options.format, options.output.display());
+
+    Ok(0)
+}
 
-    cpu.memory.load(rom);
+/// Runs the images in the terminal debugger
+#[cfg(feature = "tui")]
+fn run_debugger(options: &Options) -> io::Result<()> {
+    // Save states are stored next to the first image
+    let state_path = options.images[0].path.display().to_string();
+    let mut cpu = Cpu::with_variant(HistoryBus::new(load_memory(options)), options.variant);
+    start(&mut cpu, options);
 
     let mut terminal = tui::init()?;
-    let app_result = App::new(cpu).run(&mut terminal);
+    let app_result = App::new(cpu, state_path, &options.stop.breakpoints).run(&mut terminal);
     tui::restore()?;
 
     app_result
 }
+
+/// Stands in for the terminal debugger when it is left out of the build
+#[cfg(not(feature = "tui"))]
+fn run_debugger(_options: &Options) -> io::Result<()> {
+    usage_error("This build has no terminal debugger, run with --headless")
+}
+
+fn main() -> io::Result<()> {
+    let options = match cli::parse(env::args().skip(1)) {
+        Ok(Command::Run(options)) => options,
+        Ok(Command::Assemble(options)) => process::exit(assemble(&options)?),
+        Ok(Command::Help) => {
+            println!("{}", cli::HELP);
+            return Ok(());
+        }
+        Ok(Command::AsmHelp) => {
+            println!("{}", cli::ASM_HELP);
+            return Ok(());
+        }
+        Err(message) => usage_error(&message),
+    };
+
+    if options.headless {
+        let code = run_headless(&options)?;
+        process::exit(code);
+    }
+
+    run_debugger(&options)
+}
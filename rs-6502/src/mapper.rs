@@ -0,0 +1,239 @@
+use std::ops::RangeInclusive;
+
+use crate::bus::RegionKind;
+use crate::save_state::{StateError, StateReader, StateWriter};
+
+/// A window of the CPU address space showing one bank of physical memory
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BankWindow {
+    /// The CPU addresses covered by the window
+    pub range: RangeInclusive<u16>,
+    /// The bank shown, counted in units of the window size
+    pub bank: usize,
+    /// Whether the CPU can write to the bank shown
+    pub kind: RegionKind,
+}
+
+impl BankWindow {
+    /// Creates a window showing the given bank
+    ///
+    /// ### Parameters:
+    /// * `range` - The CPU addresses covered by the window, e.g. `0x8000..=0xBFFF`
+    /// * `bank` - The bank shown at first
+    /// * `kind` - Whether the CPU can write to the bank
+    pub fn new(range: RangeInclusive<u16>, bank: usize, kind: RegionKind) -> BankWindow {
+        BankWindow { range, bank, kind }
+    }
+
+    /// Returns the number of bytes covered by the window, which is also the bank size
+    pub fn len(&self) -> usize {
+        (*self.range.end() as usize + 1).saturating_sub(*self.range.start() as usize)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns where a CPU address of the window lands in physical memory, banks past
+    /// the end of physical memory wrap around as unconnected address lines would
+    ///
+    /// ### Parameters:
+    /// * `addr` - A CPU address inside the window
+    /// * `physical_len` - The size of physical memory, not 0
+    pub fn physical_addr(&self, addr: u16, physical_len: usize) -> usize {
+        (self.bank * self.len() + (addr - self.range.start()) as usize) % physical_len
+    }
+}
+
+/// Bank switching logic sitting on top of [`Memory`], attached with [`Memory::set_mapper`]
+///
+/// The mapper lays out windows of the address space over a block of physical memory,
+/// larger than 64KB if needed, and selects the bank every window shows when one of
+/// its control registers is written. Addresses outside every window are the plain
+/// 64KB of RAM.
+///
+/// [`Memory`]: crate::memory::Memory
+/// [`Memory::set_mapper`]: crate::memory::Memory::set_mapper
+pub trait Mapper {
+    /// Returns the windows and the banks they show at power on
+    fn windows(&self) -> Vec<BankWindow>;
+
+    /// Handles a CPU write, selecting banks when it hits a control register
+    ///
+    /// ### Parameters:
+    /// * `addr` - The address written
+    /// * `value` - The byte written
+    /// * `windows` - The current windows, whose banks and kinds may be changed
+    ///
+    /// ### Returns:
+    /// * `true` if the write hit a control register, it then doesn't reach memory
+    fn write(&mut self, addr: u16, value: u8, windows: &mut [BankWindow]) -> bool;
+
+    /// Appends the registers of the mapper not reflected by the windows to a save state
+    ///
+    /// ### Parameters:
+    /// * `writer` - The save state being built
+    fn save_state(&self, writer: &mut StateWriter) {
+        let _ = writer;
+    }
+
+    /// Restores what [`Mapper::save_state`] saved
+    ///
+    /// ### Parameters:
+    /// * `reader` - The save state, positioned where the mapper state starts
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let _ = reader;
+        Ok(())
+    }
+}
+
+/// Latch-based paging: the byte written to a register selects the bank of one window,
+/// the other windows stay fixed
+///
+/// Like a real latch, it only keeps the low bits needed to number the banks. A value
+/// still naming a missing bank, when their number is not a power of two, is ignored.
+///
+/// This covers the RAM/ROM paging of most homebrew boards, as well as the NES UxROM
+/// family, which latches the bank on writes anywhere in `$8000-$FFFF`:
+///
+/// ```
+/// use rs_6502::{BankWindow, LatchMapper, Memory, RegionKind};
+///
+/// let uxrom = LatchMapper::new(
+///     0x8000..=0xFFFF,
+///     vec![
+///         BankWindow::new(0x8000..=0xBFFF, 0, RegionKind::Rom),
+///         BankWindow::new(0xC000..=0xFFFF, 7, RegionKind::Rom),
+///     ],
+///     0,
+///     8,
+/// );
+///
+/// let mut memory = Memory::new();
+/// memory.set_mapper(Box::new(uxrom), vec![0; 8 * 0x4000]).unwrap();
+/// ```
+pub struct LatchMapper {
+    register: RangeInclusive<u16>,
+    windows: Vec<BankWindow>,
+    /// Index of the window whose bank is latched
+    switched: usize,
+    /// Number of banks the latch can select
+    banks: usize,
+}
+
+impl LatchMapper {
+    /// Creates a latch mapper
+    ///
+    /// ### Parameters:
+    /// * `register` - The addresses the latch answers to
+    /// * `windows` - The windows and their banks at power on
+    /// * `switched` - The index of the window selected by the latch
+    /// * `banks` - The number of banks of the switched window's size in physical memory
+    pub fn new(
+        register: RangeInclusive<u16>,
+        windows: Vec<BankWindow>,
+        switched: usize,
+        banks: usize,
+    ) -> LatchMapper {
+        LatchMapper {
+            register,
+            windows,
+            switched,
+            banks,
+        }
+    }
+}
+
+impl Mapper for LatchMapper {
+    fn windows(&self) -> Vec<BankWindow> {
+        self.windows.clone()
+    }
+
+    fn write(&mut self, addr: u16, value: u8, windows: &mut [BankWindow]) -> bool {
+        if !self.register.contains(&addr) {
+            return false;
+        }
+
+        let bank = value as usize & (self.banks.next_power_of_two() - 1);
+        if let Some(window) = windows.get_mut(self.switched) {
+            if bank < self.banks {
+                window.bank = bank;
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{Bus, Inspect};
+    use crate::memory::Memory;
+
+    /// Six 16KB banks at $8000-$BFFF, selected by a latch at $C000, over physical memory
+    /// whose first byte of every bank is the bank number
+    fn memory() -> Memory {
+        let mapper = LatchMapper::new(
+            0xC000..=0xC000,
+            vec![BankWindow::new(0x8000..=0xBFFF, 0, RegionKind::Ram)],
+            0,
+            6,
+        );
+        let mut physical = vec![0; 6 * 0x4000];
+        for bank in 0..6 {
+            physical[bank * 0x4000] = bank as u8;
+        }
+
+        let mut memory = Memory::new();
+        memory.set_mapper(Box::new(mapper), physical).unwrap();
+        memory
+    }
+
+    #[test]
+    fn latch_write_switches_banks() {
+        let mut memory = memory();
+        assert_eq!(memory.read_byte(0x8000), 0);
+
+        memory.write_byte(0xC000, 3);
+        assert_eq!(memory.bank_windows()[0].bank, 3);
+        assert_eq!(memory.read_byte(0x8000), 3);
+
+        // The latch write doesn't reach the RAM behind it
+        assert_eq!(memory.peek_byte(0xC000), 0);
+
+        // Writes land in the bank shown
+        memory.write_byte(0x8001, 0x42);
+        assert_eq!(memory.peek_physical(3 * 0x4000 + 1), 0x42);
+        memory.write_byte(0xC000, 2);
+        assert_eq!(memory.read_byte(0x8001), 0x00);
+    }
+
+    #[test]
+    fn latch_masks_unconnected_bits() {
+        let mut memory = memory();
+
+        // Three bits number six banks, $F9 selects bank 1
+        memory.write_byte(0xC000, 0xF9);
+        assert_eq!(memory.bank_windows()[0].bank, 1);
+
+        // Banks 6 and 7 don't exist
+        memory.write_byte(0xC000, 0x06);
+        assert_eq!(memory.bank_windows()[0].bank, 1);
+        memory.write_byte(0xC000, 0x05);
+        assert_eq!(memory.bank_windows()[0].bank, 5);
+    }
+
+    #[test]
+    fn physical_view() {
+        let mut memory = memory();
+        assert_eq!(memory.physical_len(), 6 * 0x4000);
+
+        memory.write_byte(0xC000, 4);
+        memory.poke_byte(0xBFFF, 0x99);
+        assert_eq!(memory.peek_physical(5 * 0x4000 - 1), 0x99);
+        for bank in 0..6 {
+            assert_eq!(memory.peek_physical(bank * 0x4000), bank as u8);
+        }
+    }
+}
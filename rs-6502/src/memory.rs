@@ -1,7 +1,70 @@
-use std::{fmt, vec};
+use std::{fmt, ops::RangeInclusive, str::FromStr};
 
+use crate::bus::{Bus, Inspect, RegionKind, RomWrite};
+use crate::device::Device;
+use crate::error::MapError;
+use crate::loader::LoadError;
+use crate::mapper::{BankWindow, Mapper};
+use crate::save_state::{StateError, StateReader, StateWriter};
+
+/// Most writes to ROM kept by [`RomWritePolicy::Log`] until they are taken
+pub const ROM_WRITE_LOG_CAPACITY: usize = 256;
+
+/// What happens when the CPU writes to a ROM region. The ROM is left unchanged by all
+/// of them.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum RomWritePolicy {
+    /// The write is silently dropped, as on real hardware
+    #[default]
+    Ignore,
+    /// The write is dropped and recorded, see [`Inspect::take_rom_writes`]
+    Log,
+    /// The write is dropped and a CPU polling for it with [`Cpu::poll_rom_faults`] stops
+    /// with [`CpuError::RomWrite`]
+    ///
+    /// [`Cpu::poll_rom_faults`]: crate::cpu::Cpu::poll_rom_faults
+    /// [`CpuError::RomWrite`]: crate::error::CpuError::RomWrite
+    Break,
+}
+
+impl FromStr for RomWritePolicy {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<RomWritePolicy, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "ignore" => Ok(RomWritePolicy::Ignore),
+            "log" => Ok(RomWritePolicy::Log),
+            "break" => Ok(RomWritePolicy::Break),
+            _ => Err(format!("Unknown ROM write policy: {}", name)),
+        }
+    }
+}
+
+/// A device attached to an address range of the bus
+struct MappedDevice {
+    range: RangeInclusive<u16>,
+    device: Box<dyn Device>,
+}
+
+/// Flat 64KB of RAM, the default device attached to the CPU bus. Ranges of it can be
+/// tagged as ROM, a mapper can show banks of a larger physical memory through windows
+/// of it, and memory-mapped devices can be attached over any address range, taking
+/// precedence over both.
 pub struct Memory {
     ram: [u8; 0x10000], // 64KB
+    /// Kind of every address, all RAM until regions are tagged
+    regions: Vec<RegionKind>,
+    rom_write_policy: RomWritePolicy,
+    /// Writes to ROM recorded under [`RomWritePolicy::Log`]
+    rom_writes: Vec<RomWrite>,
+    /// Write to ROM waiting to stop the CPU under [`RomWritePolicy::Break`]
+    rom_fault: Option<RomWrite>,
+    mapper: Option<Box<dyn Mapper>>,
+    /// Memory the banks of the mapper are taken from
+    physical: Vec<u8>,
+    /// Windows of the mapper and the banks they currently show
+    windows: Vec<BankWindow>,
+    devices: Vec<MappedDevice>,
 }
 
 impl fmt::Display for Memory {
@@ -10,47 +73,662 @@ impl fmt::Display for Memory {
         for i in self.ram.iter() {
             result.push(*i as char);
         }
-        write!(f, "{}\n", result)
+        writeln!(f, "{}", result)
     }
 }
 
 impl Memory {
+    /// Creates 64KB of zeroed RAM
     pub fn new() -> Memory {
-        Memory { ram: [0; 0x10000] }
+        Memory {
+            ram: [0; 0x10000],
+            regions: vec![RegionKind::Ram; 0x10000],
+            rom_write_policy: RomWritePolicy::default(),
+            rom_writes: Vec::new(),
+            rom_fault: None,
+            mapper: None,
+            physical: Vec::new(),
+            windows: Vec::new(),
+            devices: Vec::new(),
+        }
+    }
+
+    /// Copies an image into memory, ignoring ROM tags and devices. Addresses inside a
+    /// bank window are loaded into the bank currently shown.
+    ///
+    /// ### Parameters:
+    /// * `addr` - The address of the first byte
+    /// * `data` - The bytes to load
+    ///
+    /// ### Returns:
+    /// * `Err(LoadError)` if the image runs past `$FFFF`, nothing is loaded then
+    pub fn load(&mut self, addr: u16, data: &[u8]) -> Result<(), LoadError> {
+        if addr as usize + data.len() > 0x10000 {
+            return Err(LoadError::TooLarge {
+                addr,
+                len: data.len(),
+            });
+        }
+
+        for (offset, byte) in data.iter().enumerate() {
+            self.poke_byte(addr + offset as u16, *byte);
+        }
+
+        Ok(())
+    }
+
+    /// Tags a range of addresses as RAM or ROM. Loading an image is not affected by the
+    /// tags, only writes from the CPU are.
+    ///
+    /// ### Parameters:
+    /// * `range` - The addresses to tag, e.g. `0xE000..=0xFFFF`
+    /// * `kind` - What the range is backed by
+    pub fn set_region(&mut self, range: RangeInclusive<u16>, kind: RegionKind) {
+        let (start, end) = (*range.start() as usize, *range.end() as usize);
+        if start <= end {
+            self.regions[start..=end].fill(kind);
+        }
+    }
+
+    /// Selects what happens when the CPU writes to ROM
+    pub fn set_rom_write_policy(&mut self, policy: RomWritePolicy) {
+        self.rom_write_policy = policy;
+    }
+
+    pub fn rom_write_policy(&self) -> RomWritePolicy {
+        self.rom_write_policy
+    }
+
+    /// Attaches a mapper, replacing the previous one, and shows the banks it selects at
+    /// power on
+    ///
+    /// ### Parameters:
+    /// * `mapper` - The bank switching logic
+    /// * `physical` - The memory the banks are taken from, e.g. a whole cartridge ROM
+    ///
+    /// ### Returns:
+    /// * `Err(MapError)` if `physical` is empty, or a window of the mapper is empty or
+    ///   overlaps another one
+    pub fn set_mapper(
+        &mut self,
+        mapper: Box<dyn Mapper>,
+        physical: Vec<u8>,
+    ) -> Result<(), MapError> {
+        if physical.is_empty() {
+            return Err(MapError::NoPhysicalMemory);
+        }
+
+        let windows = mapper.windows();
+        for (index, window) in windows.iter().enumerate() {
+            let (start, end) = (*window.range.start(), *window.range.end());
+            if start > end {
+                return Err(MapError::EmptyRange { start, end });
+            }
+
+            if let Some(other) = windows[..index]
+                .iter()
+                .find(|other| start <= *other.range.end() && *other.range.start() <= end)
+            {
+                return Err(MapError::Overlap {
+                    start: *other.range.start(),
+                    end: *other.range.end(),
+                });
+            }
+        }
+
+        self.mapper = Some(mapper);
+        self.physical = physical;
+        self.windows = windows;
+        Ok(())
+    }
+
+    /// Returns the banked physical memory, e.g. to load banks into it
+    pub fn physical_mut(&mut self) -> &mut [u8] {
+        &mut self.physical
+    }
+
+    /// Attaches a memory-mapped device, every access to the range is forwarded to it
+    ///
+    /// ### Parameters:
+    /// * `range` - The addresses the device answers to, e.g. `0x6000..=0x600F`
+    /// * `device` - The device, receiving offsets relative to the start of the range
+    ///
+    /// ### Returns:
+    /// * `Err(MapError)` if the range is empty or overlaps another device
+    pub fn map(
+        &mut self,
+        range: RangeInclusive<u16>,
+        device: Box<dyn Device>,
+    ) -> Result<(), MapError> {
+        let (start, end) = (*range.start(), *range.end());
+        if start > end {
+            return Err(MapError::EmptyRange { start, end });
+        }
+
+        if let Some(mapped) = self
+            .devices
+            .iter()
+            .find(|mapped| start <= *mapped.range.end() && *mapped.range.start() <= end)
+        {
+            return Err(MapError::Overlap {
+                start: *mapped.range.start(),
+                end: *mapped.range.end(),
+            });
+        }
+
+        self.devices.push(MappedDevice { range, device });
+        Ok(())
+    }
+
+    /// Returns the device mapped at an address and the offset of the address within it
+    fn device_at(&self, addr: u16) -> Option<(usize, u16)> {
+        self.devices
+            .iter()
+            .position(|mapped| mapped.range.contains(&addr))
+            .map(|index| (index, addr - self.devices[index].range.start()))
+    }
+
+    /// Returns the window of the mapper covering an address
+    fn window_at(&self, addr: u16) -> Option<&BankWindow> {
+        self.windows
+            .iter()
+            .find(|window| window.range.contains(&addr))
     }
 
-    pub fn read_byte(&self, addr: u16) -> u8 {
-        self.ram[addr as usize]
+    /// Returns the byte backing an address, in a bank or in the flat RAM
+    fn storage(&mut self, addr: u16) -> &mut u8 {
+        match self.window_at(addr) {
+            Some(window) => {
+                let physical_addr = window.physical_addr(addr, self.physical.len());
+                &mut self.physical[physical_addr]
+            }
+            None => &mut self.ram[addr as usize],
+        }
+    }
+
+    /// Appends the state of the mapper and the devices to a save state
+    fn save_devices(&self, writer: &mut StateWriter) {
+        if let Some(mapper) = self.mapper.as_ref() {
+            mapper.save_state(writer);
+        }
+
+        // Devices are restored in mapping order, so the same devices must be mapped
+        writer.write_u16(self.devices.len() as u16);
+        for mapped in self.devices.iter() {
+            mapped.device.save_state(writer);
+        }
+    }
+
+    /// Restores what [`Memory::save_devices`] saved
+    fn load_devices(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        if let Some(mapper) = self.mapper.as_mut() {
+            mapper.load_state(reader)?;
+        }
+
+        if reader.read_u16()? as usize != self.devices.len() {
+            return Err(StateError::Invalid("number of devices"));
+        }
+        for mapped in self.devices.iter_mut() {
+            mapped.device.load_state(reader)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Memory {
+        Memory::new()
+    }
+}
+
+impl Bus for Memory {
+    fn read_byte(&mut self, addr: u16) -> u8 {
+        match self.device_at(addr) {
+            Some((index, offset)) => self.devices[index].device.read(offset),
+            None => self.peek_byte(addr),
+        }
+    }
+
+    fn write_byte(&mut self, addr: u16, data: u8) {
+        // The mapper decodes every write first, its registers may sit anywhere
+        if let Some(mapper) = self.mapper.as_mut() {
+            if mapper.write(addr, data, &mut self.windows) {
+                return;
+            }
+        }
+
+        match self.device_at(addr) {
+            Some((index, offset)) => self.devices[index].device.write(offset, data),
+            None if self.region(addr) == RegionKind::Rom => {
+                let write = RomWrite { addr, data };
+
+                match self.rom_write_policy {
+                    RomWritePolicy::Ignore => {}
+                    RomWritePolicy::Log => {
+                        if self.rom_writes.len() < ROM_WRITE_LOG_CAPACITY {
+                            self.rom_writes.push(write);
+                        }
+                    }
+                    RomWritePolicy::Break => self.rom_fault = Some(write),
+                }
+            }
+            None => *self.storage(addr) = data,
+        }
+    }
+
+    fn peek_byte(&self, addr: u16) -> u8 {
+        if let Some((index, offset)) = self.device_at(addr) {
+            return self.devices[index].device.peek(offset);
+        }
+
+        match self.window_at(addr) {
+            Some(window) => self.physical[window.physical_addr(addr, self.physical.len())],
+            None => self.ram[addr as usize],
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        for mapped in self.devices.iter_mut() {
+            mapped.device.tick(cycles);
+        }
     }
 
-    pub fn read_word(&self, addr: u16) -> u16 {
-        // Little Endian implementation
-        let least_significant = self.ram[addr as usize] as u16;
-        let most_significant = self.ram[(addr + 1) as usize] as u16;
-        (most_significant << 8) | least_significant
+    fn irq(&self) -> bool {
+        self.devices.iter().any(|mapped| mapped.device.irq())
     }
 
-    pub fn write_byte(&mut self, addr: u16, data: u8) {
-        self.ram[addr as usize] = data;
+    fn nmi(&self) -> bool {
+        self.devices.iter().any(|mapped| mapped.device.nmi())
     }
+}
 
-    pub fn load(&mut self, data: Vec<u8>) {
-        for (i, byte) in data.iter().enumerate() {
-            self.ram[i] = *byte;
+impl Inspect for Memory {
+    fn poke_byte(&mut self, addr: u16, data: u8) {
+        if self.device_at(addr).is_none() {
+            *self.storage(addr) = data;
         }
     }
 
-    pub fn get_ram(&self, first_index: usize, mut size: usize) -> Vec<u8> {
-        if first_index as u32 + size as u32 > 0x10000 {
-            let new_size = 0x10000 - first_index;
-            size = new_size;
+    fn bank_windows(&self) -> Vec<BankWindow> {
+        self.windows.clone()
+    }
+
+    fn set_bank_windows(&mut self, windows: &[BankWindow]) {
+        if windows.len() == self.windows.len() {
+            self.windows.clone_from_slice(windows);
         }
+    }
 
-        let mut ram_vec = vec![0; size];
-        for i in 0..size {
-            ram_vec[i] = self.ram[first_index + i];
+    fn physical_len(&self) -> usize {
+        self.physical.len()
+    }
+
+    fn peek_physical(&self, addr: usize) -> u8 {
+        self.physical[addr]
+    }
+
+    fn region(&self, addr: u16) -> RegionKind {
+        match self.window_at(addr) {
+            Some(window) => window.kind,
+            None => self.regions[addr as usize],
         }
+    }
 
-        ram_vec
+    fn take_rom_fault(&mut self) -> Option<RomWrite> {
+        self.rom_fault.take()
+    }
+
+    fn take_rom_writes(&mut self) -> Vec<RomWrite> {
+        std::mem::take(&mut self.rom_writes)
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.ram);
+
+        // The mapper must be the same, only the contents of its banks and the banks
+        // shown are saved
+        writer.write_u64(self.physical.len() as u64);
+        writer.write_bytes(&self.physical);
+        writer.write_u16(self.windows.len() as u16);
+        for window in self.windows.iter() {
+            writer.write_u64(window.bank as u64);
+            writer.write_bool(window.kind == RegionKind::Rom);
+        }
+        self.save_devices(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        // Everything is read and checked before anything changes, so an invalid state
+        // leaves the memory as it was
+        let ram = reader.read_bytes(self.ram.len())?;
+
+        if reader.read_u64()? != self.physical.len() as u64 {
+            return Err(StateError::Invalid("size of physical memory"));
+        }
+        let physical = reader.read_bytes(self.physical.len())?;
+
+        if reader.read_u16()? as usize != self.windows.len() {
+            return Err(StateError::Invalid("number of bank windows"));
+        }
+        let mut banks = Vec::with_capacity(self.windows.len());
+        for _ in 0..self.windows.len() {
+            let bank = reader.read_u64()? as usize;
+            let kind = if reader.read_bool()? {
+                RegionKind::Rom
+            } else {
+                RegionKind::Ram
+            };
+            banks.push((bank, kind));
+        }
+
+        // The mapper and the devices restore themselves, they are put back as they were
+        // if one of them rejects its state
+        let mut backup = StateWriter::new();
+        self.save_devices(&mut backup);
+        if let Err(error) = self.load_devices(reader) {
+            let backup = backup.into_bytes();
+            StateReader::new(&backup)
+                .and_then(|mut reader| self.load_devices(&mut reader))
+                .expect("The devices accept the state they just saved");
+            return Err(error);
+        }
+
+        self.ram.copy_from_slice(ram);
+        self.physical.copy_from_slice(physical);
+        for (window, (bank, kind)) in self.windows.iter_mut().zip(banks) {
+            window.bank = bank;
+            window.kind = kind;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Device holding one register, rejecting saved values above 0x7F
+    struct Latch(u8);
+
+    impl Device for Latch {
+        fn read(&mut self, _offset: u16) -> u8 {
+            self.0
+        }
+
+        fn write(&mut self, _offset: u16, value: u8) {
+            self.0 = value;
+        }
+
+        fn save_state(&self, writer: &mut StateWriter) {
+            writer.write_u8(self.0);
+        }
+
+        fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+            match reader.read_u8()? {
+                value if value <= 0x7F => {
+                    self.0 = value;
+                    Ok(())
+                }
+                _ => Err(StateError::Invalid("latch value")),
+            }
+        }
+    }
+
+    /// Device with four registers, read and written at their offset
+    struct Registers([u8; 4]);
+
+    impl Device for Registers {
+        fn read(&mut self, offset: u16) -> u8 {
+            self.0[offset as usize]
+        }
+
+        fn write(&mut self, offset: u16, value: u8) {
+            self.0[offset as usize] = value;
+        }
+
+        fn peek(&self, offset: u16) -> u8 {
+            self.0[offset as usize]
+        }
+    }
+
+    /// Device pulling the IRQ or the NMI line once a number of cycles has elapsed
+    struct Timer {
+        remaining: u64,
+        nmi: bool,
+    }
+
+    impl Device for Timer {
+        fn read(&mut self, _offset: u16) -> u8 {
+            0
+        }
+
+        fn write(&mut self, _offset: u16, _value: u8) {}
+
+        fn tick(&mut self, cycles: u64) {
+            self.remaining = self.remaining.saturating_sub(cycles);
+        }
+
+        fn irq(&self) -> bool {
+            !self.nmi && self.remaining == 0
+        }
+
+        fn nmi(&self) -> bool {
+            self.nmi && self.remaining == 0
+        }
+    }
+
+    /// Memory with latches at $6000 and $6001 and a banked window at $8000-$BFFF
+    fn memory() -> Memory {
+        use crate::mapper::LatchMapper;
+
+        let mut memory = Memory::new();
+        let mapper = LatchMapper::new(
+            0x8000..=0xBFFF,
+            vec![BankWindow::new(0x8000..=0xBFFF, 0, RegionKind::Ram)],
+            0,
+            4,
+        );
+        memory
+            .set_mapper(Box::new(mapper), vec![0; 4 * 0x4000])
+            .unwrap();
+        memory.map(0x6000..=0x6000, Box::new(Latch(0))).unwrap();
+        memory.map(0x6001..=0x6001, Box::new(Latch(0))).unwrap();
+        memory
+    }
+
+    fn save(memory: &Memory) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        memory.save_state(&mut writer);
+        writer.into_bytes()
+    }
+
+    fn load(memory: &mut Memory, state: &[u8]) -> Result<(), StateError> {
+        memory.load_state(&mut StateReader::new(state)?)
+    }
+
+    #[test]
+    fn load_state_restores_everything() {
+        let mut memory = memory();
+        memory.write_byte(0x0010, 0x11);
+        memory.write_byte(0x6000, 0x22);
+        memory.poke_byte(0x8000, 0x33);
+        let state = save(&memory);
+
+        memory.write_byte(0x0010, 0x44);
+        memory.write_byte(0x6000, 0x55);
+        memory.poke_byte(0x8000, 0x66);
+        memory.write_byte(0x8000, 2);
+        load(&mut memory, &state).unwrap();
+
+        assert_eq!(memory.peek_byte(0x0010), 0x11);
+        assert_eq!(memory.read_byte(0x6000), 0x22);
+        assert_eq!(memory.bank_windows()[0].bank, 0);
+        assert_eq!(memory.peek_byte(0x8000), 0x33);
+    }
+
+    #[test]
+    fn invalid_state_leaves_memory_untouched() {
+        let mut memory = memory();
+        memory.write_byte(0x0010, 0x11);
+        memory.write_byte(0x6000, 0x22);
+        let mut state = save(&memory);
+
+        memory.write_byte(0x0010, 0x44);
+        memory.write_byte(0x6000, 0x55);
+        memory.write_byte(0x8000, 2);
+
+        // The value of the second latch is the last byte, it is rejected once the first
+        // latch was restored
+        *state.last_mut().unwrap() = 0x80;
+        assert!(matches!(
+            load(&mut memory, &state),
+            Err(StateError::Invalid("latch value"))
+        ));
+        state.pop();
+        assert!(matches!(
+            load(&mut memory, &state),
+            Err(StateError::Truncated)
+        ));
+
+        assert_eq!(memory.peek_byte(0x0010), 0x44);
+        assert_eq!(memory.read_byte(0x6000), 0x55);
+        assert_eq!(memory.bank_windows()[0].bank, 2);
+    }
+
+    #[test]
+    fn rom_write_policies() {
+        let write = RomWrite {
+            addr: 0xE000,
+            data: 0x55,
+        };
+
+        for policy in [
+            RomWritePolicy::Ignore,
+            RomWritePolicy::Log,
+            RomWritePolicy::Break,
+        ] {
+            let mut memory = Memory::new();
+            memory.load(0xE000, &[0xAA]).unwrap();
+            memory.set_region(0xE000..=0xFFFF, RegionKind::Rom);
+            memory.set_rom_write_policy(policy);
+
+            memory.write_byte(0xE000, 0x55);
+            memory.write_byte(0xDFFF, 0x66);
+
+            assert_eq!(memory.peek_byte(0xE000), 0xAA, "{:?}", policy);
+            assert_eq!(memory.peek_byte(0xDFFF), 0x66, "{:?}", policy);
+            let logged = match policy {
+                RomWritePolicy::Log => vec![write],
+                _ => Vec::new(),
+            };
+            assert_eq!(memory.take_rom_writes(), logged, "{:?}", policy);
+            assert_eq!(
+                memory.take_rom_fault(),
+                (policy == RomWritePolicy::Break).then_some(write),
+                "{:?}",
+                policy
+            );
+        }
+    }
+
+    #[test]
+    fn rom_write_policy_names() {
+        assert_eq!("ignore".parse(), Ok(RomWritePolicy::Ignore));
+        assert_eq!("LOG".parse(), Ok(RomWritePolicy::Log));
+        assert_eq!("break".parse(), Ok(RomWritePolicy::Break));
+        assert_eq!(
+            "stop".parse::<RomWritePolicy>(),
+            Err(String::from("Unknown ROM write policy: stop"))
+        );
+    }
+
+    #[test]
+    fn devices_see_relative_offsets() {
+        let mut memory = Memory::new();
+        memory
+            .map(
+                0x6000..=0x6003,
+                Box::new(Registers([0x10, 0x11, 0x12, 0x13])),
+            )
+            .unwrap();
+
+        assert_eq!(memory.read_byte(0x6001), 0x11);
+        memory.write_byte(0x6003, 0x99);
+        assert_eq!(memory.read_byte(0x6003), 0x99);
+        assert_eq!(memory.peek_byte(0x6002), 0x12);
+
+        // The RAM under the device is neither read nor written
+        memory.poke_byte(0x6000, 0x55);
+        assert_eq!(memory.read_byte(0x6000), 0x10);
+        assert_eq!(memory.ram[0x6003], 0x00);
+        memory.write_byte(0x6004, 0x77);
+        assert_eq!(memory.read_byte(0x6004), 0x77);
+    }
+
+    #[test]
+    fn map_errors() {
+        let mut memory = Memory::new();
+        memory
+            .map(0x6000..=0x6003, Box::new(Registers([0; 4])))
+            .unwrap();
+
+        // Built from variables, a literal reversed range is a lint error
+        let (start, end) = (0x7001, 0x7000);
+        assert_eq!(
+            memory.map(start..=end, Box::new(Latch(0))),
+            Err(MapError::EmptyRange {
+                start: 0x7001,
+                end: 0x7000
+            })
+        );
+        for range in [0x5FFF..=0x6000, 0x6003..=0x6003, 0x5000..=0x7000] {
+            assert_eq!(
+                memory.map(range, Box::new(Latch(0))),
+                Err(MapError::Overlap {
+                    start: 0x6000,
+                    end: 0x6003
+                })
+            );
+        }
+        assert_eq!(memory.map(0x6004..=0x6004, Box::new(Latch(0))), Ok(()));
+    }
+
+    #[test]
+    fn device_interrupts_reach_the_cpu() {
+        use crate::cpu::{Cpu, IRQ_VECTOR, NMI_VECTOR};
+
+        for (nmi, vector) in [(false, 0x8000), (true, 0x9000)] {
+            let mut memory = Memory::new();
+            memory.load(0x0200, &[0xEA; 0x20]).unwrap();
+            memory.load(IRQ_VECTOR, &[0x00, 0x80]).unwrap();
+            memory.load(NMI_VECTOR, &[0x00, 0x90]).unwrap();
+            memory
+                .map(0x6000..=0x6000, Box::new(Timer { remaining: 10, nmi }))
+                .unwrap();
+
+            let mut cpu = Cpu::with_bus(memory);
+            cpu.pc = 0x0200;
+            cpu.sr.interrupt_disable = false;
+
+            // NOPs run until the timer has seen 10 cycles, then the interrupt follows
+            let mut cycles_before = 0;
+            while cpu.pc != vector {
+                assert!(cpu.pc < 0x0210, "no interrupt with nmi {}", nmi);
+                cycles_before = cpu.cycles;
+                cpu.execute().unwrap();
+            }
+            assert!(
+                cycles_before >= 10,
+                "interrupt after {} cycles",
+                cycles_before
+            );
+            assert!(
+                cycles_before < 12,
+                "interrupt after {} cycles",
+                cycles_before
+            );
+        }
     }
 }
@@ -0,0 +1,1020 @@
+use crate::addressing_mode::AddrMode;
+use crate::status_register::{
+    MASK_CARRY, MASK_DECIMAL, MASK_INTERRUPT, MASK_NEGATIVE, MASK_OVERFLOW, MASK_ZERO,
+};
+use crate::variant::CpuVariant;
+
+const RMB_NAMES: [&str; 8] = [
+    "RMB0", "RMB1", "RMB2", "RMB3", "RMB4", "RMB5", "RMB6", "RMB7",
+];
+const SMB_NAMES: [&str; 8] = [
+    "SMB0", "SMB1", "SMB2", "SMB3", "SMB4", "SMB5", "SMB6", "SMB7",
+];
+const BBR_NAMES: [&str; 8] = [
+    "BBR0", "BBR1", "BBR2", "BBR3", "BBR4", "BBR5", "BBR6", "BBR7",
+];
+const BBS_NAMES: [&str; 8] = [
+    "BBS0", "BBS1", "BBS2", "BBS3", "BBS4", "BBS5", "BBS6", "BBS7",
+];
+
+const MASK_NZ: u8 = MASK_NEGATIVE | MASK_ZERO;
+const MASK_NZC: u8 = MASK_NEGATIVE | MASK_ZERO | MASK_CARRY;
+const MASK_NVZC: u8 = MASK_NEGATIVE | MASK_OVERFLOW | MASK_ZERO | MASK_CARRY;
+const MASK_ALL: u8 = MASK_NVZC | MASK_DECIMAL | MASK_INTERRUPT;
+
+/// Base cycle counts of the NMOS 6502, also used by the 2A03
+#[rustfmt::skip]
+const NMOS_CYCLES: [u8; 256] = [
+    7, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 4, 4, 6, 6, 2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 4, 4, 6, 6, 2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    6, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 3, 4, 6, 6, 2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 5, 4, 6, 6, 2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4, 2, 6, 2, 6, 4, 4, 4, 4, 2, 5, 2, 5, 5, 5, 5, 5,
+    2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4, 2, 5, 2, 5, 4, 4, 4, 4, 2, 4, 2, 4, 4, 4, 4, 4,
+    2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6, 2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6, 2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+];
+
+/// Base cycle counts of the WDC 65C02. The Rockwell part only differs in `$CB` and `$DB`,
+/// which are single cycle NOPs there.
+#[rustfmt::skip]
+const CMOS_CYCLES: [u8; 256] = [
+    7, 6, 2, 1, 5, 3, 5, 5, 3, 2, 2, 1, 6, 4, 6, 5, 2, 5, 5, 1, 5, 4, 6, 5, 2, 4, 2, 1, 6, 4, 6, 5,
+    6, 6, 2, 1, 3, 3, 5, 5, 4, 2, 2, 1, 4, 4, 6, 5, 2, 5, 5, 1, 4, 4, 6, 5, 2, 4, 2, 1, 4, 4, 6, 5,
+    6, 6, 2, 1, 3, 3, 5, 5, 3, 2, 2, 1, 3, 4, 6, 5, 2, 5, 5, 1, 4, 4, 6, 5, 2, 4, 3, 1, 8, 4, 6, 5,
+    6, 6, 2, 1, 3, 3, 5, 5, 4, 2, 2, 1, 6, 4, 6, 5, 2, 5, 5, 1, 4, 4, 6, 5, 2, 4, 4, 1, 6, 4, 6, 5,
+    3, 6, 2, 1, 3, 3, 3, 5, 2, 2, 2, 1, 4, 4, 4, 5, 2, 6, 5, 1, 4, 4, 4, 5, 2, 5, 2, 1, 4, 5, 5, 5,
+    2, 6, 2, 1, 3, 3, 3, 5, 2, 2, 2, 1, 4, 4, 4, 5, 2, 5, 5, 1, 4, 4, 4, 5, 2, 4, 2, 1, 4, 4, 4, 5,
+    2, 6, 2, 1, 3, 3, 5, 5, 2, 2, 2, 3, 4, 4, 6, 5, 2, 5, 5, 1, 4, 4, 6, 5, 2, 4, 3, 3, 4, 4, 7, 5,
+    2, 6, 2, 1, 3, 3, 5, 5, 2, 2, 2, 1, 4, 4, 6, 5, 2, 5, 5, 1, 4, 4, 6, 5, 2, 4, 4, 1, 4, 4, 7, 5,
+];
+
+static NMOS_OPCODES: [OpcodeInfo; 256] = build_table(CpuVariant::Nmos6502);
+static WDC_OPCODES: [OpcodeInfo; 256] = build_table(CpuVariant::Wdc65C02);
+static ROCKWELL_OPCODES: [OpcodeInfo; 256] = build_table(CpuVariant::Rockwell65C02);
+
+/// Everything the emulator knows about one opcode on one CPU variant. Execution,
+/// disassembly and the TUI all read from the same table so they can't disagree.
+#[derive(Clone, Copy)]
+pub struct OpcodeInfo {
+    /// Assembly mnemonic, e.g. `LDA` or `BBR3`
+    pub mnemonic: &'static str,
+    /// Short description of what the instruction does
+    pub description: &'static str,
+    /// Addressing mode of the operand
+    pub addr_mode: AddrMode,
+    /// Length of the instruction in bytes, opcode included
+    pub bytes: u8,
+    /// Cycles taken when no page is crossed and no branch is taken, BRA is always
+    /// taken and includes that cycle
+    pub cycles: u8,
+    /// Whether crossing a page boundary costs one more cycle. Branches also take one
+    /// more cycle whenever they are taken.
+    pub page_cross_penalty: bool,
+    /// Status register bits the instruction may change, laid out as in the status byte
+    pub flags: u8,
+    /// Whether the opcode belongs to the documented instruction set of the variant
+    pub documented: bool,
+    pub(crate) operation: Operation,
+}
+
+/// Returns the table entry describing an opcode on the given CPU variant
+///
+/// ### Parameters:
+/// * `opcode` - The opcode to look up
+/// * `variant` - The CPU variant decoding the opcode
+///
+/// ### Returns:
+/// * The opcode description, every opcode has one
+pub fn opcode_info(opcode: u8, variant: CpuVariant) -> &'static OpcodeInfo {
+    let table = match variant {
+        CpuVariant::Nmos6502 | CpuVariant::Ricoh2A03 => &NMOS_OPCODES,
+        CpuVariant::Wdc65C02 => &WDC_OPCODES,
+        CpuVariant::Rockwell65C02 => &ROCKWELL_OPCODES,
+    };
+
+    &table[opcode as usize]
+}
+
+/// Builds the 256 entry opcode table of a CPU variant at compile time
+const fn build_table(variant: CpuVariant) -> [OpcodeInfo; 256] {
+    let mut table = [OpcodeInfo {
+        mnemonic: "",
+        description: "",
+        addr_mode: AddrMode::Impl,
+        bytes: 1,
+        cycles: 0,
+        page_cross_penalty: false,
+        flags: 0,
+        documented: false,
+        operation: Operation::Nop,
+    }; 256];
+
+    let mut index = 0;
+    while index < 256 {
+        let opcode = index as u8;
+        let operation = decode(opcode, variant);
+        let addr_mode = addr_mode(opcode, variant);
+        let (mnemonic, description) = operation.name_and_description();
+
+        table[index] = OpcodeInfo {
+            mnemonic,
+            description,
+            addr_mode,
+            bytes: addr_mode.instruction_bytes(),
+            cycles: base_cycles(opcode, operation, variant),
+            page_cross_penalty: has_page_cross_penalty(opcode, operation, addr_mode, variant),
+            flags: flags_affected(opcode, operation, variant),
+            documented: is_documented(opcode, operation),
+            operation,
+        };
+        index += 1;
+    }
+
+    table
+}
+
+/// Returns the cycles an opcode takes before any page cross or taken branch
+const fn base_cycles(opcode: u8, operation: Operation, variant: CpuVariant) -> u8 {
+    if !variant.is_cmos() {
+        return NMOS_CYCLES[opcode as usize];
+    }
+
+    match operation {
+        // The unused opcodes in the $x3 and $xB columns finish within the fetch
+        Operation::Nop if opcode & 0x07 == 0x03 => 1,
+        _ => CMOS_CYCLES[opcode as usize],
+    }
+}
+
+/// Returns whether an opcode takes one more cycle when its effective address crosses a
+/// page boundary
+const fn has_page_cross_penalty(
+    opcode: u8,
+    operation: Operation,
+    addr_mode: AddrMode,
+    variant: CpuVariant,
+) -> bool {
+    match addr_mode {
+        AddrMode::Rel | AddrMode::ZeroPageRel => true,
+        AddrMode::AbsX | AddrMode::AbsY | AddrMode::IndY => match operation.access() {
+            Access::Read => true,
+            Access::Write => false,
+            // The 65C02 only spends the extra cycle on shifts and rotates that cross
+            Access::Modify => {
+                variant.is_cmos()
+                    && matches!(
+                        operation,
+                        Operation::Asl | Operation::Lsr | Operation::Rol | Operation::Ror
+                    )
+                    && opcode & 0x1F == 0x1E
+            }
+        },
+        _ => false,
+    }
+}
+
+/// Returns the status register bits an opcode may change
+const fn flags_affected(opcode: u8, operation: Operation, variant: CpuVariant) -> u8 {
+    match operation {
+        Operation::Adc | Operation::Sbc | Operation::Rra | Operation::Isc | Operation::Arr => {
+            MASK_NVZC
+        }
+        Operation::Lda
+        | Operation::Ldx
+        | Operation::Ldy
+        | Operation::Tax
+        | Operation::Tay
+        | Operation::Tsx
+        | Operation::Txa
+        | Operation::Tya
+        | Operation::Pla
+        | Operation::Plx
+        | Operation::Ply
+        | Operation::And
+        | Operation::Ora
+        | Operation::Eor
+        | Operation::Inc
+        | Operation::Dec
+        | Operation::Inx
+        | Operation::Iny
+        | Operation::Dex
+        | Operation::Dey
+        | Operation::Lax
+        | Operation::Las
+        | Operation::Lxa
+        | Operation::Ane => MASK_NZ,
+        Operation::Asl
+        | Operation::Lsr
+        | Operation::Rol
+        | Operation::Ror
+        | Operation::Cmp
+        | Operation::Cpx
+        | Operation::Cpy
+        | Operation::Slo
+        | Operation::Rla
+        | Operation::Sre
+        | Operation::Dcp
+        | Operation::Sbx
+        | Operation::Alr
+        | Operation::Anc => MASK_NZC,
+        // BIT immediate has no memory operand to copy N and V from
+        Operation::Bit if opcode == 0x89 => MASK_ZERO,
+        Operation::Bit => MASK_NEGATIVE | MASK_OVERFLOW | MASK_ZERO,
+        Operation::Trb | Operation::Tsb => MASK_ZERO,
+        Operation::Clc | Operation::Sec => MASK_CARRY,
+        Operation::Cld | Operation::Sed => MASK_DECIMAL,
+        Operation::Cli | Operation::Sei => MASK_INTERRUPT,
+        Operation::Clv => MASK_OVERFLOW,
+        Operation::Plp | Operation::Rti => MASK_ALL,
+        Operation::Brk if variant.is_cmos() => MASK_INTERRUPT | MASK_DECIMAL,
+        Operation::Brk => MASK_INTERRUPT,
+        _ => 0,
+    }
+}
+
+/// Returns the addressing mode of any opcode on the given CPU variant
+const fn addr_mode(opcode: u8, variant: CpuVariant) -> AddrMode {
+    if variant.is_cmos() {
+        cmos_addr_mode(opcode)
+    } else {
+        nmos_addr_mode(opcode)
+    }
+}
+
+/// Returns the addressing mode of a 65C02 opcode, which only differs from the NMOS
+/// layout where new instructions replaced undocumented ones
+const fn cmos_addr_mode(opcode: u8) -> AddrMode {
+    match opcode {
+        // One byte NOPs, WAI and STP
+        _ if opcode & 0x07 == 0x03 => AddrMode::Impl,
+        // RMB and SMB
+        _ if opcode & 0x0F == 0x07 => AddrMode::ZeroPage,
+        // BBR and BBS
+        _ if opcode & 0x0F == 0x0F => AddrMode::ZeroPageRel,
+        // (zp) column
+        _ if opcode & 0x1F == 0x12 => AddrMode::ZeroPageInd,
+        0x02 | 0x22 | 0x42 | 0x62 => AddrMode::Immediate,
+        0x1A | 0x3A => AddrMode::Accumulator,
+        0x14 => AddrMode::ZeroPage,
+        0x1C | 0x5C | 0x9C | 0xDC | 0xFC => AddrMode::Abs,
+        0x7C => AddrMode::AbsIndX,
+        0x80 => AddrMode::Rel,
+        0x9E => AddrMode::AbsX,
+        _ => nmos_addr_mode(opcode),
+    }
+}
+
+/// Returns the addressing mode of any NMOS 6502 opcode, documented or not
+///
+/// Opcodes are laid out as `aaabbbcc`: `cc` selects the instruction group and `bbb`
+/// the addressing mode within it. The undocumented `cc = 11` group combines the
+/// modes of the `01` and `10` groups.
+const fn nmos_addr_mode(opcode: u8) -> AddrMode {
+    let aaa = opcode >> 5;
+    let bbb = (opcode >> 2) & 0x07;
+    let cc = opcode & 0x03;
+
+    // STX/LDX and their undocumented neighbours index with Y instead of X
+    let uses_y = aaa == 0x04 || aaa == 0x05;
+
+    match (cc, bbb) {
+        (0x00, 0x00) => match aaa {
+            0x01 => AddrMode::Abs,
+            0x00 | 0x02 | 0x03 => AddrMode::Impl,
+            _ => AddrMode::Immediate,
+        },
+        (0x00, 0x03) if opcode == 0x6C => AddrMode::Ind,
+        (0x00, 0x04) => AddrMode::Rel,
+        (0x00, 0x02) | (0x00, 0x06) => AddrMode::Impl,
+        (0x02, 0x00) => match aaa {
+            0x00..=0x03 => AddrMode::Impl,
+            _ => AddrMode::Immediate,
+        },
+        (0x02, 0x02) => match aaa {
+            0x00..=0x03 => AddrMode::Accumulator,
+            _ => AddrMode::Impl,
+        },
+        (0x02, 0x04) | (0x02, 0x06) => AddrMode::Impl,
+        (0x01, 0x00) | (0x03, 0x00) => AddrMode::IndX,
+        (0x01, 0x04) | (0x03, 0x04) => AddrMode::IndY,
+        (0x01, 0x06) | (0x03, 0x06) => AddrMode::AbsY,
+        (_, 0x01) => AddrMode::ZeroPage,
+        (_, 0x02) => AddrMode::Immediate,
+        (_, 0x03) => AddrMode::Abs,
+        (0x02, 0x05) | (0x03, 0x05) if uses_y => AddrMode::ZeroPageY,
+        (0x02, 0x07) | (0x03, 0x07) if uses_y => AddrMode::AbsY,
+        (_, 0x05) => AddrMode::ZeroPageX,
+        _ => AddrMode::AbsX,
+    }
+}
+
+/// Enumerates the operations understood by the CPU
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Operation {
+    Adc,
+    And,
+    Asl,
+    Bcc,
+    Bcs,
+    Beq,
+    Bit,
+    Bmi,
+    Bne,
+    Bpl,
+    Brk,
+    Bvc,
+    Bvs,
+    Clc,
+    Cld,
+    Cli,
+    Clv,
+    Cmp,
+    Cpx,
+    Cpy,
+    Dec,
+    Dex,
+    Dey,
+    Eor,
+    Inc,
+    Inx,
+    Iny,
+    Jmp,
+    Jsr,
+    Lda,
+    Ldx,
+    Ldy,
+    Lsr,
+    Nop,
+    Ora,
+    Pha,
+    Php,
+    Pla,
+    Plp,
+    Rol,
+    Ror,
+    Rti,
+    Rts,
+    Sbc,
+    Sec,
+    Sed,
+    Sei,
+    Sta,
+    Stx,
+    Sty,
+    Tax,
+    Tay,
+    Tsx,
+    Txa,
+    Txs,
+    Tya,
+    // Undocumented NMOS instructions
+    Alr,
+    Anc,
+    Ane,
+    Arr,
+    Dcp,
+    Isc,
+    Jam,
+    Las,
+    Lax,
+    Lxa,
+    Rla,
+    Rra,
+    Sax,
+    Sbx,
+    Sha,
+    Shx,
+    Shy,
+    Slo,
+    Sre,
+    Tas,
+    // 65C02 instructions
+    Bra,
+    Phx,
+    Phy,
+    Plx,
+    Ply,
+    Stz,
+    Trb,
+    Tsb,
+    Wai,
+    Stp,
+    Bbr(u8),
+    Bbs(u8),
+    Rmb(u8),
+    Smb(u8),
+}
+
+/// How an instruction uses the bus once its effective address is known
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Access {
+    /// Reads the operand
+    Read,
+    /// Writes a register to memory
+    Write,
+    /// Reads the operand, writes it back unmodified and then writes the result
+    Modify,
+}
+
+impl Operation {
+    /// Returns how the operation uses its effective address
+    pub(crate) const fn access(self) -> Access {
+        match self {
+            Operation::Sta
+            | Operation::Stx
+            | Operation::Sty
+            | Operation::Sax
+            | Operation::Sha
+            | Operation::Shx
+            | Operation::Shy
+            | Operation::Tas
+            | Operation::Stz => Access::Write,
+            Operation::Asl
+            | Operation::Lsr
+            | Operation::Rol
+            | Operation::Ror
+            | Operation::Inc
+            | Operation::Dec
+            | Operation::Slo
+            | Operation::Rla
+            | Operation::Sre
+            | Operation::Rra
+            | Operation::Dcp
+            | Operation::Isc
+            | Operation::Trb
+            | Operation::Tsb
+            | Operation::Rmb(_)
+            | Operation::Smb(_) => Access::Modify,
+            _ => Access::Read,
+        }
+    }
+
+    /// Returns whether the operation only exists as an undocumented NMOS opcode
+    pub(crate) const fn is_undocumented(self) -> bool {
+        matches!(
+            self,
+            Operation::Alr
+                | Operation::Anc
+                | Operation::Ane
+                | Operation::Arr
+                | Operation::Dcp
+                | Operation::Isc
+                | Operation::Jam
+                | Operation::Las
+                | Operation::Lax
+                | Operation::Lxa
+                | Operation::Rla
+                | Operation::Rra
+                | Operation::Sax
+                | Operation::Sbx
+                | Operation::Sha
+                | Operation::Shx
+                | Operation::Shy
+                | Operation::Slo
+                | Operation::Sre
+                | Operation::Tas
+        )
+    }
+
+    /// Returns the mnemonic and a short description of the operation
+    const fn name_and_description(self) -> (&'static str, &'static str) {
+        match self {
+            Operation::Adc => ("ADC", "Add w/ Carry"),
+            Operation::And => ("AND", "Logical AND"),
+            Operation::Asl => ("ASL", "Shift Left"),
+            Operation::Bcc => ("BCC", "Branch Carry Clear"),
+            Operation::Bcs => ("BCS", "Branch Carry Set"),
+            Operation::Beq => ("BEQ", "Branch Zero"),
+            Operation::Bit => ("BIT", "Bit Test"),
+            Operation::Bmi => ("BMI", "Branch Minus"),
+            Operation::Bne => ("BNE", "Branch Not Zero"),
+            Operation::Bpl => ("BPL", "Branch Plus"),
+            Operation::Brk => ("BRK", "Break"),
+            Operation::Bvc => ("BVC", "Branch Overflow Clr"),
+            Operation::Bvs => ("BVS", "Branch Overflow Set"),
+            Operation::Clc => ("CLC", "Clear Carry"),
+            Operation::Cld => ("CLD", "Clear Decimal"),
+            Operation::Cli => ("CLI", "Clear Interrupt"),
+            Operation::Clv => ("CLV", "Clear Overflow"),
+            Operation::Cmp => ("CMP", "Compare"),
+            Operation::Cpx => ("CPX", "Compare X"),
+            Operation::Cpy => ("CPY", "Compare Y"),
+            Operation::Dec => ("DEC", "Decrement"),
+            Operation::Dex => ("DEX", "Decrement X"),
+            Operation::Dey => ("DEY", "Decrement Y"),
+            Operation::Eor => ("EOR", "Exclusive OR"),
+            Operation::Inc => ("INC", "Increment"),
+            Operation::Inx => ("INX", "Increment X"),
+            Operation::Iny => ("INY", "Increment Y"),
+            Operation::Jmp => ("JMP", "Jump"),
+            Operation::Jsr => ("JSR", "Jump Subroutine"),
+            Operation::Lda => ("LDA", "Load Acc"),
+            Operation::Ldx => ("LDX", "Load X Reg"),
+            Operation::Ldy => ("LDY", "Load Y Reg"),
+            Operation::Lsr => ("LSR", "Shift Right"),
+            Operation::Nop => ("NOP", "No operation"),
+            Operation::Ora => ("ORA", "Inclusive OR"),
+            Operation::Pha => ("PHA", "Push Acc"),
+            Operation::Php => ("PHP", "Push Proc Status"),
+            Operation::Pla => ("PLA", "Pull Acc"),
+            Operation::Plp => ("PLP", "Pull Proc Status"),
+            Operation::Rol => ("ROL", "Rotate Left"),
+            Operation::Ror => ("ROR", "Rotate Right"),
+            Operation::Rti => ("RTI", "Ret Interrupt"),
+            Operation::Rts => ("RTS", "Ret Subroutine"),
+            Operation::Sbc => ("SBC", "Sub w/ Carry"),
+            Operation::Sec => ("SEC", "Set Carry"),
+            Operation::Sed => ("SED", "Set Decimal"),
+            Operation::Sei => ("SEI", "Set Interrupt"),
+            Operation::Sta => ("STA", "Store Acc"),
+            Operation::Stx => ("STX", "Store X"),
+            Operation::Sty => ("STY", "Store Y"),
+            Operation::Tax => ("TAX", "Acc -> X"),
+            Operation::Tay => ("TAY", "Acc -> Y"),
+            Operation::Tsx => ("TSX", "SP -> X"),
+            Operation::Txa => ("TXA", "X -> Acc"),
+            Operation::Txs => ("TXS", "X -> SP"),
+            Operation::Tya => ("TYA", "Y -> Acc"),
+            Operation::Alr => ("ALR", "AND + LSR"),
+            Operation::Anc => ("ANC", "AND, N -> Carry"),
+            Operation::Ane => ("ANE", "X AND Imm -> Acc"),
+            Operation::Arr => ("ARR", "AND + ROR"),
+            Operation::Dcp => ("DCP", "DEC + CMP"),
+            Operation::Isc => ("ISC", "INC + SBC"),
+            Operation::Jam => ("JAM", "Halt the CPU"),
+            Operation::Las => ("LAS", "Mem AND SP"),
+            Operation::Lax => ("LAX", "Load Acc and X"),
+            Operation::Lxa => ("LXA", "Imm -> Acc and X"),
+            Operation::Rla => ("RLA", "ROL + AND"),
+            Operation::Rra => ("RRA", "ROR + ADC"),
+            Operation::Sax => ("SAX", "Store Acc AND X"),
+            Operation::Sbx => ("SBX", "Acc AND X - Imm"),
+            Operation::Sha => ("SHA", "Store Acc AND X"),
+            Operation::Shx => ("SHX", "Store X AND High"),
+            Operation::Shy => ("SHY", "Store Y AND High"),
+            Operation::Slo => ("SLO", "ASL + ORA"),
+            Operation::Sre => ("SRE", "LSR + EOR"),
+            Operation::Tas => ("TAS", "Acc AND X -> SP"),
+            Operation::Bra => ("BRA", "Branch Always"),
+            Operation::Phx => ("PHX", "Push X"),
+            Operation::Phy => ("PHY", "Push Y"),
+            Operation::Plx => ("PLX", "Pull X"),
+            Operation::Ply => ("PLY", "Pull Y"),
+            Operation::Stz => ("STZ", "Store Zero"),
+            Operation::Trb => ("TRB", "Test Reset Bits"),
+            Operation::Tsb => ("TSB", "Test Set Bits"),
+            Operation::Wai => ("WAI", "Wait Interrupt"),
+            Operation::Stp => ("STP", "Stop"),
+            Operation::Bbr(bit) => (BBR_NAMES[bit as usize], "Branch Bit Reset"),
+            Operation::Bbs(bit) => (BBS_NAMES[bit as usize], "Branch Bit Set"),
+            Operation::Rmb(bit) => (RMB_NAMES[bit as usize], "Reset Memory Bit"),
+            Operation::Smb(bit) => (SMB_NAMES[bit as usize], "Set Memory Bit"),
+        }
+    }
+}
+
+/// Returns whether an opcode belongs to the documented instruction set of the variant
+/// that decoded it
+///
+/// ### Parameters:
+/// * `opcode` - The opcode fetched
+/// * `operation` - The operation the opcode decoded to
+const fn is_documented(opcode: u8, operation: Operation) -> bool {
+    match operation {
+        Operation::Nop => opcode == 0xEA,
+        Operation::Sbc => opcode != 0xEB,
+        _ => !operation.is_undocumented(),
+    }
+}
+
+/// Decodes an opcode into the operation it performs on the given CPU variant
+///
+/// ### Parameters:
+/// * `opcode` - The opcode to decode
+/// * `variant` - The CPU variant executing the opcode
+///
+/// ### Returns:
+/// * The operation, every opcode decodes to one
+const fn decode(opcode: u8, variant: CpuVariant) -> Operation {
+    if !variant.is_cmos() {
+        return decode_nmos(opcode);
+    }
+
+    if let Some(operation) = decode_cmos(opcode, variant) {
+        return operation;
+    }
+
+    // Every opcode left undocumented on the NMOS chip is a NOP on the 65C02
+    match decode_nmos(opcode) {
+        operation if operation.is_undocumented() => Operation::Nop,
+        operation => operation,
+    }
+}
+
+/// Decodes the opcodes the 65C02 added or changed
+const fn decode_cmos(opcode: u8, variant: CpuVariant) -> Option<Operation> {
+    let operation = match opcode {
+        // BRA
+        0x80 => Operation::Bra,
+        // PHX
+        0xDA => Operation::Phx,
+        // PHY
+        0x5A => Operation::Phy,
+        // PLX
+        0xFA => Operation::Plx,
+        // PLY
+        0x7A => Operation::Ply,
+        // STZ zpg / zpg, x / abs / abs, x
+        0x64 | 0x74 | 0x9C | 0x9E => Operation::Stz,
+        // TRB zpg / abs
+        0x14 | 0x1C => Operation::Trb,
+        // TSB zpg / abs
+        0x04 | 0x0C => Operation::Tsb,
+        // BIT immediate / zpg, x / abs, x
+        0x89 | 0x34 | 0x3C => Operation::Bit,
+        // INC accumulator
+        0x1A => Operation::Inc,
+        // DEC accumulator
+        0x3A => Operation::Dec,
+        // (zpg) versions of the ALU instructions
+        0x12 => Operation::Ora,
+        0x32 => Operation::And,
+        0x52 => Operation::Eor,
+        0x72 => Operation::Adc,
+        0x92 => Operation::Sta,
+        0xB2 => Operation::Lda,
+        0xD2 => Operation::Cmp,
+        0xF2 => Operation::Sbc,
+        // JMP (abs, x)
+        0x7C => Operation::Jmp,
+        // RMB0-7 / SMB0-7
+        _ if opcode & 0x0F == 0x07 && variant.has_bit_instructions() => {
+            let bit = (opcode >> 4) & 0x07;
+            if opcode & 0x80 == 0 {
+                Operation::Rmb(bit)
+            } else {
+                Operation::Smb(bit)
+            }
+        }
+        // BBR0-7 / BBS0-7
+        _ if opcode & 0x0F == 0x0F && variant.has_bit_instructions() => {
+            let bit = (opcode >> 4) & 0x07;
+            if opcode & 0x80 == 0 {
+                Operation::Bbr(bit)
+            } else {
+                Operation::Bbs(bit)
+            }
+        }
+        // WAI
+        0xCB if variant.has_wait_and_stop() => Operation::Wai,
+        // STP
+        0xDB if variant.has_wait_and_stop() => Operation::Stp,
+        // The undocumented SBC immediate is a NOP like the rest of its column
+        0xEB => Operation::Nop,
+        _ => return None,
+    };
+
+    Some(operation)
+}
+
+/// Decodes an opcode as the NMOS 6502 does, every opcode decodes to an operation
+const fn decode_nmos(opcode: u8) -> Operation {
+    match opcode {
+        //BRK
+        0x00 => Operation::Brk,
+        // CLC
+        0x18 => Operation::Clc,
+        // CLD
+        0xD8 => Operation::Cld,
+        // CLI
+        0x58 => Operation::Cli,
+        // CLV
+        0xB8 => Operation::Clv,
+        // NOP
+        0xEA => Operation::Nop,
+        // INY
+        0xC8 => Operation::Iny,
+        // INX
+        0xE8 => Operation::Inx,
+        // LDA immediate / zpg / zpg, x / abs / abs, x / abs, y / ind, x / ind, y
+        0xA9 | 0xA5 | 0xB5 | 0xAD | 0xBD | 0xB9 | 0xA1 | 0xB1 => Operation::Lda,
+        // LDX immediate / zpg / zpg, y / abs / abs, y
+        0xA2 | 0xA6 | 0xB6 | 0xAE | 0xBE => Operation::Ldx,
+        // LDY immediate / zpg / zpg, x / abs / abs, x
+        0xA0 | 0xA4 | 0xB4 | 0xAC | 0xBC => Operation::Ldy,
+        // LSR accumulator / zpg / zpg, x / abs / abs, x
+        0x4A | 0x46 | 0x56 | 0x4E | 0x5E => Operation::Lsr,
+        // ORA immediate / zpg / zpg, x / abs / abs, x / abs, y / (ind, x) / (ind), y
+        0x09 | 0x05 | 0x15 | 0x0D | 0x1D | 0x19 | 0x01 | 0x11 => Operation::Ora,
+        // PHA
+        0x48 => Operation::Pha,
+        // PHP
+        0x08 => Operation::Php,
+        // PLA
+        0x68 => Operation::Pla,
+        // PLP
+        0x28 => Operation::Plp,
+        // ROL accumulator / zpg / zpg, x / abs / abs, x
+        0x2A | 0x26 | 0x36 | 0x2E | 0x3E => Operation::Rol,
+        // ASL accumulator / zpg / zpg, x / abs / abs, x
+        0x0A | 0x06 | 0x16 | 0x0E | 0x1E => Operation::Asl,
+        // STA zpg / zpg, x / abs / abs, x / abs, y / (ind, x) / (ind), y
+        0x85 | 0x95 | 0x8D | 0x9D | 0x99 | 0x81 | 0x91 => Operation::Sta,
+        // STX zpg / zpg, y / abs
+        0x86 | 0x96 | 0x8E => Operation::Stx,
+        // STY zpg / zpg, x / abs
+        0x84 | 0x94 | 0x8C => Operation::Sty,
+        // TAX
+        0xAA => Operation::Tax,
+        // TAY
+        0xA8 => Operation::Tay,
+        // TSX
+        0xBA => Operation::Tsx,
+        // TXA
+        0x8A => Operation::Txa,
+        // TXS
+        0x9A => Operation::Txs,
+        // TYA
+        0x98 => Operation::Tya,
+        // ADC immediate / zpg / zpg, x / abs / abs, x / abs, y / (ind, x) / (ind), y
+        0x69 | 0x65 | 0x75 | 0x6D | 0x7D | 0x79 | 0x61 | 0x71 => Operation::Adc,
+        // AND immediate / zpg / zpg, x / abs / abs, x / abs, y / (ind, x) / (ind), y
+        0x29 | 0x25 | 0x35 | 0x2D | 0x3D | 0x39 | 0x21 | 0x31 => Operation::And,
+        // BRANCH IFS
+        0x90 => Operation::Bcc,
+        0xB0 => Operation::Bcs,
+        0xF0 => Operation::Beq,
+        0x30 => Operation::Bmi,
+        0xD0 => Operation::Bne,
+        0x10 => Operation::Bpl,
+        0x50 => Operation::Bvc,
+        0x70 => Operation::Bvs,
+        // BIT zpg / abs
+        0x24 | 0x2C => Operation::Bit,
+        // JSR
+        0x20 => Operation::Jsr,
+        //JMP abs / ind
+        0x4C | 0x6C => Operation::Jmp,
+        // RTS
+        0x60 => Operation::Rts,
+        // RTI
+        0x40 => Operation::Rti,
+        // CMP immediate / zpg / zpg, x / abs / abs, x / abs, y / (ind, x) / (ind), y
+        0xC9 | 0xC5 | 0xD5 | 0xCD | 0xDD | 0xD9 | 0xC1 | 0xD1 => Operation::Cmp,
+        // CPX immediate / zpg / abs
+        0xE0 | 0xE4 | 0xEC => Operation::Cpx,
+        // CPY immediate / zpg / abs
+        0xC0 | 0xC4 | 0xCC => Operation::Cpy,
+        // DEC zpg / zpg, x / abs / abs, x
+        0xC6 | 0xD6 | 0xCE | 0xDE => Operation::Dec,
+        // DEX
+        0xCA => Operation::Dex,
+        // DEY
+        0x88 => Operation::Dey,
+        // EOR immediate / zpg / zpg, x / abs / abs, x / abs, y / (ind, x) / (ind), y
+        0x49 | 0x45 | 0x55 | 0x4D | 0x5D | 0x59 | 0x41 | 0x51 => Operation::Eor,
+        // INC zpg / zpg, x / abs / abs, x
+        0xE6 | 0xF6 | 0xEE | 0xFE => Operation::Inc,
+        // ROR accumulator / zpg / zpg, x / abs / abs, x
+        0x6A | 0x66 | 0x76 | 0x6E | 0x7E => Operation::Ror,
+        // SBC immediate / zpg / zpg, x / abs / abs, x / abs, y / (ind, x) / (ind), y
+        0xE9 | 0xE5 | 0xF5 | 0xED | 0xFD | 0xF9 | 0xE1 | 0xF1 => Operation::Sbc,
+        // SEC
+        0x38 => Operation::Sec,
+        // SED
+        0xF8 => Operation::Sed,
+        // SEI
+        0x78 => Operation::Sei,
+        // Undocumented NMOS opcodes
+        // SLO (ind, x) / zpg / abs / (ind), y / zpg, x / abs, y / abs, x
+        0x03 | 0x07 | 0x0F | 0x13 | 0x17 | 0x1B | 0x1F => Operation::Slo,
+        // RLA (ind, x) / zpg / abs / (ind), y / zpg, x / abs, y / abs, x
+        0x23 | 0x27 | 0x2F | 0x33 | 0x37 | 0x3B | 0x3F => Operation::Rla,
+        // SRE (ind, x) / zpg / abs / (ind), y / zpg, x / abs, y / abs, x
+        0x43 | 0x47 | 0x4F | 0x53 | 0x57 | 0x5B | 0x5F => Operation::Sre,
+        // RRA (ind, x) / zpg / abs / (ind), y / zpg, x / abs, y / abs, x
+        0x63 | 0x67 | 0x6F | 0x73 | 0x77 | 0x7B | 0x7F => Operation::Rra,
+        // SAX (ind, x) / zpg / abs / zpg, y
+        0x83 | 0x87 | 0x8F | 0x97 => Operation::Sax,
+        // LAX (ind, x) / zpg / abs / (ind), y / zpg, y / abs, y
+        0xA3 | 0xA7 | 0xAF | 0xB3 | 0xB7 | 0xBF => Operation::Lax,
+        // DCP (ind, x) / zpg / abs / (ind), y / zpg, x / abs, y / abs, x
+        0xC3 | 0xC7 | 0xCF | 0xD3 | 0xD7 | 0xDB | 0xDF => Operation::Dcp,
+        // ISC (ind, x) / zpg / abs / (ind), y / zpg, x / abs, y / abs, x
+        0xE3 | 0xE7 | 0xEF | 0xF3 | 0xF7 | 0xFB | 0xFF => Operation::Isc,
+        // ANC immediate
+        0x0B | 0x2B => Operation::Anc,
+        // ALR immediate
+        0x4B => Operation::Alr,
+        // ARR immediate
+        0x6B => Operation::Arr,
+        // ANE immediate
+        0x8B => Operation::Ane,
+        // LXA immediate
+        0xAB => Operation::Lxa,
+        // SBX immediate
+        0xCB => Operation::Sbx,
+        // SBC immediate
+        0xEB => Operation::Sbc,
+        // SHA (ind), y / abs, y
+        0x93 | 0x9F => Operation::Sha,
+        // SHX abs, y
+        0x9E => Operation::Shx,
+        // SHY abs, x
+        0x9C => Operation::Shy,
+        // TAS abs, y
+        0x9B => Operation::Tas,
+        // LAS abs, y
+        0xBB => Operation::Las,
+        // NOP implied / immediate / zpg / zpg, x / abs / abs, x
+        0x1A | 0x3A | 0x5A | 0x7A | 0xDA | 0xFA => Operation::Nop,
+        0x80 | 0x82 | 0x89 | 0xC2 | 0xE2 => Operation::Nop,
+        0x04 | 0x44 | 0x64 | 0x14 | 0x34 | 0x54 | 0x74 | 0xD4 | 0xF4 => Operation::Nop,
+        0x0C | 0x1C | 0x3C | 0x5C | 0x7C | 0xDC | 0xFC => Operation::Nop,
+        // JAM
+        0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xB2 | 0xD2 | 0xF2 => {
+            Operation::Jam
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks the mnemonic, addressing mode, cycles and page cross penalty of opcodes
+    fn check(variant: CpuVariant, expected: &[(u8, &str, AddrMode, u8, bool)]) {
+        for &(opcode, mnemonic, addr_mode, cycles, penalty) in expected {
+            let info = opcode_info(opcode, variant);
+
+            assert_eq!(
+                (
+                    info.mnemonic,
+                    info.addr_mode,
+                    info.cycles,
+                    info.page_cross_penalty
+                ),
+                (mnemonic, addr_mode, cycles, penalty),
+                "{} opcode ${:02X}",
+                variant,
+                opcode
+            );
+        }
+    }
+
+    #[test]
+    fn undocumented_nmos_opcodes() {
+        let expected = [
+            (0xA7, "LAX", AddrMode::ZeroPage, 3, false),
+            (0xB7, "LAX", AddrMode::ZeroPageY, 4, false),
+            (0xBF, "LAX", AddrMode::AbsY, 4, true),
+            (0xB3, "LAX", AddrMode::IndY, 5, true),
+            (0x87, "SAX", AddrMode::ZeroPage, 3, false),
+            (0x97, "SAX", AddrMode::ZeroPageY, 4, false),
+            (0x83, "SAX", AddrMode::IndX, 6, false),
+            (0xC7, "DCP", AddrMode::ZeroPage, 5, false),
+            (0xDF, "DCP", AddrMode::AbsX, 7, false),
+            (0xD3, "DCP", AddrMode::IndY, 8, false),
+            (0xE7, "ISC", AddrMode::ZeroPage, 5, false),
+            (0xFB, "ISC", AddrMode::AbsY, 7, false),
+            (0xE3, "ISC", AddrMode::IndX, 8, false),
+            (0x07, "SLO", AddrMode::ZeroPage, 5, false),
+            (0x1F, "SLO", AddrMode::AbsX, 7, false),
+            (0x27, "RLA", AddrMode::ZeroPage, 5, false),
+            (0x33, "RLA", AddrMode::IndY, 8, false),
+            (0x47, "SRE", AddrMode::ZeroPage, 5, false),
+            (0x5B, "SRE", AddrMode::AbsY, 7, false),
+            (0x67, "RRA", AddrMode::ZeroPage, 5, false),
+            (0x63, "RRA", AddrMode::IndX, 8, false),
+            (0x0B, "ANC", AddrMode::Immediate, 2, false),
+            (0x2B, "ANC", AddrMode::Immediate, 2, false),
+            (0x4B, "ALR", AddrMode::Immediate, 2, false),
+            (0x6B, "ARR", AddrMode::Immediate, 2, false),
+        ];
+
+        check(CpuVariant::Nmos6502, &expected);
+        check(CpuVariant::Ricoh2A03, &expected);
+        for (opcode, ..) in expected {
+            assert!(!opcode_info(opcode, CpuVariant::Nmos6502).documented);
+        }
+    }
+
+    #[test]
+    fn undocumented_nmos_flags() {
+        let expected = [
+            (0xA7, MASK_NZ),
+            (0x87, 0),
+            (0xC7, MASK_NZC),
+            (0xE7, MASK_NVZC),
+            (0x07, MASK_NZC),
+            (0x27, MASK_NZC),
+            (0x47, MASK_NZC),
+            (0x67, MASK_NVZC),
+            (0x0B, MASK_NZC),
+            (0x4B, MASK_NZC),
+            (0x6B, MASK_NVZC),
+        ];
+
+        for (opcode, flags) in expected {
+            assert_eq!(
+                opcode_info(opcode, CpuVariant::Nmos6502).flags,
+                flags,
+                "opcode ${:02X}",
+                opcode
+            );
+        }
+    }
+
+    #[test]
+    fn variants_decode_differently() {
+        // Mnemonics on the NMOS 6502, the WDC 65C02 and the Rockwell R65C02
+        let expected = [
+            (0x04, ["NOP", "TSB", "TSB"]),
+            (0x07, ["SLO", "RMB0", "RMB0"]),
+            (0x0F, ["SLO", "BBR0", "BBR0"]),
+            (0x14, ["NOP", "TRB", "TRB"]),
+            (0x64, ["NOP", "STZ", "STZ"]),
+            (0x80, ["NOP", "BRA", "BRA"]),
+            (0x89, ["NOP", "BIT", "BIT"]),
+            (0xB2, ["JAM", "LDA", "LDA"]),
+            (0xCB, ["SBX", "WAI", "NOP"]),
+            (0xDA, ["NOP", "PHX", "PHX"]),
+            (0xDB, ["DCP", "STP", "NOP"]),
+            (0xF7, ["ISC", "SMB7", "SMB7"]),
+            (0xFA, ["NOP", "PLX", "PLX"]),
+            (0xFF, ["ISC", "BBS7", "BBS7"]),
+        ];
+        let variants = [
+            CpuVariant::Nmos6502,
+            CpuVariant::Wdc65C02,
+            CpuVariant::Rockwell65C02,
+        ];
+
+        for (opcode, mnemonics) in expected {
+            for (variant, mnemonic) in variants.into_iter().zip(mnemonics) {
+                assert_eq!(
+                    opcode_info(opcode, variant).mnemonic,
+                    mnemonic,
+                    "{} opcode ${:02X}",
+                    variant,
+                    opcode
+                );
+            }
+            // The 2A03 decodes like the NMOS 6502
+            assert_eq!(
+                opcode_info(opcode, CpuVariant::Ricoh2A03).mnemonic,
+                mnemonics[0]
+            );
+        }
+    }
+
+    #[test]
+    fn cmos_opcodes() {
+        let expected = [
+            (0x80, "BRA", AddrMode::Rel, 3, true),
+            (0xDA, "PHX", AddrMode::Impl, 3, false),
+            (0xFA, "PLX", AddrMode::Impl, 4, false),
+            (0x64, "STZ", AddrMode::ZeroPage, 3, false),
+            (0x9E, "STZ", AddrMode::AbsX, 5, false),
+            (0x0C, "TSB", AddrMode::Abs, 6, false),
+            (0x1C, "TRB", AddrMode::Abs, 6, false),
+            (0xB2, "LDA", AddrMode::ZeroPageInd, 5, false),
+            (0x92, "STA", AddrMode::ZeroPageInd, 5, false),
+            (0x89, "BIT", AddrMode::Immediate, 2, false),
+            (0x6C, "JMP", AddrMode::Ind, 6, false),
+            (0x7C, "JMP", AddrMode::AbsIndX, 6, false),
+        ];
+
+        check(CpuVariant::Wdc65C02, &expected);
+        check(CpuVariant::Rockwell65C02, &expected);
+
+        // BIT #imm only sets Z
+        assert_eq!(opcode_info(0x89, CpuVariant::Wdc65C02).flags, MASK_ZERO);
+        assert_eq!(
+            opcode_info(0x2C, CpuVariant::Wdc65C02).flags,
+            MASK_NEGATIVE | MASK_OVERFLOW | MASK_ZERO
+        );
+        check(
+            CpuVariant::Nmos6502,
+            &[(0x6C, "JMP", AddrMode::Ind, 5, false)],
+        );
+    }
+
+    #[test]
+    fn rockwell_bit_instructions() {
+        let expected = [
+            (0x07, "RMB0", AddrMode::ZeroPage, 5, false),
+            (0x77, "RMB7", AddrMode::ZeroPage, 5, false),
+            (0x87, "SMB0", AddrMode::ZeroPage, 5, false),
+            (0xF7, "SMB7", AddrMode::ZeroPage, 5, false),
+            (0x0F, "BBR0", AddrMode::ZeroPageRel, 5, true),
+            (0x7F, "BBR7", AddrMode::ZeroPageRel, 5, true),
+            (0x8F, "BBS0", AddrMode::ZeroPageRel, 5, true),
+            (0xFF, "BBS7", AddrMode::ZeroPageRel, 5, true),
+        ];
+
+        check(CpuVariant::Rockwell65C02, &expected);
+        check(CpuVariant::Wdc65C02, &expected);
+    }
+}
@@ -0,0 +1,427 @@
+use std::fmt;
+
+use crate::{
+    bus::{Bus, BusWrapper},
+    cpu::Cpu,
+    error::CpuError,
+    opcode::opcode_info,
+};
+
+/// Opcode of BRK on every variant
+const BRK_OPCODE: u8 = 0x00;
+
+/// Bus wrapper catching writes to one address, which programs run headless use to
+/// signal they are done
+pub struct WatchBus<B: Bus> {
+    inner: B,
+    /// Address whose writes are caught
+    watched: Option<u16>,
+    /// Last byte written to the watched address since it was last taken
+    written: Option<u8>,
+}
+
+impl<B: Bus> WatchBus<B> {
+    /// Wraps a bus, no address is watched until [`WatchBus::watch`] is called
+    ///
+    /// ### Parameters:
+    /// * `inner` - The bus doing the actual reads and writes
+    pub fn new(inner: B) -> WatchBus<B> {
+        WatchBus {
+            inner,
+            watched: None,
+            written: None,
+        }
+    }
+
+    /// Selects the address whose writes are caught, `None` to stop watching
+    pub fn watch(&mut self, addr: Option<u16>) {
+        self.watched = addr;
+        self.written = None;
+    }
+
+    /// Returns the last byte written to the watched address, if it was written since
+    /// the last call
+    pub fn take_write(&mut self) -> Option<u8> {
+        self.written.take()
+    }
+}
+
+impl<B: Bus> BusWrapper for WatchBus<B> {
+    type Inner = B;
+
+    fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    fn inner_mut(&mut self) -> &mut B {
+        &mut self.inner
+    }
+
+    fn observe_write(&mut self, addr: u16, data: u8) {
+        if self.watched == Some(addr) {
+            self.written = Some(data);
+        }
+    }
+}
+
+/// The conditions a headless run stops on, CPU errors always stop it
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct StopConditions {
+    /// Addresses the run stops at, before the instruction there executes
+    pub breakpoints: Vec<u16>,
+    /// Stop before a BRK executes
+    pub brk: bool,
+    /// Stop when an instruction jumps or branches onto itself
+    pub trap: bool,
+    /// Stop after an instruction writes to this address
+    pub magic_write: Option<u16>,
+    /// Stop once this many cycles have run
+    pub max_cycles: Option<u64>,
+    /// Stop once this many instructions have run
+    pub max_instructions: Option<u64>,
+    /// Stop before an undocumented opcode executes
+    pub illegal: bool,
+}
+
+/// Enumerates the reasons a headless run stopped
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StopReason {
+    /// The PC reached a breakpoint
+    Breakpoint { pc: u16 },
+    /// A BRK is about to execute
+    Brk { pc: u16 },
+    /// The instruction at `pc` jumped or branched onto itself
+    Trap { pc: u16 },
+    /// The watched address was written
+    MagicWrite { addr: u16, data: u8 },
+    /// The cycle budget ran out
+    CycleBudget { cycles: u64 },
+    /// The instruction budget ran out
+    InstructionBudget { instructions: u64 },
+    /// An undocumented opcode is about to execute
+    IllegalOpcode { pc: u16, opcode: u8 },
+    /// The CPU could not execute an instruction
+    Error(CpuError),
+}
+
+impl StopReason {
+    /// Returns the process exit code reporting the reason, 2 is left for invalid
+    /// command lines
+    ///
+    /// | Reason | Code |
+    /// | ------ | ---- |
+    /// | Breakpoint | 0 |
+    /// | CPU error | 1 |
+    /// | BRK | 3 |
+    /// | Trap | 4 |
+    /// | Magic write | 5 |
+    /// | Cycle budget | 6 |
+    /// | Instruction budget | 7 |
+    /// | Illegal opcode | 8 |
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            StopReason::Breakpoint { .. } => 0,
+            StopReason::Error(_) => 1,
+            StopReason::Brk { .. } => 3,
+            StopReason::Trap { .. } => 4,
+            StopReason::MagicWrite { .. } => 5,
+            StopReason::CycleBudget { .. } => 6,
+            StopReason::InstructionBudget { .. } => 7,
+            StopReason::IllegalOpcode { .. } => 8,
+        }
+    }
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Breakpoint { pc } => write!(f, "Breakpoint at 0x{:04X}", pc),
+            StopReason::Brk { pc } => write!(f, "BRK at 0x{:04X}", pc),
+            StopReason::Trap { pc } => write!(f, "Trapped at 0x{:04X}", pc),
+            StopReason::MagicWrite { addr, data } => {
+                write!(f, "Wrote 0x{:02X} to 0x{:04X}", data, addr)
+            }
+            StopReason::CycleBudget { cycles } => {
+                write!(f, "Cycle budget of {} reached", cycles)
+            }
+            StopReason::InstructionBudget { instructions } => {
+                write!(f, "Instruction budget of {} reached", instructions)
+            }
+            StopReason::IllegalOpcode { pc, opcode } => {
+                write!(f, "Illegal opcode 0x{:02X} at 0x{:04X}", opcode, pc)
+            }
+            StopReason::Error(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+/// Runs a CPU without the debugger until one of its stop conditions holds
+///
+/// ```
+/// use rs_6502::run::{Runner, StopConditions, StopReason, WatchBus};
+/// use rs_6502::{Cpu, Memory};
+///
+/// let mut memory = Memory::new();
+/// // INX, then JMP $0201
+/// memory.load(0x0200, &[0xE8, 0x4C, 0x01, 0x02]).unwrap();
+///
+/// let mut cpu = Cpu::with_bus(WatchBus::new(memory));
+/// cpu.pc = 0x0200;
+///
+/// let conditions = StopConditions {
+///     trap: true,
+///     ..StopConditions::default()
+/// };
+/// let reason = Runner::new(conditions).run(&mut cpu);
+///
+/// assert_eq!(reason, StopReason::Trap { pc: 0x0201 });
+/// assert_eq!(cpu.x, 1);
+/// ```
+pub struct Runner {
+    conditions: StopConditions,
+    /// Instructions executed so far
+    instructions: u64,
+}
+
+impl Runner {
+    /// Creates a runner stopping on the given conditions
+    pub fn new(conditions: StopConditions) -> Runner {
+        Runner {
+            conditions,
+            instructions: 0,
+        }
+    }
+
+    /// Returns the number of instructions executed so far
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Runs until a stop condition holds
+    ///
+    /// ### Parameters:
+    /// * `cpu` - The CPU to run, at an instruction boundary
+    ///
+    /// ### Returns:
+    /// * Why the run stopped
+    pub fn run<B: Bus>(&mut self, cpu: &mut Cpu<WatchBus<B>>) -> StopReason {
+        loop {
+            if let Some(reason) = self.check(cpu) {
+                return reason;
+            }
+            if let Some(reason) = self.execute(cpu) {
+                return reason;
+            }
+        }
+    }
+
+    /// Checks the conditions that stop the run before the next instruction executes
+    ///
+    /// ### Parameters:
+    /// * `cpu` - The CPU about to execute an instruction
+    ///
+    /// ### Returns:
+    /// * The reason to stop, or `None` if the instruction may execute
+    pub fn check<B: Bus>(&self, cpu: &Cpu<B>) -> Option<StopReason> {
+        let conditions = &self.conditions;
+        let pc = cpu.pc;
+        let opcode = cpu.bus.peek_byte(pc);
+
+        if conditions.breakpoints.contains(&pc) {
+            Some(StopReason::Breakpoint { pc })
+        } else if conditions.max_cycles.is_some_and(|max| cpu.cycles >= max) {
+            Some(StopReason::CycleBudget { cycles: cpu.cycles })
+        } else if conditions
+            .max_instructions
+            .is_some_and(|max| self.instructions >= max)
+        {
+            Some(StopReason::InstructionBudget {
+                instructions: self.instructions,
+            })
+        } else if conditions.brk && opcode == BRK_OPCODE {
+            Some(StopReason::Brk { pc })
+        } else if conditions.illegal && !opcode_info(opcode, cpu.variant()).documented {
+            Some(StopReason::IllegalOpcode { pc, opcode })
+        } else {
+            None
+        }
+    }
+
+    /// Executes one instruction and checks the conditions it can trigger
+    ///
+    /// ### Parameters:
+    /// * `cpu` - The CPU to step
+    ///
+    /// ### Returns:
+    /// * The reason to stop, or `None` if the run goes on
+    pub fn execute<B: Bus>(&mut self, cpu: &mut Cpu<WatchBus<B>>) -> Option<StopReason> {
+        cpu.bus.watch(self.conditions.magic_write);
+
+        let step = match cpu.execute() {
+            Ok(step) => step,
+            // Strict mode reports undocumented opcodes the same way
+            Err(CpuError::IllegalOpcode { pc, opcode }) => {
+                return Some(StopReason::IllegalOpcode { pc, opcode })
+            }
+            Err(error) => return Some(StopReason::Error(error)),
+        };
+        self.instructions += 1;
+
+        if let (Some(addr), Some(data)) = (self.conditions.magic_write, cpu.bus.take_write()) {
+            Some(StopReason::MagicWrite { addr, data })
+        } else if self.conditions.trap && step.pc == cpu.pc {
+            // A jump or taken branch onto itself leaves the PC where it was
+            Some(StopReason::Trap { pc: step.pc })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Memory;
+
+    /// CPU running `program` from $0200
+    fn machine(program: &[u8]) -> Cpu<WatchBus<Memory>> {
+        let mut memory = Memory::new();
+        memory.load(0x0200, program).unwrap();
+
+        let mut cpu = Cpu::with_bus(WatchBus::new(memory));
+        cpu.pc = 0x0200;
+        cpu
+    }
+
+    #[test]
+    fn stop_reasons() {
+        let nops = [0xEA; 0x10];
+        let expected: [(&[u8], StopConditions, StopReason, i32); 8] = [
+            (
+                &nops,
+                StopConditions {
+                    breakpoints: vec![0x0203],
+                    ..StopConditions::default()
+                },
+                StopReason::Breakpoint { pc: 0x0203 },
+                0,
+            ),
+            // INX; JAM
+            (
+                &[0xE8, 0x02],
+                StopConditions::default(),
+                StopReason::Error(CpuError::Jammed { pc: 0x0201 }),
+                1,
+            ),
+            // INX; BRK
+            (
+                &[0xE8, 0x00],
+                StopConditions {
+                    brk: true,
+                    ..StopConditions::default()
+                },
+                StopReason::Brk { pc: 0x0201 },
+                3,
+            ),
+            // INX; JMP $0201
+            (
+                &[0xE8, 0x4C, 0x01, 0x02],
+                StopConditions {
+                    trap: true,
+                    ..StopConditions::default()
+                },
+                StopReason::Trap { pc: 0x0201 },
+                4,
+            ),
+            // LDA #$2A; STA $6000
+            (
+                &[0xA9, 0x2A, 0x8D, 0x00, 0x60, 0xEA],
+                StopConditions {
+                    magic_write: Some(0x6000),
+                    ..StopConditions::default()
+                },
+                StopReason::MagicWrite {
+                    addr: 0x6000,
+                    data: 0x2A,
+                },
+                5,
+            ),
+            // Three NOPs reach the budget
+            (
+                &nops,
+                StopConditions {
+                    max_cycles: Some(5),
+                    ..StopConditions::default()
+                },
+                StopReason::CycleBudget { cycles: 6 },
+                6,
+            ),
+            (
+                &nops,
+                StopConditions {
+                    max_instructions: Some(3),
+                    ..StopConditions::default()
+                },
+                StopReason::InstructionBudget { instructions: 3 },
+                7,
+            ),
+            // INX; LAX $10
+            (
+                &[0xE8, 0xA7, 0x10],
+                StopConditions {
+                    illegal: true,
+                    ..StopConditions::default()
+                },
+                StopReason::IllegalOpcode {
+                    pc: 0x0201,
+                    opcode: 0xA7,
+                },
+                8,
+            ),
+        ];
+
+        for (program, conditions, reason, exit_code) in expected {
+            let mut cpu = machine(program);
+            assert_eq!(Runner::new(conditions).run(&mut cpu), reason);
+            assert_eq!(reason.exit_code(), exit_code, "{}", reason);
+        }
+    }
+
+    #[test]
+    fn stops_before_the_instruction() {
+        // INX; INX; BRK
+        let mut cpu = machine(&[0xE8, 0xE8, 0x00]);
+        let mut runner = Runner::new(StopConditions {
+            breakpoints: vec![0x0201],
+            brk: true,
+            ..StopConditions::default()
+        });
+
+        assert_eq!(runner.run(&mut cpu), StopReason::Breakpoint { pc: 0x0201 });
+        assert_eq!((cpu.x, runner.instructions()), (1, 1));
+
+        // Resuming from the breakpoint stops again on it, it must be stepped over
+        assert_eq!(runner.run(&mut cpu), StopReason::Breakpoint { pc: 0x0201 });
+        assert_eq!(runner.execute(&mut cpu), None);
+        assert_eq!(runner.run(&mut cpu), StopReason::Brk { pc: 0x0202 });
+        assert_eq!((cpu.x, runner.instructions()), (2, 2));
+    }
+
+    #[test]
+    fn strict_mode_stops_on_illegal_opcodes() {
+        // INX; LAX $10
+        let mut cpu = machine(&[0xE8, 0xA7, 0x10]);
+        cpu.set_strict(true);
+
+        let reason = Runner::new(StopConditions::default()).run(&mut cpu);
+        assert_eq!(
+            reason,
+            StopReason::IllegalOpcode {
+                pc: 0x0201,
+                opcode: 0xA7
+            }
+        );
+        assert_eq!(reason.exit_code(), 8);
+        assert_eq!(cpu.pc, 0x0201);
+    }
+}
@@ -0,0 +1,164 @@
+use std::{error::Error, fmt, io};
+
+/// Bytes every save state starts with
+pub const STATE_MAGIC: &[u8; 8] = b"RS6502ST";
+/// Version of the save state layout, bumped whenever the layout changes
+pub const STATE_VERSION: u16 = 3;
+
+/// Enumerates the reasons a save state could not be written or restored
+#[derive(Debug)]
+pub enum StateError {
+    /// The file could not be read or written
+    Io(io::Error),
+    /// The data does not start with [`STATE_MAGIC`]
+    NotAState,
+    /// The state was written by an incompatible version of the emulator
+    UnsupportedVersion(u16),
+    /// The data ended before the whole state was read
+    Truncated,
+    /// A field holds a value no save state can contain
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::Io(error) => write!(f, "Save state I/O error: {}", error),
+            StateError::NotAState => write!(f, "Not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported save state version {}", version)
+            }
+            StateError::Truncated => write!(f, "Save state is truncated"),
+            StateError::Invalid(field) => write!(f, "Invalid {} in save state", field),
+        }
+    }
+}
+
+impl Error for StateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StateError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for StateError {
+    fn from(error: io::Error) -> StateError {
+        StateError::Io(error)
+    }
+}
+
+/// Builds a save state, multi-byte values are stored little endian
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    /// Creates a writer holding the save state header
+    pub fn new() -> StateWriter {
+        let mut writer = StateWriter { data: Vec::new() };
+        writer.write_bytes(STATE_MAGIC);
+        writer.write_u16(STATE_VERSION);
+        writer
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    /// Returns the finished save state
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> StateWriter {
+        StateWriter::new()
+    }
+}
+
+/// Reads back a save state built by [`StateWriter`]
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    /// Checks the save state header and returns a reader positioned after it
+    ///
+    /// ### Parameters:
+    /// * `data` - The whole save state
+    ///
+    /// ### Returns:
+    /// * The reader, or `Err(StateError)` if the header is missing or from another
+    ///   version
+    pub fn new(data: &'a [u8]) -> Result<StateReader<'a>, StateError> {
+        let mut reader = StateReader { data };
+
+        match reader.read_bytes(STATE_MAGIC.len()) {
+            Ok(magic) if magic == STATE_MAGIC => {}
+            _ => return Err(StateError::NotAState),
+        }
+
+        match reader.read_u16()? {
+            STATE_VERSION => Ok(reader),
+            version => Err(StateError::UnsupportedVersion(version)),
+        }
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid("flag")),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.read_bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Returns the next `len` bytes of the save state
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    /// Returns the number of bytes not read yet
+    pub fn remaining(&self) -> usize {
+        self.data.len()
+    }
+}
@@ -1,50 +1,42 @@
+use crate::bus::Bus;
+
+/// First address of the hardware stack, which always lives in page $01
+pub const STACK_PAGE: u16 = 0x0100;
+
+/// Stack pointer of the 6502, the stack contents live on the bus at `$0100 + SP`
 pub struct Stack {
-    pub stack: [u8; 0x0100], // 256B
     pub sp: u8,
 }
 
 impl Stack {
+    /// Creates a stack pointer at the top of page $01
     pub fn new() -> Stack {
-        Stack {
-            stack: [0; 0x0100],
-            sp: 0xFF,
-        }
+        Stack { sp: 0xFF }
     }
 
-    pub fn push_byte(&mut self, data: u8) {
-        self.stack[self.sp as usize] = data;
-        self.sp -= 1;
+    pub fn push_byte<B: Bus>(&mut self, bus: &mut B, data: u8) {
+        bus.write_byte(STACK_PAGE | self.sp as u16, data);
+        self.sp = self.sp.wrapping_sub(1);
     }
 
-    pub fn pop_byte(&mut self) -> u8 {
-        self.sp += 1;
-
-        self.stack[self.sp as usize]
-    }
+    pub fn pop_byte<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        self.sp = self.sp.wrapping_add(1);
 
-    pub fn push_word(&mut self, data: u16) {
-        self.stack[self.sp as usize] = (data >> 8) as u8;
-        self.stack[(self.sp - 1) as usize] = data as u8;
-        self.sp -= 2;
-    }
-
-    pub fn pop_word(&mut self) -> u16 {
-        let word: u16;
-
-        word = ((self.stack[(self.sp + 1) as usize] as u16)) | ((self.stack[(self.sp + 2) as usize] as u16) << 8);
-
-        self.sp += 2;
-
-        word
+        bus.read_byte(STACK_PAGE | self.sp as u16)
     }
 
     pub fn get_sp(&self) -> u8 {
-        self.sp 
+        self.sp
     }
 
-    pub fn get_stack(&self) -> Vec<u8> {
-        let stack_vec = self.stack.to_vec();
+    /// Returns a copy of the whole stack page as currently seen on the bus
+    pub fn get_stack<B: Bus>(&self, bus: &B) -> Vec<u8> {
+        bus.peek_range(STACK_PAGE as usize, 0x0100)
+    }
+}
 
-        stack_vec
+impl Default for Stack {
+    fn default() -> Stack {
+        Stack::new()
     }
 }
@@ -1,3 +1,13 @@
+pub const MASK_NEGATIVE: u8 = 0b10000000;
+pub const MASK_OVERFLOW: u8 = 0b01000000;
+pub const MASK_UNUSED: u8 = 0b00100000;
+pub const MASK_BRK: u8 = 0b00010000;
+pub const MASK_DECIMAL: u8 = 0b00001000;
+pub const MASK_INTERRUPT: u8 = 0b00000100;
+pub const MASK_ZERO: u8 = 0b00000010;
+pub const MASK_CARRY: u8 = 0b00000001;
+
+/// The processor status register P, one field per flag
 pub struct StatusRegister {
     pub negative: bool,
     pub overflow: bool,
@@ -9,6 +19,7 @@ pub struct StatusRegister {
 }
 
 impl StatusRegister {
+    /// Creates a status register with every flag cleared
     pub fn new() -> StatusRegister {
         StatusRegister {
             negative: false,
@@ -34,14 +45,29 @@ impl StatusRegister {
         negative | overflow | brk | decimal | interrupt | zero | carry
     }
 
+    /// Returns the status byte as it is pushed onto the stack, bit 5 always reads as
+    /// set and the B bit tells a BRK/PHP push apart from a hardware interrupt
+    ///
+    /// ### Parameters:
+    /// * `brk` - Whether the B bit should be set
+    pub fn get_pushed_status_byte(&self, brk: bool) -> u8 {
+        let status = (self.get_status_byte() & !MASK_BRK) | MASK_UNUSED;
+
+        if brk {
+            status | MASK_BRK
+        } else {
+            status
+        }
+    }
+
     pub fn set_status_byte(&mut self, status_byte: u8) {
-        self.negative = (status_byte & 0b10000000) != 0;
-        self.overflow = (status_byte & 0b01000000) != 0;
-        self.brk = (status_byte & 0b00010000) != 0;
-        self.decimal = (status_byte & 0b00001000) != 0;
-        self.interrupt_disable = (status_byte & 0b00000100) != 0;
-        self.zero = (status_byte & 0b00000010) != 0;
-        self.carry = (status_byte & 0b00000001) != 0;
+        self.negative = (status_byte & MASK_NEGATIVE) != 0;
+        self.overflow = (status_byte & MASK_OVERFLOW) != 0;
+        self.brk = (status_byte & MASK_BRK) != 0;
+        self.decimal = (status_byte & MASK_DECIMAL) != 0;
+        self.interrupt_disable = (status_byte & MASK_INTERRUPT) != 0;
+        self.zero = (status_byte & MASK_ZERO) != 0;
+        self.carry = (status_byte & MASK_CARRY) != 0;
     }
 
     pub fn get_negative(&self) -> bool {
@@ -71,4 +97,10 @@ impl StatusRegister {
     pub fn get_carry(&self) -> bool {
         self.carry
     }
-}
\ No newline at end of file
+}
+
+impl Default for StatusRegister {
+    fn default() -> StatusRegister {
+        StatusRegister::new()
+    }
+}
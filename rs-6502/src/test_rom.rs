@@ -0,0 +1,148 @@
+use std::fmt;
+
+use crate::{
+    bus::Bus, cpu::Cpu, error::CpuError, loader::LoadError, memory::Memory, variant::CpuVariant,
+};
+
+/// Address `6502_functional_test.bin` starts executing from
+pub const FUNCTIONAL_TEST_START: u16 = 0x0400;
+/// Address of the success trap in the default build of the functional test
+pub const FUNCTIONAL_TEST_SUCCESS: u16 = 0x3469;
+/// Address the decimal test is assembled and started at
+pub const DECIMAL_TEST_START: u16 = 0x0200;
+/// Zero page byte the decimal test leaves at 0 when every result matched
+pub const DECIMAL_TEST_ERROR: u16 = 0x000B;
+
+/// Enough for the functional test, which needs about 100 million cycles
+const DEFAULT_MAX_CYCLES: u64 = 200_000_000;
+
+/// Describes how to load a self-checking test ROM and how it reports success
+///
+/// Test ROMs such as Klaus Dormann's end every test, passed or failed, by jumping or
+/// branching to itself. The runner stops on that self-loop, or on a JAM/STP, and
+/// judges the result from the trap address and an optional error byte.
+pub struct TestRom {
+    /// Address the image is copied to
+    pub load_addr: u16,
+    /// Address execution starts from, bypassing the reset vector
+    pub start_addr: u16,
+    /// Address of the trap reached when every test passed, if the ROM has one
+    pub success_trap: Option<u16>,
+    /// Address of a byte that must read 0 once the ROM trapped, if the ROM has one
+    pub error_flag: Option<u16>,
+    /// Cycles after which the ROM is considered stuck
+    pub max_cycles: u64,
+}
+
+/// How a test ROM run ended
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TestOutcome {
+    /// The ROM trapped where success is reported
+    Passed { trap: u16, cycles: u64 },
+    /// The ROM trapped anywhere else, the trap address identifies the failing test
+    Failed { trap: u16, cycles: u64 },
+    /// The cycle budget ran out before the ROM trapped
+    TimedOut { pc: u16, cycles: u64 },
+}
+
+impl TestRom {
+    /// Returns the setup of `6502_functional_test.bin` as shipped, a 64KB image
+    pub fn functional() -> TestRom {
+        TestRom {
+            load_addr: 0x0000,
+            start_addr: FUNCTIONAL_TEST_START,
+            success_trap: Some(FUNCTIONAL_TEST_SUCCESS),
+            error_flag: None,
+            max_cycles: DEFAULT_MAX_CYCLES,
+        }
+    }
+
+    /// Returns the setup of `6502_decimal_test.bin`, assembled at its default origin.
+    /// The ROM must end with a trap (`jmp *`) or a JAM rather than the 65C02 STP
+    /// opcode when run on an NMOS variant.
+    pub fn decimal() -> TestRom {
+        TestRom {
+            load_addr: DECIMAL_TEST_START,
+            start_addr: DECIMAL_TEST_START,
+            success_trap: None,
+            error_flag: Some(DECIMAL_TEST_ERROR),
+            max_cycles: DEFAULT_MAX_CYCLES,
+        }
+    }
+
+    /// Loads the image into 64KB of RAM and runs it until it traps
+    ///
+    /// ### Parameters:
+    /// * `image` - The ROM image
+    /// * `variant` - The CPU variant to run the ROM on
+    ///
+    /// ### Returns:
+    /// * How the run ended, or `Err(LoadError)` if the image runs past `$FFFF`
+    pub fn run(&self, image: &[u8], variant: CpuVariant) -> Result<TestOutcome, LoadError> {
+        let mut memory = Memory::new();
+        memory.load(self.load_addr, image)?;
+
+        let mut cpu = Cpu::with_variant(memory, variant);
+        cpu.pc = self.start_addr;
+
+        while cpu.cycles < self.max_cycles {
+            let trap = match cpu.execute() {
+                // A jump or taken branch onto itself leaves the PC where it was
+                Ok(step) if step.pc == cpu.pc => step.pc,
+                Ok(_) => continue,
+                Err(CpuError::Jammed { pc }) => pc,
+                Err(error) => unreachable!("Strict mode is off, got {}", error),
+            };
+
+            return Ok(self.judge(&cpu, trap));
+        }
+
+        Ok(TestOutcome::TimedOut {
+            pc: cpu.pc,
+            cycles: cpu.cycles,
+        })
+    }
+
+    fn judge<B: Bus>(&self, cpu: &Cpu<B>, trap: u16) -> TestOutcome {
+        let trap_ok = self.success_trap.map_or(true, |success| success == trap);
+        let flag_ok = self
+            .error_flag
+            .map_or(true, |addr| cpu.bus.peek_byte(addr) == 0);
+
+        if trap_ok && flag_ok {
+            TestOutcome::Passed {
+                trap,
+                cycles: cpu.cycles,
+            }
+        } else {
+            TestOutcome::Failed {
+                trap,
+                cycles: cpu.cycles,
+            }
+        }
+    }
+}
+
+impl fmt::Display for TestOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TestOutcome::Passed { trap, cycles } => {
+                write!(
+                    f,
+                    "Passed, trapped at 0x{:04X} after {} cycles",
+                    trap, cycles
+                )
+            }
+            TestOutcome::Failed { trap, cycles } => {
+                write!(
+                    f,
+                    "Failed, trapped at 0x{:04X} after {} cycles",
+                    trap, cycles
+                )
+            }
+            TestOutcome::TimedOut { pc, cycles } => {
+                write!(f, "Timed out at 0x{:04X} after {} cycles", pc, cycles)
+            }
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::BTreeSet;
 use std::io::{self, stdout, Stdout};
 
 use crossterm::{
@@ -10,15 +11,20 @@ use ratatui::{
     widgets::{block::*, *},
 };
 
-use crate::cpu::Cpu;
-use crate::cpu_state::CpuState;
-use crate::instruction::Instruction;
+use rs_6502::stack::STACK_PAGE;
+use rs_6502::{
+    Bus, Cpu, CpuError, CpuState, History, HistoryBus, Inspect, Instruction, Memory, RegionKind,
+};
 
 /// A type alias for the terminal type used in this application
 pub type Tui = Terminal<CrosstermBackend<Stdout>>;
 
-pub struct App {
-    cpu: Cpu,
+pub struct App<B: Inspect = Memory> {
+    cpu: Cpu<HistoryBus<B>>,
+    /// Instructions executed so far, undone by stepping back
+    history: History,
+    /// Addresses where running backwards stops
+    breakpoints: BTreeSet<u16>,
     exit: bool,
     previous_state: Option<CpuState>,
     current_state: Option<CpuState>,
@@ -28,16 +34,28 @@ pub struct App {
     stack: Option<Vec<u8>>,
     prev_instruction: Option<Instruction>,
     curr_instruction: Option<Instruction>,
+    /// Whether the memory table browses the banked physical memory
+    physical_view: bool,
+    irq_asserted: bool,
+    strict: bool,
+    error: Option<CpuError>,
+    /// Outcome of the last save state operation, shown until the next step
+    message: Option<String>,
+    /// Save states are written next to the ROM, as `<rom>.state<slot>`
+    state_path: String,
+    slot: u8,
 }
 
-impl App {
-    pub fn new(cpu: Cpu) -> App {
+impl<B: Inspect> App<B> {
+    pub fn new(cpu: Cpu<HistoryBus<B>>, state_path: String, breakpoints: &[u16]) -> App<B> {
         let curr = CpuState::new(&cpu);
-        let stack = cpu.stack.get_stack();
+        let stack = cpu.stack.get_stack(&cpu.bus);
         let curr_instruction = Instruction::new(&cpu);
 
         App {
             cpu,
+            history: History::default(),
+            breakpoints: breakpoints.iter().copied().collect(),
             exit: false,
             previous_state: None,
             current_state: Some(curr),
@@ -47,6 +65,13 @@ impl App {
             stack: Some(stack),
             prev_instruction: None,
             curr_instruction: Some(curr_instruction),
+            physical_view: false,
+            irq_asserted: false,
+            strict: false,
+            error: None,
+            message: None,
+            state_path,
+            slot: 1,
         }
     }
 
@@ -74,6 +99,17 @@ impl App {
         match key_event.code {
             KeyCode::Char('q') => self.exit(),
             KeyCode::Char(' ') => self.execute_instruction_and_update_state(),
+            KeyCode::Char('b') => self.step_back_and_update_state(),
+            KeyCode::Char('B') => self.run_back_and_update_state(),
+            KeyCode::Char('p') => self.toggle_breakpoint(),
+            KeyCode::Char('r') => self.reset_and_update_state(),
+            KeyCode::Char('n') => self.pulse_nmi(),
+            KeyCode::Char('i') => self.toggle_irq(),
+            KeyCode::Char('x') => self.toggle_strict(),
+            KeyCode::Char(digit @ '1'..='9') => self.slot = digit as u8 - b'0',
+            KeyCode::Char('v') => self.save_state(),
+            KeyCode::Char('l') => self.load_state(),
+            KeyCode::Char('m') => self.toggle_physical_view(),
             KeyCode::Down | KeyCode::Char('s') => self.scroll_down_memory(),
             KeyCode::Up | KeyCode::Char('w') => self.scroll_up_memory(),
             _ => {}
@@ -92,7 +128,7 @@ impl App {
             .split(frame.size());
 
         self.num_memory_lines = layout[1].height as u8 - 3;
-        self.memory = Some(self.cpu.memory.get_ram(self.memory_index, self.num_memory_lines as usize * 16));
+        self.memory = Some(self.memory_view());
 
         // Split the CPU and Memory layout
         let cpu_layouts = Layout::default()
@@ -127,8 +163,14 @@ impl App {
                     .bg(Color::Rgb(0, 0, 0)),
             );
 
+        let current_title = match (&self.error, &self.message) {
+            (Some(error), _) => format!(" Current State - {} ", error),
+            (None, Some(message)) => format!(" Current State - {} ", message),
+            (None, None) => String::from(" Current State "),
+        };
+
         let current_cpu_block = Block::default()
-            .title(Title::from(" Current State ".bold()).alignment(Alignment::Center))
+            .title(Title::from(current_title.bold()).alignment(Alignment::Center))
             .borders(Borders::ALL)
             .border_type(BorderType::Double)
             .style(
@@ -138,8 +180,27 @@ impl App {
             );
 
         // Create the blocks for the memory layout
+        let memory_end = (self.memory_index + self.num_memory_lines as usize * 16)
+            .min(self.memory_len())
+            .saturating_sub(1);
+        let memory_title = if self.physical_view {
+            format!(
+                "  Physical Memory 0x{:05X} - 0x{:05X}  ",
+                self.memory_index, memory_end
+            )
+        } else if self.cpu.bus.bank_windows().is_empty() {
+            format!("  RAM 0x{:04X} - 0x{:04X}  ", self.memory_index, memory_end)
+        } else {
+            format!(
+                "  RAM 0x{:04X} - 0x{:04X} | Banks {}  ",
+                self.memory_index,
+                memory_end,
+                self.banks_description()
+            )
+        };
+
         let ram_page_block: Block = Block::default()
-            .title(Title::from("  RAM Page 0x0000 - 0x00FF  ".bold()).alignment(Alignment::Center))
+            .title(Title::from(memory_title.bold()).alignment(Alignment::Center))
             .borders(Borders::ALL)
             .border_type(BorderType::Double)
             .style(
@@ -237,11 +298,19 @@ impl App {
             .block(curr_instruction_block);
 
         // Getting the paragraph for the interactive instructions
-        let instruction = Paragraph::new("<SPACE> - Execute Instruction | <q> - Quit | <↑/w> - Scroll RAM Up | <↓/s> - Scroll RAM Down")
+        let instruction = Paragraph::new(format!(
+            "<SPACE> - Execute Instruction | <b> - Step Back ({}) | <B> - Run Back to Breakpoint | <p> - Breakpoint at PC ({}) | <r> - Reset | <n> - NMI | <i> - IRQ {} | <x> - Strict {} | <1-9> - Slot ({}) | <v> - Save State | <l> - Load State | <m> - Physical Memory | <q> - Quit | <↑/w> - Scroll RAM Up | <↓/s> - Scroll RAM Down",
+            self.history.len(),
+            self.breakpoints.len(),
+            if self.irq_asserted { "(on)" } else { "(off)" },
+            if self.strict { "(on)" } else { "(off)" },
+            self.slot
+        ))
             .style(Style::default().fg(Color::Rgb(0, 255, 0)).bg(Color::Black))
             .alignment(Alignment::Center);
 
-        let title = Paragraph::new(" MOS 6502 Emulator ").alignment(Alignment::Center);
+        let title = Paragraph::new(format!(" {} Emulator ", self.cpu.variant()))
+            .alignment(Alignment::Center);
 
         let instructions_title = Paragraph::new(" Instructions ").alignment(Alignment::Center);
 
@@ -256,24 +325,37 @@ impl App {
                     .collect::<Vec<Constraint>>(),
             )
             .rows((0..self.num_memory_lines as usize).map(|i| {
-                if (16 * i as u32 + self.memory_index as u32) >= 0x10000 {
+                if 16 * i + self.memory_index >= self.memory_len() {
                     return Row::new(vec![Cell::from("")]);
                 }
 
-                let mut cells = vec![Cell::from(format!(
-                    "${:03X}_",
-                    (16 * i + self.memory_index) >> 4
-                ))];
+                // Physical addresses may go past 16 bits
+                let row_addr = (16 * i + self.memory_index) >> 4;
+                let mut cells = vec![Cell::from(if self.physical_view {
+                    format!("${:04X}_", row_addr)
+                } else {
+                    format!("${:03X}_", row_addr)
+                })];
                 for j in 0..16 {
                     let index = i * 16 + j;
-                    let value = format!("${:02X}", self.memory.as_ref().unwrap()[index]);
+                    let Some(byte) = self.memory.as_ref().unwrap().get(index) else {
+                        break;
+                    };
+                    let value = format!("${:02X}", byte);
 
-                    let cell = if index + self.memory_index == self.cpu.pc as usize {
+                    let addr = index + self.memory_index;
+
+                    let cell = if self.physical_view {
+                        Cell::from(value)
+                    } else if addr == self.cpu.pc as usize {
                         Cell::from(value).style(
                             Style::default()
                                 .bg(Color::Rgb(0, 255, 0))
                                 .fg(Color::Rgb(0, 0, 0)),
                         )
+                    } else if self.cpu.bus.region(addr as u16) == RegionKind::Rom {
+                        // ROM is told apart from the green RAM
+                        Cell::from(value).style(Style::default().fg(Color::Rgb(255, 176, 0)))
                     } else {
                         Cell::from(value)
                     };
@@ -295,7 +377,10 @@ impl App {
                     .collect::<Vec<Constraint>>(),
             )
             .rows((0..self.stack.as_ref().unwrap().len() / 16).map(|i| {
-                let mut cells = vec![Cell::from(format!("${:1X}_", (i * 16) >> 4))];
+                let mut cells = vec![Cell::from(format!(
+                    "${:03X}_",
+                    (STACK_PAGE as usize + i * 16) >> 4
+                ))];
                 for j in 0..16 {
                     let index = i * 16 + j;
                     let value = format!("${:02X}", self.stack.as_ref().unwrap()[index]);
@@ -310,7 +395,7 @@ impl App {
                         Cell::from(value)
                     };
 
-                    cells.push(Cell::from(cell));
+                    cells.push(cell);
                 }
 
                 Row::new(cells)
@@ -333,45 +418,218 @@ impl App {
         self.exit = true;
     }
 
+    /// Executes one instruction, an error is shown above the current state until the
+    /// next step so the machine can be inspected
     fn execute_instruction_and_update_state(&mut self) {
+        self.error = self.history.step(&mut self.cpu).err();
+        self.message = self.rom_writes_message();
+        self.update_state();
+    }
+
+    /// Describes the writes to ROM logged by the last step, if any
+    fn rom_writes_message(&mut self) -> Option<String> {
+        let writes = self.cpu.bus.take_rom_writes();
+        let last = writes.last()?;
+
+        Some(format!(
+            "Ignored {} write(s) to ROM, last 0x{:02X} at 0x{:04X}",
+            writes.len(),
+            last.data,
+            last.addr
+        ))
+    }
+
+    /// Undoes the last instruction executed
+    fn step_back_and_update_state(&mut self) {
+        self.message = if self.history.step_back(&mut self.cpu) {
+            None
+        } else {
+            Some(String::from("No history left"))
+        };
+        self.after_rewind();
+    }
+
+    /// Undoes instructions until the PC reaches a breakpoint
+    fn run_back_and_update_state(&mut self) {
+        let breakpoints = &self.breakpoints;
+        let undone = self
+            .history
+            .run_back(&mut self.cpu, |pc| breakpoints.contains(&pc));
+
+        self.message = Some(format!("Stepped back {} instructions", undone));
+        self.after_rewind();
+    }
+
+    fn after_rewind(&mut self) {
+        self.error = None;
+        self.irq_asserted = self.cpu.irq_line();
+        self.update_state();
+    }
+
+    /// Sets or clears a breakpoint on the instruction at the PC
+    fn toggle_breakpoint(&mut self) {
+        let pc = self.cpu.pc;
+
+        self.message = if self.breakpoints.remove(&pc) {
+            Some(format!("Breakpoint at 0x{:04X} cleared", pc))
+        } else {
+            self.breakpoints.insert(pc);
+            Some(format!("Breakpoint at 0x{:04X} set", pc))
+        };
+    }
+
+    fn reset_and_update_state(&mut self) {
+        self.history.clear();
+        self.cpu.reset();
+        self.error = None;
+        self.message = None;
+        self.update_state();
+    }
+
+    fn slot_path(&self) -> String {
+        format!("{}.state{}", self.state_path, self.slot)
+    }
+
+    /// Writes the whole machine to the selected slot
+    fn save_state(&mut self) {
+        self.message = Some(match self.cpu.save_state(self.slot_path()) {
+            Ok(()) => format!("Saved slot {}", self.slot),
+            Err(error) => error.to_string(),
+        });
+    }
+
+    /// Restores the machine from the selected slot, the previous state is kept for
+    /// comparison
+    fn load_state(&mut self) {
+        match self.cpu.load_state(self.slot_path()) {
+            Ok(()) => {
+                self.history.clear();
+                self.error = None;
+                self.irq_asserted = self.cpu.irq_line();
+                self.update_state();
+                self.message = Some(format!("Loaded slot {}", self.slot));
+            }
+            Err(error) => self.message = Some(error.to_string()),
+        }
+    }
+
+    /// Pulses the NMI line, the interrupt is serviced on the next step
+    fn pulse_nmi(&mut self) {
+        self.cpu.set_nmi_line(true);
+        self.cpu.set_nmi_line(false);
+    }
+
+    /// Toggles the level of the IRQ line, serviced on the next step if I is clear
+    fn toggle_irq(&mut self) {
+        self.irq_asserted = !self.irq_asserted;
+        self.cpu.set_irq_line(self.irq_asserted);
+    }
+
+    /// Toggles strict mode, where undocumented opcodes and invalid BCD operands stop
+    /// execution with an error
+    fn toggle_strict(&mut self) {
+        self.strict = !self.strict;
+        self.cpu.set_strict(self.strict);
+    }
+
+    fn update_state(&mut self) {
         self.previous_state = self.current_state.take();
         self.prev_instruction = self.curr_instruction.take();
 
-        let cycles_used = self.cpu.execute() as u32;
-        let mut new_state = CpuState::new(&self.cpu);
-        new_state.cycles = self.previous_state.as_ref().unwrap().cycles + cycles_used;
+        let new_state = CpuState::new(&self.cpu);
 
         let new_instruction = Instruction::new(&self.cpu);
 
         self.curr_instruction = Some(new_instruction);
         self.current_state = Some(new_state);
 
-        self.memory = Some(self.cpu.memory.get_ram(self.memory_index, self.num_memory_lines as usize * 16));
-        self.stack = Some(self.cpu.stack.get_stack());
+        self.memory = Some(self.memory_view());
+        self.stack = Some(self.cpu.stack.get_stack(&self.cpu.bus));
+    }
+
+    /// Returns the size of the memory browsed, the address space or the banked memory
+    fn memory_len(&self) -> usize {
+        if self.physical_view {
+            self.cpu.bus.physical_len()
+        } else {
+            0x10000
+        }
+    }
+
+    /// Returns the bytes shown by the memory table
+    fn memory_view(&self) -> Vec<u8> {
+        let size = self.num_memory_lines as usize * 16;
+
+        if self.physical_view {
+            let end = (self.memory_index + size).min(self.memory_len());
+            (self.memory_index..end)
+                .map(|addr| self.cpu.bus.peek_physical(addr))
+                .collect()
+        } else {
+            self.cpu.bus.peek_range(self.memory_index, size)
+        }
+    }
+
+    /// Switches the memory table between the address space seen by the CPU and the
+    /// banked physical memory
+    fn toggle_physical_view(&mut self) {
+        if self.cpu.bus.physical_len() == 0 {
+            self.message = Some(String::from("No banked memory"));
+            return;
+        }
+
+        self.physical_view = !self.physical_view;
+        self.memory_index = 0;
+        self.memory = Some(self.memory_view());
+    }
+
+    /// Describes the bank shown by every window, e.g. "8000-BFFF:#3 ROM"
+    fn banks_description(&self) -> String {
+        self.cpu
+            .bus
+            .bank_windows()
+            .iter()
+            .map(|window| {
+                let kind = match window.kind {
+                    RegionKind::Ram => "RAM",
+                    RegionKind::Rom => "ROM",
+                };
+                format!(
+                    "{:04X}-{:04X}:#{} {}",
+                    window.range.start(),
+                    window.range.end(),
+                    window.bank,
+                    kind
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(", ")
     }
 
     fn scroll_down_memory(&mut self) {
-        if self.memory_index + self.num_memory_lines as usize * 16 >= 0x10000 {
+        if self.memory_index + self.num_memory_lines as usize * 16 >= self.memory_len() {
             self.memory_index = 0;
         } else {
-            self.memory_index = self.memory_index + self.num_memory_lines as usize * 16
+            self.memory_index += self.num_memory_lines as usize * 16
         }
 
-        self.memory = Some(self.cpu.memory.get_ram(self.memory_index, self.num_memory_lines as usize * 16));
+        self.memory = Some(self.memory_view());
     }
 
     fn scroll_up_memory(&mut self) {
         if self.memory_index as i32 - self.num_memory_lines as i32 * 16 < 0 {
             if self.memory_index == 0 {
-                self.memory_index = 0x10000 - self.num_memory_lines as usize * 16;
+                self.memory_index = self
+                    .memory_len()
+                    .saturating_sub(self.num_memory_lines as usize * 16);
             } else {
                 self.memory_index = 0;
             }
         } else {
-            self.memory_index = self.memory_index - self.num_memory_lines as usize * 16;
+            self.memory_index -= self.num_memory_lines as usize * 16;
         }
 
-        self.memory = Some(self.cpu.memory.get_ram(self.memory_index, self.num_memory_lines as usize * 16));
+        self.memory = Some(self.memory_view());
     }
 }
 
@@ -0,0 +1,87 @@
+use std::{fmt, str::FromStr};
+
+/// Enumerates the members of the 6502 family the CPU can emulate
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CpuVariant {
+    /// Original MOS 6502, undocumented opcodes included
+    Nmos6502,
+    /// WDC W65C02S: the CMOS instruction set, the Rockwell bit instructions, WAI and STP
+    Wdc65C02,
+    /// Rockwell R65C02: the CMOS instruction set and the bit instructions, no WAI or STP
+    Rockwell65C02,
+    /// Ricoh 2A03 used in the NES: an NMOS 6502 whose decimal mode has been cut out
+    Ricoh2A03,
+}
+
+impl CpuVariant {
+    /// Every variant, in the order they are listed in the usage message
+    pub const ALL: [CpuVariant; 4] = [
+        CpuVariant::Nmos6502,
+        CpuVariant::Wdc65C02,
+        CpuVariant::Rockwell65C02,
+        CpuVariant::Ricoh2A03,
+    ];
+
+    /// Returns the short name used to select the variant on the command line
+    pub const fn name(self) -> &'static str {
+        match self {
+            CpuVariant::Nmos6502 => "nmos",
+            CpuVariant::Wdc65C02 => "65c02",
+            CpuVariant::Rockwell65C02 => "r65c02",
+            CpuVariant::Ricoh2A03 => "2a03",
+        }
+    }
+
+    /// Returns whether the variant uses the CMOS instruction set and bus behaviour
+    pub const fn is_cmos(self) -> bool {
+        matches!(self, CpuVariant::Wdc65C02 | CpuVariant::Rockwell65C02)
+    }
+
+    /// Returns whether the D flag changes how ADC and SBC work
+    pub const fn has_decimal_mode(self) -> bool {
+        !matches!(self, CpuVariant::Ricoh2A03)
+    }
+
+    /// Returns whether `JMP ($xxFF)` reads the pointer's high byte from $xx00. The
+    /// 65C02 fixed it, at the cost of one more cycle.
+    pub const fn has_indirect_jump_bug(self) -> bool {
+        !self.is_cmos()
+    }
+
+    /// Returns whether BBR, BBS, RMB and SMB are available
+    pub const fn has_bit_instructions(self) -> bool {
+        self.is_cmos()
+    }
+
+    /// Returns whether WAI and STP are available
+    pub const fn has_wait_and_stop(self) -> bool {
+        matches!(self, CpuVariant::Wdc65C02)
+    }
+}
+
+impl fmt::Display for CpuVariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CpuVariant::Nmos6502 => "MOS 6502",
+            CpuVariant::Wdc65C02 => "WDC 65C02",
+            CpuVariant::Rockwell65C02 => "Rockwell R65C02",
+            CpuVariant::Ricoh2A03 => "Ricoh 2A03",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for CpuVariant {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<CpuVariant, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "nmos" | "6502" => Ok(CpuVariant::Nmos6502),
+            "65c02" | "wdc65c02" => Ok(CpuVariant::Wdc65C02),
+            "r65c02" | "rockwell" => Ok(CpuVariant::Rockwell65C02),
+            "2a03" | "nes" => Ok(CpuVariant::Ricoh2A03),
+            _ => Err(format!("Unknown CPU variant: {}", name)),
+        }
+    }
+}
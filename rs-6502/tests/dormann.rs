@@ -0,0 +1,209 @@
+//! Runs Klaus Dormann's functional and decimal test ROMs
+//!
+//! The ROMs are not shipped with the repository. Place `6502_functional_test.bin` and
+//! `6502_decimal_test.bin` in `tests/roms`, or point `RS6502_TEST_ROMS` at the folder
+//! holding them, then run `cargo test --test dormann -- --ignored`. A missing ROM
+//! fails the test. The runner itself is checked against a tiny ROM built by `fixture`.
+
+use std::{env, fs, path::PathBuf};
+
+use rs_6502::test_rom::{TestOutcome, TestRom};
+use rs_6502::{CpuVariant, LoadError};
+
+/// Where the fixture traps when every check held
+const FIXTURE_PASS: u16 = 0x0240;
+/// Where the fixture traps when a check went wrong
+const FIXTURE_FAIL: u16 = 0x0250;
+
+fn load_rom(name: &str) -> Vec<u8> {
+    let dir = env::var_os("RS6502_TEST_ROMS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/roms"));
+    let path = dir.join(name);
+
+    fs::read(&path).unwrap_or_else(|error| panic!("Could not read {}: {}", path.display(), error))
+}
+
+fn run(rom: TestRom, name: &str, variant: CpuVariant) {
+    let image = load_rom(name);
+    let outcome = rom.run(&image, variant).unwrap();
+    println!("{} on {}: {}", name, variant, outcome);
+
+    assert!(
+        matches!(outcome, TestOutcome::Passed { .. }),
+        "{} on {}: {}",
+        name,
+        variant,
+        outcome
+    );
+}
+
+#[test]
+#[ignore = "needs the Dormann test ROMs"]
+fn functional_test() {
+    run(
+        TestRom::functional(),
+        "6502_functional_test.bin",
+        CpuVariant::Nmos6502,
+    );
+}
+
+#[test]
+#[ignore = "needs the Dormann test ROMs"]
+fn decimal_test() {
+    run(
+        TestRom::decimal(),
+        "6502_decimal_test.bin",
+        CpuVariant::Nmos6502,
+    );
+}
+
+/// Builds a tiny self-checking ROM in the style of the Dormann tests, loaded at $0200.
+/// Each check traps at `FIXTURE_FAIL` when it goes wrong, the end of the ROM traps at
+/// `FIXTURE_PASS` after leaving `errors` in the error byte at $0B.
+///
+/// ### Parameters:
+/// * `broken` - Makes the loop check go wrong
+/// * `errors` - The byte left at $0B
+fn fixture(broken: bool, errors: u8) -> Vec<u8> {
+    let inx_or_nop = if broken { 0xE8 } else { 0xEA };
+
+    #[rustfmt::skip]
+    let checks = [
+        0xA9, 0x00,         // $0200  lda #0
+        0x85, 0x0B,         // $0202  sta $0B
+        0x18,               // $0204  clc
+        0xA9, 0x19,         // $0205  lda #$19
+        0x69, 0x23,         // $0207  adc #$23
+        0xC9, 0x3C,         // $0209  cmp #$3C
+        0xD0, 0x43,         // $020B  bne fail
+        0xA2, 0x03,         // $020D  ldx #3
+        0xCA,               // $020F  dex
+        0xD0, 0xFD,         // $0210  bne $020F
+        inx_or_nop,         // $0212  inx or nop
+        0xE0, 0x00,         // $0213  cpx #0
+        0xD0, 0x39,         // $0215  bne fail
+        0xA9, errors,       // $0217  lda #errors
+        0x85, 0x0B,         // $0219  sta $0B
+        0x4C, 0x40, 0x02,   // $021B  jmp pass
+    ];
+
+    let mut image = vec![0; 0x53];
+    image[..checks.len()].copy_from_slice(&checks);
+    // pass: jmp pass
+    image[0x40..0x43].copy_from_slice(&[0x4C, 0x40, 0x02]);
+    // fail: jmp fail
+    image[0x50..0x53].copy_from_slice(&[0x4C, 0x50, 0x02]);
+    image
+}
+
+fn fixture_rom(success_trap: Option<u16>, error_flag: Option<u16>) -> TestRom {
+    TestRom {
+        load_addr: 0x0200,
+        start_addr: 0x0200,
+        success_trap,
+        error_flag,
+        max_cycles: 1_000,
+    }
+}
+
+#[test]
+fn fixture_passes_at_the_success_trap() {
+    let outcome = fixture_rom(Some(FIXTURE_PASS), None)
+        .run(&fixture(false, 0), CpuVariant::Nmos6502)
+        .unwrap();
+
+    assert!(
+        matches!(
+            outcome,
+            TestOutcome::Passed {
+                trap: FIXTURE_PASS,
+                ..
+            }
+        ),
+        "{}",
+        outcome
+    );
+}
+
+#[test]
+fn fixture_failure_reports_the_trap() {
+    let outcome = fixture_rom(Some(FIXTURE_PASS), None)
+        .run(&fixture(true, 0), CpuVariant::Nmos6502)
+        .unwrap();
+
+    assert!(
+        matches!(
+            outcome,
+            TestOutcome::Failed {
+                trap: FIXTURE_FAIL,
+                ..
+            }
+        ),
+        "{}",
+        outcome
+    );
+}
+
+#[test]
+fn fixture_error_flag() {
+    let rom = fixture_rom(None, Some(0x000B));
+
+    let outcome = rom.run(&fixture(false, 0), CpuVariant::Nmos6502).unwrap();
+    assert!(
+        matches!(
+            outcome,
+            TestOutcome::Passed {
+                trap: FIXTURE_PASS,
+                ..
+            }
+        ),
+        "{}",
+        outcome
+    );
+
+    // Trapping where success is reported is not enough with errors left in the flag
+    let outcome = rom.run(&fixture(false, 1), CpuVariant::Nmos6502).unwrap();
+    assert!(
+        matches!(
+            outcome,
+            TestOutcome::Failed {
+                trap: FIXTURE_PASS,
+                ..
+            }
+        ),
+        "{}",
+        outcome
+    );
+}
+
+#[test]
+fn fixture_times_out() {
+    let rom = TestRom {
+        max_cycles: 10,
+        ..fixture_rom(Some(FIXTURE_PASS), None)
+    };
+    let outcome = rom.run(&fixture(false, 0), CpuVariant::Nmos6502).unwrap();
+
+    assert!(
+        matches!(outcome, TestOutcome::TimedOut { cycles, .. } if cycles >= 10),
+        "{}",
+        outcome
+    );
+}
+
+#[test]
+fn image_past_the_address_space_is_rejected() {
+    let rom = TestRom {
+        load_addr: 0xFFF0,
+        ..fixture_rom(Some(FIXTURE_PASS), None)
+    };
+
+    assert!(matches!(
+        rom.run(&fixture(false, 0), CpuVariant::Nmos6502),
+        Err(LoadError::TooLarge {
+            addr: 0xFFF0,
+            len: 0x53
+        })
+    ));
+}
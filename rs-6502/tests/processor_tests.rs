@@ -0,0 +1,329 @@
+//! Checks every opcode against the ProcessorTests single-step JSON files
+//!
+//! The files are not shipped with the repository. Place the `6502`, `wdc65c02`,
+//! `rockwell65c02` and `nes6502` folders of the 65x02 test set in
+//! `tests/ProcessorTests`, or point `RS6502_PROCESSOR_TESTS` at the folder holding
+//! them. Each folder holds `v1/00.json` to `v1/ff.json`, one array of test cases per
+//! opcode. These tests are ignored by default, run them with
+//! `cargo test --test processor_tests -- --ignored`. A missing file fails the test.
+//!
+//! The harness itself is checked against `tests/fixtures/processor_tests_*.json`, a
+//! few hand-written cases per variant in the same format.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use rs_6502::{opcode_info, Bus, Cpu, CpuVariant};
+use serde_json::Value;
+
+/// Most mismatches printed per opcode, the rest are only counted
+const MAX_REPORTED: usize = 3;
+
+/// One bus access as listed in the test files
+#[derive(PartialEq)]
+struct BusCycle {
+    addr: u16,
+    data: u8,
+    write: bool,
+}
+
+/// 64KB of RAM recording every access the CPU makes
+struct TracingMemory {
+    ram: Vec<u8>,
+    cycles: Vec<BusCycle>,
+}
+
+impl Bus for TracingMemory {
+    fn read_byte(&mut self, addr: u16) -> u8 {
+        let data = self.ram[addr as usize];
+        self.cycles.push(BusCycle {
+            addr,
+            data,
+            write: false,
+        });
+        data
+    }
+
+    fn write_byte(&mut self, addr: u16, data: u8) {
+        self.ram[addr as usize] = data;
+        self.cycles.push(BusCycle {
+            addr,
+            data,
+            write: true,
+        });
+    }
+
+    fn peek_byte(&self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+}
+
+fn tests_dir() -> PathBuf {
+    env::var_os("RS6502_PROCESSOR_TESTS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/ProcessorTests"))
+}
+
+fn read_cases(path: &Path) -> Value {
+    let text = fs::read_to_string(path)
+        .unwrap_or_else(|error| panic!("Could not read {}: {}", path.display(), error));
+    serde_json::from_str(&text).expect("Invalid test file")
+}
+
+fn field(state: &Value, name: &str) -> u64 {
+    state[name]
+        .as_u64()
+        .unwrap_or_else(|| panic!("Missing field {}", name))
+}
+
+fn ram_entries(state: &Value) -> impl Iterator<Item = (u16, u8)> + '_ {
+    state["ram"]
+        .as_array()
+        .expect("Missing RAM")
+        .iter()
+        .map(|entry| {
+            (
+                entry[0].as_u64().unwrap() as u16,
+                entry[1].as_u64().unwrap() as u8,
+            )
+        })
+}
+
+/// Runs one test case and returns what differs from the expected final state
+fn run_case(case: &Value, variant: CpuVariant) -> Vec<String> {
+    let initial = &case["initial"];
+    let expected = &case["final"];
+
+    let mut memory = TracingMemory {
+        ram: vec![0; 0x10000],
+        cycles: Vec::new(),
+    };
+    for (addr, data) in ram_entries(initial) {
+        memory.ram[addr as usize] = data;
+    }
+
+    let mut cpu = Cpu::with_variant(memory, variant);
+    cpu.pc = field(initial, "pc") as u16;
+    cpu.stack.sp = field(initial, "s") as u8;
+    cpu.a = field(initial, "a") as u8;
+    cpu.x = field(initial, "x") as u8;
+    cpu.y = field(initial, "y") as u8;
+    cpu.sr.set_status_byte(field(initial, "p") as u8);
+
+    // Halting opcodes report an error but still run their bus cycles
+    let _ = cpu.execute();
+
+    let mut diffs = Vec::new();
+    let registers = [
+        ("pc", cpu.pc as u64),
+        ("s", cpu.stack.sp as u64),
+        ("a", cpu.a as u64),
+        ("x", cpu.x as u64),
+        ("y", cpu.y as u64),
+        // B and bit 5 do not exist in the register, they only show up when P is pushed
+        ("p", (cpu.sr.get_status_byte() | 0x30) as u64),
+    ];
+    for (name, value) in registers {
+        let want = if name == "p" {
+            field(expected, name) | 0x30
+        } else {
+            field(expected, name)
+        };
+
+        if value != want {
+            diffs.push(format!(
+                "{}: got 0x{:X}, expected 0x{:X}",
+                name, value, want
+            ));
+        }
+    }
+
+    for (addr, want) in ram_entries(expected) {
+        let got = cpu.bus.ram[addr as usize];
+        if got != want {
+            diffs.push(format!(
+                "RAM 0x{:04X}: got 0x{:02X}, expected 0x{:02X}",
+                addr, got, want
+            ));
+        }
+    }
+
+    let want_cycles: Vec<BusCycle> = case["cycles"]
+        .as_array()
+        .expect("Missing cycles")
+        .iter()
+        .map(|cycle| BusCycle {
+            addr: cycle[0].as_u64().unwrap() as u16,
+            data: cycle[1].as_u64().unwrap() as u8,
+            write: cycle[2].as_str() == Some("write"),
+        })
+        .collect();
+    let got_cycles = &cpu.bus.cycles;
+
+    if got_cycles.len() != want_cycles.len() {
+        diffs.push(format!(
+            "cycles: got {}, expected {}",
+            got_cycles.len(),
+            want_cycles.len()
+        ));
+    } else if let Some(index) = (0..got_cycles.len()).find(|&i| got_cycles[i] != want_cycles[i]) {
+        let describe = |cycle: &BusCycle| {
+            let kind = if cycle.write { "write" } else { "read" };
+            format!("{} 0x{:02X} @ 0x{:04X}", kind, cycle.data, cycle.addr)
+        };
+        diffs.push(format!(
+            "bus cycle {}: got {}, expected {}",
+            index + 1,
+            describe(&got_cycles[index]),
+            describe(&want_cycles[index])
+        ));
+    }
+
+    diffs
+}
+
+/// Runs every opcode file of a variant, returns the number of failing test cases
+fn run_variant(folder: &str, variant: CpuVariant) -> usize {
+    let dir = tests_dir().join(folder).join("v1");
+
+    let mut failures = 0;
+    for opcode in 0..=255u8 {
+        let info = opcode_info(opcode, variant);
+
+        // JAM keeps the bus busy forever, the files only list an arbitrary slice of it
+        if info.mnemonic == "JAM" {
+            continue;
+        }
+
+        let cases = read_cases(&dir.join(format!("{:02x}.json", opcode)));
+
+        let mut failed = 0;
+        for case in cases.as_array().expect("Test file is not an array") {
+            let diffs = run_case(case, variant);
+            if diffs.is_empty() {
+                continue;
+            }
+
+            if failed < MAX_REPORTED {
+                println!(
+                    "{} 0x{:02X} {} [{}]: {}",
+                    variant,
+                    opcode,
+                    info.mnemonic,
+                    case["name"].as_str().unwrap_or("?"),
+                    diffs.join("; ")
+                );
+            }
+            failed += 1;
+        }
+
+        if failed > 0 {
+            println!(
+                "{} 0x{:02X} {}: {} failing cases",
+                variant, opcode, info.mnemonic, failed
+            );
+        }
+        failures += failed;
+    }
+
+    failures
+}
+
+#[test]
+#[ignore = "needs the ProcessorTests files"]
+fn nmos_6502() {
+    assert_eq!(run_variant("6502", CpuVariant::Nmos6502), 0);
+}
+
+#[test]
+#[ignore = "needs the ProcessorTests files"]
+fn wdc_65c02() {
+    assert_eq!(run_variant("wdc65c02", CpuVariant::Wdc65C02), 0);
+}
+
+#[test]
+#[ignore = "needs the ProcessorTests files"]
+fn rockwell_65c02() {
+    assert_eq!(run_variant("rockwell65c02", CpuVariant::Rockwell65C02), 0);
+}
+
+#[test]
+#[ignore = "needs the ProcessorTests files"]
+fn ricoh_2a03() {
+    assert_eq!(run_variant("nes6502", CpuVariant::Ricoh2A03), 0);
+}
+
+/// Checked-in cases, `tests/fixtures/processor_tests_<name>.json`, and the variant
+/// running them
+const FIXTURES: [(&str, CpuVariant); 4] = [
+    ("6502", CpuVariant::Nmos6502),
+    ("65c02", CpuVariant::Wdc65C02),
+    ("r65c02", CpuVariant::Rockwell65C02),
+    ("2a03", CpuVariant::Ricoh2A03),
+];
+
+fn fixture_cases(name: &str) -> Vec<Value> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join(format!("tests/fixtures/processor_tests_{}.json", name));
+    match read_cases(&path) {
+        Value::Array(cases) => cases,
+        _ => panic!("Test file is not an array"),
+    }
+}
+
+fn fixture_case(name: &str) -> Value {
+    fixture_cases("6502")
+        .into_iter()
+        .find(|case| case["name"] == name)
+        .unwrap_or_else(|| panic!("No case named {}", name))
+}
+
+#[test]
+fn fixture() {
+    for (name, variant) in FIXTURES {
+        for case in fixture_cases(name) {
+            assert_eq!(
+                run_case(&case, variant),
+                Vec::<String>::new(),
+                "{} {}",
+                variant,
+                case["name"]
+            );
+        }
+    }
+}
+
+#[test]
+fn fixture_mismatches_are_reported() {
+    // STA $10 with A = 0x42, expecting 0x43 everywhere instead
+    let mut case = fixture_case("85 10");
+    case["final"]["a"] = Value::from(0x43);
+    case["final"]["ram"][2][1] = Value::from(0x43);
+    case["cycles"][2][1] = Value::from(0x43);
+
+    assert_eq!(
+        run_case(&case, CpuVariant::Nmos6502),
+        vec![
+            "a: got 0x42, expected 0x43",
+            "RAM 0x0010: got 0x42, expected 0x43",
+            "bus cycle 3: got write 0x42 @ 0x0010, expected write 0x43 @ 0x0010",
+        ]
+    );
+
+    // A read where a write is expected
+    let mut case = fixture_case("85 10");
+    case["cycles"][2][2] = Value::from("read");
+    assert_eq!(
+        run_case(&case, CpuVariant::Nmos6502),
+        vec!["bus cycle 3: got write 0x42 @ 0x0010, expected read 0x42 @ 0x0010"]
+    );
+
+    let mut case = fixture_case("85 10");
+    case["cycles"].as_array_mut().unwrap().pop();
+    assert_eq!(
+        run_case(&case, CpuVariant::Nmos6502),
+        vec!["cycles: got 3, expected 2"]
+    );
+}